@@ -0,0 +1,123 @@
+use shell::Shell;
+use readline::context::EditingMode;
+use readline::keymap::{self, Binding, Keymap, Keymaps};
+use token::CommandData;
+
+use std::io::{self, Write};
+use std::path::Path;
+
+/// `bind [-m keymap] [-lpPsS] [-f file] [-q name] [-r keyseq] [keyseq:function ...]`
+pub fn run(shell: &mut Shell, cmd: CommandData) -> Result<(), String> {
+    let mut out = cmd.out.unwrap();
+    let keymaps = shell.keymaps.clone();
+    let mut keymaps = keymaps.borrow_mut();
+
+    let mode = if shell.options.vi { EditingMode::Vi } else { EditingMode::Emacs };
+    let mut name = keymap::keymap_name(mode).to_string();
+    let mut args = cmd.options.iter();
+    while let Some(arg) = args.next() {
+        let res = match arg.as_str() {
+            "-m" | "-f" | "-q" | "-r" => {
+                let value = match args.next() {
+                    Some(value) => value,
+                    None => return Err(format!("bind: {}: option requires an argument", arg)),
+                };
+                match arg.as_str() {
+                    "-m" => {
+                        if keymaps.get(value).is_none() {
+                            return Err(format!("bind: `{}': invalid keymap name", value));
+                        }
+                        name = value.clone();
+                    }
+                    "-f" => {
+                        let errors = keymaps.read_file(Path::new(value), &mut name);
+                        if !errors.is_empty() {
+                            return Err(format!("bind: {}", errors.join("\nmican: bind: ")));
+                        }
+                    }
+                    "-q" => {
+                        let keys = keymap(&keymaps, &name).keys_for(value);
+                        if Keymaps::function_names().iter().all(|f| f != value) {
+                            return Err(format!("bind: `{}': unknown function name", value));
+                        }
+                        if keys.is_empty() {
+                            return Err(format!("bind: {} is not bound to any keys.", value));
+                        }
+                        writeln!(out, "{} can be invoked via {}.", value, quote_all(&keys))
+                            .map_err(|e| e.to_string())?;
+                    }
+                    _ => {
+                        let seq = keymap::parse_keyseq(value.trim_matches('"'))
+                            .map_err(|e| format!("bind: {}", e))?;
+                        keymaps.get_mut(&name).unwrap().unbind(&seq);
+                    }
+                }
+                Ok(())
+            }
+            "-l" => Keymaps::function_names()
+                .iter()
+                .try_for_each(|f| writeln!(out, "{}", f)),
+            "-p" | "-P" => print_functions(&mut out, keymap(&keymaps, &name), arg == "-p"),
+            "-s" | "-S" => print_macros(&mut out, keymap(&keymaps, &name), arg == "-s"),
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                return Err(format!("bind: {}: invalid option", arg));
+            }
+            line => {
+                keymaps.parse_line(line, &mut name).map_err(|e| format!("bind: {}", e))?;
+                Ok(())
+            }
+        };
+        res.map_err(|e| e.to_string())?;
+    }
+
+    if let Some(mode) = keymaps.editing_mode.take() {
+        shell.options.vi = mode == EditingMode::Vi;
+    }
+    Ok(())
+}
+
+fn keymap<'a>(keymaps: &'a Keymaps, name: &str) -> &'a Keymap {
+    keymaps.get(name).unwrap()
+}
+
+fn quote_all(keys: &[Vec<u8>]) -> String {
+    keys.iter()
+        .map(|k| format!("\"{}\"", keymap::format_keyseq(k)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// `-p` prints the function bindings so that they can be read back, `-P`
+/// describes them.
+fn print_functions(out: &mut dyn Write, keymap: &Keymap, reusable: bool) -> io::Result<()> {
+    for name in Keymaps::function_names() {
+        let keys = keymap.keys_for(name);
+        match (reusable, keys.is_empty()) {
+            (true, true) => writeln!(out, "# {} (not bound)", name)?,
+            (true, false) => {
+                for k in keys {
+                    writeln!(out, "\"{}\": {}", keymap::format_keyseq(&k), name)?;
+                }
+            }
+            (false, true) => writeln!(out, "{} is not bound to any keys", name)?,
+            (false, false) => writeln!(out, "{} can be found on {}.", name, quote_all(&keys))?,
+        }
+    }
+    Ok(())
+}
+
+/// `-s` prints the macros so that they can be read back, `-S` describes
+/// them.
+fn print_macros(out: &mut dyn Write, keymap: &Keymap, reusable: bool) -> io::Result<()> {
+    for (keys, binding) in keymap.bindings() {
+        if let Binding::Macro(ref text) = *binding {
+            let keys = keymap::format_keyseq(&keys);
+            if reusable {
+                writeln!(out, "\"{}\": {}", keys, binding)?;
+            } else {
+                writeln!(out, "{} outputs {}", keys, keymap::format_keyseq(text))?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,21 +1,18 @@
+use shell::Shell;
 use token::CommandData;
 
 use std::env;
+use std::path::PathBuf;
 
-// TODO
-
-#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
-pub fn run(cmd: CommandData) -> Result<(), String> {
-    if cmd.options.is_empty() {
-        #[allow(deprecated)]
-        env::set_current_dir(&env::home_dir().unwrap()).unwrap();
-        return Ok(());
-    }
-
-    let mut current_path_buf = env::current_dir().unwrap();
-    current_path_buf.push(&cmd.options[0]);
-    if env::set_current_dir(current_path_buf.as_path()).is_err() {
-        return Err(format!("{} not found", cmd.options[0]));
+/// `cd [dir]`, to `$HOME` when `dir` is left out. It changes the shell's
+/// own directory, so it runs in the shell.
+pub fn run(_shell: &mut Shell, cmd: CommandData) -> Result<(), String> {
+    let dir = match cmd.options.first() {
+        Some(dir) => PathBuf::from(dir),
+        None => match env::var_os("HOME") {
+            Some(home) => PathBuf::from(home),
+            None => return Err("cd: HOME not set".into()),
+        },
     };
-    Ok(())
+    env::set_current_dir(&dir).map_err(|_| format!("{} not found", dir.display()))
 }
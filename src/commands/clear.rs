@@ -16,6 +16,7 @@ fn test_bye_run() {
     let cmd = CommandData {
         program: "clear".to_string(),
         options: vec![],
+        redirects: vec![],
         out: Some(Output::from(File::create("/dev/null").unwrap())),
         input: None,
     };
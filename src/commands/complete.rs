@@ -0,0 +1,139 @@
+use shell::Shell;
+use readline::completer::{CompletionContext, Spec};
+use token::CommandData;
+
+use std::io::Write;
+
+/// `complete [-fdcv] [-W wordlist] [-F function] [-pr] [name ...]`
+///
+/// mican has no shell functions, so the `-F` function is a command line. It
+/// runs in the shell with `COMP_WORDS`, `COMP_CWORD`, `COMP_LINE` and
+/// `COMP_POINT` set, and leaves the completions in `COMPREPLY`, separated by
+/// whitespace. Neither `set -e` nor the ERR and DEBUG traps apply to it.
+pub fn run(shell: &mut Shell, cmd: CommandData) -> Result<(), String> {
+    let mut out = cmd.out.unwrap();
+    let specs = shell.completions.clone();
+    let mut specs = specs.borrow_mut();
+
+    let mut spec = Spec::default();
+    let mut print = false;
+    let mut remove = false;
+    let mut names = Vec::new();
+    let mut args = cmd.options.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') || arg.len() == 1 || !names.is_empty() {
+            names.push(arg.as_str());
+            continue;
+        }
+        for flag in arg[1..].chars() {
+            match flag {
+                'W' | 'F' => {
+                    let value = match args.next() {
+                        Some(value) => value.clone(),
+                        None => return Err(format!("complete: -{}: option requires an argument", flag)),
+                    };
+                    if flag == 'W' {
+                        spec.words = Some(value);
+                    } else {
+                        spec.function = Some(value);
+                    }
+                }
+                'f' => spec.files = true,
+                'd' => spec.directories = true,
+                'c' => spec.commands = true,
+                'v' => spec.variables = true,
+                'p' => print = true,
+                'r' => remove = true,
+                _ => return Err(format!("complete: -{}: invalid option", flag)),
+            }
+        }
+    }
+
+    if remove {
+        if names.is_empty() {
+            specs.clear();
+        }
+        for name in names {
+            if specs.remove(name).is_none() {
+                return Err(format!("complete: {}: no completion specification", name));
+            }
+        }
+        return Ok(());
+    }
+
+    if print || spec == Spec::default() {
+        if names.is_empty() {
+            for (name, spec) in specs.iter() {
+                writeln!(out, "complete {} {}", spec, name).map_err(|e| e.to_string())?;
+            }
+        }
+        for name in names {
+            match specs.get(name) {
+                Some(spec) => writeln!(out, "complete {} {}", spec, name).map_err(|e| e.to_string())?,
+                None => return Err(format!("complete: {}: no completion specification", name)),
+            }
+        }
+        return Ok(());
+    }
+
+    if names.is_empty() {
+        return Err("complete: a command name is needed".into());
+    }
+    for name in names {
+        specs.set(name, spec.clone());
+    }
+    Ok(())
+}
+
+/// Runs the command of a `-F` spec for the word `context` describes and
+/// returns the words it put in `COMPREPLY`.
+pub fn call_function(shell: &mut Shell, function: &str, context: &CompletionContext) -> Vec<String> {
+    shell.set_var("COMP_WORDS", &context.words.join(" "));
+    shell.set_var("COMP_CWORD", &context.index.to_string());
+    shell.set_var("COMP_LINE", &context.line);
+    shell.set_var("COMP_POINT", &context.cursor.to_string());
+    shell.set_var("COMPREPLY", "");
+
+    shell.run_internal(function);
+    let reply = shell
+        .var("COMPREPLY")
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(|s| s.to_string())
+        .collect();
+    for name in &["COMP_WORDS", "COMP_CWORD", "COMP_LINE", "COMP_POINT", "COMPREPLY"] {
+        shell.unset_var(name);
+    }
+    reply
+}
+
+#[test]
+fn test_complete() {
+    let mut shell = Shell::new();
+    assert_eq!(shell.run("complete -f -W 'start stop' svc ctl"), Ok(0));
+    let spec = Spec {
+        words: Some("start stop".into()),
+        files: true,
+        ..Spec::default()
+    };
+    assert_eq!(shell.completions.borrow().get("svc"), Some(&spec));
+    assert_eq!(shell.completions.borrow().get("ctl"), Some(&spec));
+    assert_eq!(shell.run("complete -r svc"), Ok(0));
+    assert_eq!(shell.completions.borrow().get("svc"), None);
+    assert_eq!(shell.run("complete -r svc"), Ok(1));
+    assert_eq!(shell.run("complete -W"), Ok(1));
+
+    let chars: Vec<char> = "svc st".chars().collect();
+    let context = CompletionContext::new(&chars, chars.len());
+    let reply = call_function(&mut shell, "COMPREPLY=\"$COMP_CWORD $COMP_WORDS\"", &context);
+    assert_eq!(reply, vec!["1", "svc", "st"]);
+    assert_eq!(shell.var("COMP_WORDS"), None);
+    assert_eq!(shell.var("COMPREPLY"), None);
+
+    // A failing function neither exits nor sets off the ERR trap.
+    assert_eq!(shell.run("set -e; trap 'ERR_RAN=yes' ERR"), Ok(0));
+    let reply = call_function(&mut shell, "COMPREPLY=stop; false", &context);
+    assert_eq!(reply, vec!["stop"]);
+    assert_eq!(shell.var("ERR_RAN"), None);
+    assert_eq!(shell.last_status, 0);
+}
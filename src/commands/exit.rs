@@ -0,0 +1,11 @@
+use shell::Shell;
+use token::CommandData;
+
+/// `exit [n]`, with `$?` when `n` is left out.
+pub fn run(shell: &mut Shell, cmd: CommandData) -> Result<(), String> {
+    let status = match cmd.options.first() {
+        Some(n) => n.parse::<i32>().map_err(|_| format!("exit: {}: numeric argument required", n))?,
+        None => shell.last_status,
+    };
+    shell.exit(status)
+}
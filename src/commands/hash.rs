@@ -0,0 +1,77 @@
+use shell::Shell;
+use token::CommandData;
+
+use std::io::Write;
+
+/// `hash [-rt] [name ...]`
+///
+/// Without names, lists the commands looked up so far and how many times
+/// each was run. A name is looked up in `$PATH` and remembered, or with
+/// `-t` printed with where it is. `-r` forgets everything.
+pub fn run(shell: &mut Shell, cmd: CommandData) -> Result<(), String> {
+    let mut out = cmd.out.unwrap();
+    let table = shell.command_table.clone();
+    let mut table = table.borrow_mut();
+
+    let mut reset = false;
+    let mut print = false;
+    let mut names = Vec::new();
+    for arg in &cmd.options {
+        if !arg.starts_with('-') || arg.len() == 1 || !names.is_empty() {
+            names.push(arg.as_str());
+            continue;
+        }
+        for flag in arg[1..].chars() {
+            match flag {
+                'r' => reset = true,
+                't' => print = true,
+                _ => return Err(format!("hash: -{}: invalid option", flag)),
+            }
+        }
+    }
+
+    if reset {
+        table.reset();
+    }
+    if names.is_empty() {
+        if print {
+            return Err("hash: -t: option requires an argument".into());
+        }
+        if reset {
+            return Ok(());
+        }
+        let remembered = table.remembered();
+        if remembered.is_empty() {
+            return writeln!(out, "hash: hash table empty").map_err(|e| e.to_string());
+        }
+        writeln!(out, "hits\tcommand").map_err(|e| e.to_string())?;
+        for (_, hits, path) in remembered {
+            writeln!(out, "{:4}\t{}", hits, path.display()).map_err(|e| e.to_string())?;
+        }
+        return Ok(());
+    }
+
+    for name in names {
+        let path = match table.lookup(name) {
+            Some(path) => path,
+            None => return Err(format!("hash: {}: not found", name)),
+        };
+        if print {
+            writeln!(out, "{}", path.display()).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_hash() {
+    let mut shell = Shell::new();
+    assert_eq!(shell.run("hash sh"), Ok(0));
+    assert!(shell.command_table.borrow_mut().remembered().iter().any(|(name, hits, _)| {
+        name == "sh" && *hits == 0
+    }));
+    assert_eq!(shell.run("hash mican-no-such-command"), Ok(1));
+    assert_eq!(shell.run("hash -r"), Ok(0));
+    assert!(shell.command_table.borrow_mut().remembered().is_empty());
+    assert_eq!(shell.run("hash -x"), Ok(1));
+}
@@ -1,8 +1,50 @@
+pub mod bind;
 pub mod bye;
 pub mod cd;
 pub mod clear;
+pub mod complete;
+pub mod exit;
+pub mod hash;
 pub mod ls;
 pub mod other;
 pub mod pwd;
+pub mod set;
+pub mod shopt;
 pub mod tanakh;
+pub mod trap;
 pub mod syar;
+
+use shell::Shell;
+use token::CommandData;
+
+/// How a builtin runs. An empty error fails it without a message.
+#[derive(Clone, Copy)]
+pub enum Builtin {
+    /// In the shell process, because it changes the shell's own state.
+    Shell(fn(&mut Shell, CommandData) -> Result<(), String>),
+    /// In a child, like commands from `$PATH`.
+    Forked(fn(CommandData) -> Result<(), String>),
+}
+
+/// The commands mican runs itself rather than from `$PATH`, by name.
+pub const BUILTINS: &[(&str, Builtin)] = &[
+    ("bind", Builtin::Shell(bind::run)),
+    ("bye", Builtin::Shell(bye::run)),
+    ("cd", Builtin::Shell(cd::run)),
+    ("clear", Builtin::Forked(clear::run)),
+    ("complete", Builtin::Shell(complete::run)),
+    ("exit", Builtin::Shell(exit::run)),
+    ("hash", Builtin::Shell(hash::run)),
+    ("ls", Builtin::Forked(ls::run)),
+    ("pwd", Builtin::Forked(pwd::run)),
+    ("set", Builtin::Shell(set::run)),
+    ("shopt", Builtin::Shell(shopt::run)),
+    ("syar", Builtin::Forked(syar::run)),
+    ("tanakh", Builtin::Forked(tanakh::run)),
+    ("trap", Builtin::Shell(trap::run)),
+];
+
+/// The builtin called `name`, if there is one.
+pub fn builtin(name: &str) -> Option<Builtin> {
+    BUILTINS.iter().find(|&&(n, _)| n == name).map(|&(_, builtin)| builtin)
+}
@@ -1,9 +1,21 @@
 use token::{CommandData, Input, Output};
 
-use std::process::{Command, Stdio};
+use std::io;
+use std::path::Path;
+use std::os::unix::process::CommandExt;
+use std::process::{exit, Command, Stdio};
 
+/// Replaces the forked child with the program, so its exit status is ours.
 pub fn run(cmd: CommandData) -> Result<(), String> {
-    let mut output = match Command::new(&cmd.program)
+    let program = cmd.program.clone();
+    exec(cmd, Path::new(&program))
+}
+
+/// Like `run`, with the program at `path`, where the command hash table has
+/// it.
+pub fn exec(cmd: CommandData, path: &Path) -> Result<(), String> {
+    let err = Command::new(path)
+        .arg0(&cmd.program)
         .args(&cmd.options)
         .stdin(match cmd.input.unwrap() {
             Input::Stdin(_) => Stdio::inherit(),
@@ -13,13 +25,17 @@ pub fn run(cmd: CommandData) -> Result<(), String> {
             Output::Stdout(_) => Stdio::inherit(),
             Output::File(output) => output.into(),
         })
-        .spawn() {
-        Ok(p) => p,
-        Err(e) => return Err(format!("{}", e)),
-    };
+        .exec();
 
-    match output.wait() {
-        Ok(_) => Ok(()),
-        Err(e) => Err(format!("{}", e)),
+    match err.kind() {
+        io::ErrorKind::NotFound => {
+            eprintln!("mican: {}: command not found", cmd.program);
+            exit(127)
+        }
+        io::ErrorKind::PermissionDenied => {
+            eprintln!("mican: {}: permission denied", cmd.program);
+            exit(126)
+        }
+        _ => Err(format!("{}: {}", cmd.program, err)),
     }
 }
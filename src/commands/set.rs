@@ -0,0 +1,74 @@
+use shell::{quote, Shell};
+use options::Options;
+use token::CommandData;
+
+use std::io::Write;
+
+/// `set [-+eufvxC] [-+o name] [--] [arg ...]`
+pub fn run(shell: &mut Shell, cmd: CommandData) -> Result<(), String> {
+    let mut out = cmd.out.unwrap();
+
+    if cmd.options.is_empty() {
+        for (name, value) in shell.vars() {
+            writeln!(out, "{}={}", name, quote(&value)).map_err(|e| e.to_string())?;
+        }
+        return Ok(());
+    }
+
+    let mut args = cmd.options.into_iter().peekable();
+    let mut positional = None;
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            positional = Some(args.by_ref().collect());
+            break;
+        }
+
+        let on = arg.starts_with('-');
+        if !on && !arg.starts_with('+') || arg.len() == 1 {
+            positional = Some(Some(arg).into_iter().chain(args.by_ref()).collect());
+            break;
+        }
+
+        for flag in arg[1..].chars() {
+            if flag == 'o' {
+                match args.peek() {
+                    Some(name) if !name.starts_with('-') && !name.starts_with('+') => (),
+                    _ => {
+                        print_options(shell, &mut out, on).map_err(|e| e.to_string())?;
+                        continue;
+                    }
+                }
+                let name = args.next().unwrap();
+                if !shell.options.set(&name, on) {
+                    return Err(format!("set: {}: invalid option name", name));
+                }
+                continue;
+            }
+
+            match Options::name_of_flag(flag) {
+                Some(name) => {
+                    shell.options.set(name, on);
+                }
+                None => return Err(format!("set: {}{}: invalid option", &arg[..1], flag)),
+            }
+        }
+    }
+
+    if let Some(positional) = positional {
+        shell.positional = positional;
+    }
+    Ok(())
+}
+
+/// `set -o` lists every option, `set +o` prints the commands to restore them.
+fn print_options(shell: &Shell, out: &mut dyn Write, on: bool) -> ::std::io::Result<()> {
+    for name in Options::names() {
+        let value = shell.options.get(name).unwrap();
+        if on {
+            writeln!(out, "{:<15} {}", name, if value { "on" } else { "off" })?;
+        } else {
+            writeln!(out, "set {}o {}", if value { '-' } else { '+' }, name)?;
+        }
+    }
+    Ok(())
+}
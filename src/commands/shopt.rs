@@ -0,0 +1,62 @@
+use shell::Shell;
+use options::Shopts;
+use token::CommandData;
+
+use std::io::Write;
+
+/// `shopt [-s|-u|-p|-q] [name ...]`
+pub fn run(shell: &mut Shell, cmd: CommandData) -> Result<(), String> {
+    let mut out = cmd.out.unwrap();
+
+    let mut args = cmd.options.as_slice();
+    let mode = match args.first().map(|s| s.as_str()) {
+        Some("-s") | Some("-u") | Some("-p") | Some("-q") => {
+            args = &args[1..];
+            cmd.options[0].clone()
+        }
+        _ => String::new(),
+    };
+
+    let names: Vec<&str> = if args.is_empty() {
+        Shopts::names()
+    } else {
+        args.iter().map(|s| s.as_str()).collect()
+    };
+
+    for name in names {
+        let value = match shell.shopts.get(name) {
+            Some(value) => value,
+            None => return Err(format!("shopt: {}: invalid shell option name", name)),
+        };
+
+        let res = match mode.as_str() {
+            "-s" | "-u" if !args.is_empty() => {
+                shell.shopts.set(name, mode == "-s");
+                Ok(())
+            }
+            // Without names, -s and -u list the options that are on or off.
+            "-s" | "-u" if value == (mode == "-s") => writeln!(out, "{:<15} {}", name, on_off(value)),
+            "-s" | "-u" => Ok(()),
+            "-p" => writeln!(out, "shopt {} {}", if value { "-s" } else { "-u" }, name),
+            "-q" if value => Ok(()),
+            // Only the status says.
+            "-q" => return Err(String::new()),
+            _ => writeln!(out, "{:<15} {}", name, on_off(value)),
+        };
+        res.map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value { "on" } else { "off" }
+}
+
+#[test]
+fn test_shopt() {
+    let mut shell = Shell::new();
+    assert_eq!(shell.run("shopt -q nullglob"), Ok(1));
+    assert_eq!(shell.run("shopt -s nullglob"), Ok(0));
+    assert_eq!(shell.run("shopt -q nullglob"), Ok(0));
+    assert_eq!(shell.run("shopt -q nosuchoption"), Ok(1));
+}
@@ -5,7 +5,7 @@ use std::{thread, time};
 
 
 // TODO
-#[cfg_attr(feature = "cargo-clippy", allow(non_ascii_literal))]
+#[allow(clippy::non_ascii_literal)]
 pub fn run(cmd: CommandData) -> Result<(), String> {
     let mut out = cmd.out.unwrap();
     let tanakhs = vec![
@@ -0,0 +1,96 @@
+use shell::{quote, Shell};
+use token::CommandData;
+use trap::Condition;
+
+use std::io::Write;
+
+use nix::sys::signal::Signal;
+
+/// `trap [-lp] [[command] condition ...]`
+///
+/// `command` is run when one of the conditions occurs: a signal name or
+/// number, `EXIT`, `ERR` or `DEBUG`. An empty command ignores the signal
+/// and `-` puts back the default action.
+pub fn run(shell: &mut Shell, cmd: CommandData) -> Result<(), String> {
+    let mut out = cmd.out.unwrap();
+    // What follows `--` isn't an option, as in what `trap -p` prints.
+    let (args, options) = match cmd.options.split_first() {
+        Some((first, rest)) if first == "--" => (rest, false),
+        _ => (&cmd.options[..], true),
+    };
+
+    match args.first().map(|s| s.as_str()) {
+        None | Some("-p") if options || args.is_empty() => {
+            let names: Vec<Condition> = args[1.min(args.len())..]
+                .iter()
+                .map(|name| parse_condition(name))
+                .collect::<Result<_, _>>()?;
+            for (cond, action) in shell.traps.iter() {
+                if names.is_empty() || names.contains(cond) {
+                    writeln!(out, "trap -- {} {}", quote(action), cond.name()).map_err(|e| e.to_string())?;
+                }
+            }
+            return Ok(());
+        }
+        Some("-l") if options => {
+            for sig in Signal::iterator() {
+                writeln!(out, "{:2}) {:?}", sig as i32, sig).map_err(|e| e.to_string())?;
+            }
+            return Ok(());
+        }
+        _ => (),
+    }
+
+    // A lone condition resets it, like `trap - condition`.
+    let (action, names) = if args.len() == 1 {
+        (None, args)
+    } else if args[0] == "-" {
+        (None, &args[1..])
+    } else {
+        (Some(args[0].as_str()), &args[1..])
+    };
+
+    for name in names {
+        let cond = parse_condition(name)?;
+        if let Condition::Signal(sig) = cond {
+            if sig == Signal::SIGKILL || sig == Signal::SIGSTOP {
+                return Err(format!("trap: {}: cannot be trapped", name));
+            }
+        }
+
+        match action {
+            Some(action) => shell.traps.set(cond, action),
+            None => {
+                shell.traps.remove(cond);
+                if let Condition::Signal(sig) = cond {
+                    shell.reset_signal(sig)?;
+                }
+            }
+        }
+    }
+    shell.install_signals()
+}
+
+fn parse_condition(name: &str) -> Result<Condition, String> {
+    Condition::from_name(name).ok_or_else(|| format!("trap: {}: invalid signal specification", name))
+}
+
+#[test]
+fn test_trap() {
+    use std::fs;
+    use testing::TempDir;
+
+    let dir = TempDir::new("trap");
+    let file = dir.join("traps");
+    let mut shell = Shell::new();
+    assert_eq!(shell.run("trap 'echo \"it'\\''s\"' ERR; trap '' DEBUG"), Ok(0));
+    let traps: Vec<(Condition, String)> = shell.traps.iter().map(|(c, a)| (*c, a.clone())).collect();
+    assert_eq!(shell.run(&format!("trap -p > {}", file.display())), Ok(0));
+
+    // What `trap -p` prints sets the same traps again.
+    shell.traps = ::trap::Traps::new();
+    assert_eq!(shell.run(&fs::read_to_string(&file).unwrap()), Ok(0));
+    assert_eq!(shell.traps.iter().map(|(c, a)| (*c, a.clone())).collect::<Vec<_>>(), traps);
+    assert_eq!(shell.run("trap -- - ERR"), Ok(0));
+    assert_eq!(shell.traps.get(Condition::Err), None);
+}
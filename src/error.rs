@@ -0,0 +1,125 @@
+//! Errors reported while parsing a command line.
+
+use std::error::Error;
+use std::fmt;
+
+use token::TokenKind;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// Byte range of the offending token in the parsed input.
+    pub span: (usize, usize),
+    pub expected: Vec<TokenKind>,
+    pub found: TokenKind,
+    pub hint: Option<String>,
+}
+
+impl ParseError {
+    pub fn new(span: (usize, usize), expected: Vec<TokenKind>, found: TokenKind) -> Self {
+        Self {
+            span,
+            expected,
+            found,
+            hint: None,
+        }
+    }
+
+    pub fn with_hint(mut self, hint: &str) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    /// Shifts the span by `n` bytes, for an error found in a line of a larger source.
+    pub fn offset(mut self, n: usize) -> Self {
+        self.span = (self.span.0 + n, self.span.1 + n);
+        self
+    }
+
+    /// Returns the 1-based line and column (in characters) where the error starts.
+    pub fn line_col(&self, source: &str) -> (usize, usize) {
+        let start = self.span.0;
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line = source[..line_start].matches('\n').count() + 1;
+        let col = source[line_start..start].chars().count() + 1;
+        (line, col)
+    }
+
+    /// Renders the error as a code frame with a caret under the offending column.
+    ///
+    /// ```text
+    /// mican: parse error: expected a command, found end of line
+    ///  --> build.mican:3:5
+    ///   |
+    /// 3 | ls |
+    ///   |     ^ expected a command
+    ///   = hint: add a command after `|` or remove it
+    /// ```
+    ///
+    /// `path` is only given when the source came from a script.
+    pub fn render(&self, source: &str, path: Option<&str>) -> String {
+        let (start, end) = self.span;
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..].find('\n').map_or(source.len(), |i| start + i);
+        let (line_n, col) = self.line_col(source);
+
+        // Keep tabs so the caret lines up with the echoed line.
+        let padding: String = source[line_start..start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = source[start..end.min(line_end).max(start)].chars().count().max(1);
+        let gutter = " ".repeat(line_n.to_string().len());
+
+        let mut frame = format!("mican: parse error: {}\n", self);
+        if let Some(path) = path {
+            frame.push_str(&format!("{}--> {}:{}:{}\n", gutter, path, line_n, col));
+        }
+        frame.push_str(&format!("{} |\n", gutter));
+        frame.push_str(&format!("{} | {}\n", line_n, &source[line_start..line_end]));
+        frame.push_str(&format!(
+            "{} | {}{} expected {}\n",
+            gutter,
+            padding,
+            "^".repeat(width),
+            self.expected_str()
+        ));
+        if let Some(ref hint) = self.hint {
+            frame.push_str(&format!("{} = hint: {}\n", gutter, hint));
+        }
+        frame
+    }
+
+    fn expected_str(&self) -> String {
+        self.expected
+            .iter()
+            .map(|t| t.to_string())
+            .collect::<Vec<_>>()
+            .join(" or ")
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "expected {}, found {}", self.expected_str(), self.found)
+    }
+}
+
+impl Error for ParseError {}
+
+#[test]
+fn test_render() {
+    let source = "pwd\nls |\n";
+    let err = ParseError::new((8, 8), vec![TokenKind::Command], TokenKind::EndOfLine)
+        .with_hint("add a command after `|` or remove it");
+
+    assert_eq!(err.line_col(source), (2, 5));
+    assert_eq!(
+        err.render(source, Some("build.mican")),
+        "mican: parse error: expected a command, found end of line\n \
+         --> build.mican:2:5\n  \
+         |\n\
+         2 | ls |\n  \
+         |     ^ expected a command\n  \
+         = hint: add a command after `|` or remove it\n"
+    );
+}
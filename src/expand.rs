@@ -0,0 +1,449 @@
+//! Word expansion: tilde, parameters, field splitting, pathname expansion
+//! and quote removal, in that order.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use shell::Shell;
+
+const GLOB_CHARS: &[char] = &['*', '?', '['];
+
+/// A field being built. `pattern` mirrors `text` with quoted glob
+/// characters escaped, so pathname expansion only sees unquoted ones.
+#[derive(Default)]
+struct Field {
+    text: String,
+    pattern: String,
+    has_glob: bool,
+    /// Set once anything, even `""`, contributed to the field.
+    present: bool,
+    /// Set by `"$@"` without parameters, which expands to no field at all.
+    empty_at: bool,
+}
+
+impl Field {
+    fn push_quoted(&mut self, c: char) {
+        if GLOB_CHARS.contains(&c) || c == '\\' {
+            self.pattern.push('\\');
+        }
+        self.pattern.push(c);
+        self.text.push(c);
+        self.present = true;
+    }
+
+    fn push_unquoted(&mut self, c: char) {
+        if GLOB_CHARS.contains(&c) {
+            self.has_glob = true;
+        }
+        self.pattern.push(c);
+        self.text.push(c);
+        self.present = true;
+    }
+}
+
+struct Expander<'a> {
+    shell: &'a Shell,
+    chars: Vec<char>,
+    pos: usize,
+    split: bool,
+    fields: Vec<Field>,
+    field: Field,
+}
+
+/// Expands a word into zero or more fields, like a command argument.
+pub fn expand(shell: &Shell, word: &str) -> Result<Vec<String>, String> {
+    let fields = Expander::new(shell, word, true).run()?;
+
+    let mut res = Vec::new();
+    for field in fields {
+        if field.has_glob && !shell.options.noglob {
+            let matches = glob(&field.pattern, shell.shopts.dotglob);
+            if !matches.is_empty() {
+                res.extend(matches);
+                continue;
+            } else if shell.shopts.nullglob {
+                continue;
+            }
+        }
+        res.push(field.text);
+    }
+    Ok(res)
+}
+
+/// Expands a word into a single string, without field splitting or
+/// pathname expansion, like the value of an assignment.
+pub fn expand_str(shell: &Shell, word: &str) -> Result<String, String> {
+    let fields = Expander::new(shell, word, false).run()?;
+    Ok(
+        fields
+            .into_iter()
+            .map(|f| f.text)
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+impl<'a> Expander<'a> {
+    fn new(shell: &'a Shell, word: &str, split: bool) -> Self {
+        Self {
+            shell,
+            chars: word.chars().collect(),
+            pos: 0,
+            split,
+            fields: Vec::new(),
+            field: Field::default(),
+        }
+    }
+
+    fn run(mut self) -> Result<Vec<Field>, String> {
+        self.expand_tilde();
+
+        let mut in_double = false;
+        while let Some(c) = self.next() {
+            match c {
+                '\'' if !in_double => {
+                    self.field.present = true;
+                    while let Some(c) = self.next() {
+                        if c == '\'' {
+                            break;
+                        }
+                        self.field.push_quoted(c);
+                    }
+                }
+                '"' => {
+                    self.field.present = true;
+                    in_double = !in_double;
+                }
+                '\\' => {
+                    match self.next() {
+                        Some(c) if !in_double || "$`\"\\\n".contains(c) => self.field.push_quoted(c),
+                        Some(c) => {
+                            self.field.push_quoted('\\');
+                            self.field.push_quoted(c);
+                        }
+                        None => self.field.push_quoted('\\'),
+                    }
+                }
+                '$' => self.expand_parameter(in_double)?,
+                c if in_double => self.field.push_quoted(c),
+                c => self.field.push_unquoted(c),
+            }
+        }
+
+        self.end_field();
+        Ok(self.fields)
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.get(self.pos).cloned();
+        self.pos += 1;
+        c
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn end_field(&mut self) {
+        let field = ::std::mem::take(&mut self.field);
+        if field.present && !(field.empty_at && field.text.is_empty()) {
+            self.fields.push(field);
+        }
+    }
+
+    /// `~` and `~/...` become `$HOME`.
+    fn expand_tilde(&mut self) {
+        if self.peek() != Some('~') {
+            return;
+        }
+        match self.chars.get(1) {
+            None | Some(&'/') => (),
+            Some(_) => return,
+        }
+        if let Ok(home) = env::var("HOME") {
+            self.pos += 1;
+            for c in home.chars() {
+                self.field.push_quoted(c);
+            }
+        }
+    }
+
+    fn expand_parameter(&mut self, in_double: bool) -> Result<(), String> {
+        let name = match self.peek() {
+            Some('{') => {
+                let close = match self.chars[self.pos..].iter().position(|&c| c == '}') {
+                    Some(i) => self.pos + i,
+                    None => return Err("${: bad substitution".into()),
+                };
+                let name: String = self.chars[self.pos + 1..close].iter().collect();
+                self.pos = close + 1;
+                if !is_name(&name) && !is_special(&name) {
+                    return Err(format!("${{{}}}: bad substitution", name));
+                }
+                name
+            }
+            Some(c) if c.is_ascii_digit() || "?#$@*-!".contains(c) => {
+                self.pos += 1;
+                c.to_string()
+            }
+            Some(c) if c == '_' || c.is_ascii_alphabetic() => {
+                let len = self.chars[self.pos..]
+                    .iter()
+                    .take_while(|&&c| c == '_' || c.is_ascii_alphanumeric())
+                    .count();
+                let name: String = self.chars[self.pos..self.pos + len].iter().collect();
+                self.pos += len;
+                name
+            }
+            _ => {
+                if in_double {
+                    self.field.push_quoted('$');
+                } else {
+                    self.field.push_unquoted('$');
+                }
+                return Ok(());
+            }
+        };
+
+        if name == "@" || name == "*" {
+            let params = self.shell.positional.clone();
+            if in_double && name == "@" {
+                // "$@" keeps every parameter as its own field.
+                self.field.empty_at = params.is_empty();
+                for (i, p) in params.iter().enumerate() {
+                    if i > 0 {
+                        self.end_field();
+                    }
+                    for c in p.chars() {
+                        self.field.push_quoted(c);
+                    }
+                }
+            } else {
+                self.push_value(&params.join(" "), in_double);
+            }
+            return Ok(());
+        }
+
+        match self.shell.var(&name) {
+            Some(value) => self.push_value(&value, in_double),
+            None if self.shell.options.nounset && name != "!" => {
+                return Err(format!("{}: unbound variable", name))
+            }
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn push_value(&mut self, value: &str, in_double: bool) {
+        if in_double || !self.split {
+            for c in value.chars() {
+                self.field.push_quoted(c);
+            }
+            return;
+        }
+
+        for c in value.chars() {
+            if c.is_whitespace() {
+                self.end_field();
+            } else {
+                self.field.push_unquoted(c);
+            }
+        }
+    }
+}
+
+pub fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c == '_' || c.is_ascii_alphabetic() => (),
+        _ => return false,
+    }
+    chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+fn is_special(s: &str) -> bool {
+    s.chars().all(|c| c.is_ascii_digit()) && !s.is_empty() ||
+        s.len() == 1 && "?#$@*-!".contains(s)
+}
+
+/// Expands a pathname pattern into the sorted list of matching paths.
+pub fn glob(pattern: &str, dotglob: bool) -> Vec<String> {
+    let mut paths = if pattern.starts_with('/') {
+        vec!["/".to_string()]
+    } else {
+        vec![String::new()]
+    };
+
+    let components: Vec<&str> = pattern.split('/').filter(|c| !c.is_empty()).collect();
+    for (i, component) in components.iter().enumerate() {
+        let last = i == components.len() - 1;
+        let mut next = Vec::new();
+        for base in &paths {
+            if !component.contains(GLOB_CHARS) {
+                let path = format!("{}{}", base, unescape(component));
+                if Path::new(&path).exists() {
+                    next.push(if last { path } else { path + "/" });
+                }
+                continue;
+            }
+
+            let dir = if base.is_empty() { "." } else { base.as_str() };
+            let entries = match fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            let pat: Vec<char> = component.chars().collect();
+            let mut names: Vec<String> = entries
+                .flatten()
+                .filter_map(|e| e.file_name().into_string().ok())
+                .filter(|name| {
+                    (!name.starts_with('.') || dotglob || component.starts_with('.')) &&
+                        fnmatch(&pat, &name.chars().collect::<Vec<_>>())
+                })
+                .collect();
+            names.sort();
+            for name in names {
+                let path = format!("{}{}", base, name);
+                if last {
+                    next.push(path);
+                } else if Path::new(&path).is_dir() {
+                    next.push(path + "/");
+                }
+            }
+        }
+        paths = next;
+    }
+
+    if pattern.ends_with('/') {
+        paths
+    } else {
+        paths.into_iter().filter(|p| p != "/" && !p.is_empty()).collect()
+    }
+}
+
+fn unescape(s: &str) -> String {
+    let mut res = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(c) = chars.next() {
+                res.push(c);
+            }
+        } else {
+            res.push(c);
+        }
+    }
+    res
+}
+
+/// Matches `name` against a shell pattern with `*`, `?`, `[...]` and `\` escapes.
+pub fn fnmatch(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some(&'*') => (0..name.len() + 1).any(|i| fnmatch(&pattern[1..], &name[i..])),
+        Some(&'?') => !name.is_empty() && fnmatch(&pattern[1..], &name[1..]),
+        Some(&'[') => {
+            if name.is_empty() {
+                return false;
+            }
+            match match_bracket(&pattern[1..], name[0]) {
+                Some((matched, len)) => matched && fnmatch(&pattern[1 + len..], &name[1..]),
+                // An unclosed `[` is an ordinary character.
+                None => name[0] == '[' && fnmatch(&pattern[1..], &name[1..]),
+            }
+        }
+        Some(&'\\') if pattern.len() > 1 => {
+            !name.is_empty() && name[0] == pattern[1] && fnmatch(&pattern[2..], &name[1..])
+        }
+        Some(&c) => !name.is_empty() && name[0] == c && fnmatch(&pattern[1..], &name[1..]),
+    }
+}
+
+/// Returns whether `c` is in the bracket expression and how many pattern
+/// characters it took, including the closing `]`.
+fn match_bracket(pattern: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 0;
+    let negate = match pattern.first() {
+        Some(&'!') | Some(&'^') => {
+            i += 1;
+            true
+        }
+        _ => false,
+    };
+
+    let mut matched = false;
+    let mut first = true;
+    while i < pattern.len() {
+        let lo = pattern[i];
+        if lo == ']' && !first {
+            return Some((matched != negate, i + 1));
+        }
+        first = false;
+        if i + 2 < pattern.len() && pattern[i + 1] == '-' && pattern[i + 2] != ']' {
+            if lo <= c && c <= pattern[i + 2] {
+                matched = true;
+            }
+            i += 3;
+        } else {
+            if lo == c {
+                matched = true;
+            }
+            i += 1;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn matches(pattern: &str, name: &str) -> bool {
+        fnmatch(
+            &pattern.chars().collect::<Vec<_>>(),
+            &name.chars().collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn test_fnmatch() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(!matches("*.rs", "main.rc"));
+        assert!(matches("m?can", "mican"));
+        assert!(matches("[a-c]at", "bat"));
+        assert!(!matches("[!a-c]at", "bat"));
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "a"));
+        assert!(matches("[", "["));
+    }
+
+    #[test]
+    fn test_expand() {
+        let mut shell = Shell::new();
+        shell.set_var("NAME", "mi can");
+        shell.positional = vec!["a b".into(), "c".into()];
+
+        assert_eq!(expand(&shell, "$NAME").unwrap(), vec!["mi", "can"]);
+        assert_eq!(expand(&shell, "\"$NAME\"").unwrap(), vec!["mi can"]);
+        assert_eq!(expand(&shell, "'$NAME'").unwrap(), vec!["$NAME"]);
+        assert_eq!(expand(&shell, "x${NAME}y").unwrap(), vec!["xmi", "cany"]);
+        assert_eq!(expand(&shell, "\"$@\"").unwrap(), vec!["a b", "c"]);
+        assert_eq!(expand(&shell, "$#").unwrap(), vec!["2"]);
+        assert_eq!(expand(&shell, "\"\"").unwrap(), vec![""]);
+        shell.positional.clear();
+        assert!(expand(&shell, "\"$@\"").unwrap().is_empty());
+        assert!(expand(&shell, "$UNSET_IN_TEST").unwrap().is_empty());
+        assert_eq!(expand(&shell, "'*'").unwrap(), vec!["*"]);
+
+        shell.options.nounset = true;
+        assert!(expand(&shell, "$UNSET_IN_TEST").is_err());
+
+        shell.options.noglob = true;
+        assert_eq!(expand(&shell, "src/*.rs").unwrap(), vec!["src/*.rs"]);
+        shell.options.noglob = false;
+        assert!(expand(&shell, "src/*.rs").unwrap().contains(
+            &"src/main.rs".to_string(),
+        ));
+    }
+}
@@ -0,0 +1,201 @@
+//! The command hash table: where each command in `$PATH` is, so that
+//! neither running nor completing a command searches the directories again.
+//! A directory is read again only when `$PATH` or its modification time
+//! changes, and nothing is read before a command is first looked up.
+
+use std::collections::btree_map::{BTreeMap, Keys};
+use std::env;
+use std::fs;
+use std::mem;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[derive(Debug, Default)]
+pub struct CommandTable {
+    /// The `$PATH` the directories are from, `None` before the first
+    /// lookup.
+    path: Option<String>,
+    dirs: Vec<Dir>,
+    /// Each command and which of `dirs` it is run from, the first that has
+    /// it.
+    commands: BTreeMap<String, usize>,
+    /// The commands looked up by name and how many times they were run, as
+    /// `hash` lists them.
+    hits: BTreeMap<String, usize>,
+}
+
+/// A directory in `$PATH` and the executables in it.
+#[derive(Debug)]
+struct Dir {
+    path: PathBuf,
+    /// When it was last changed, as of reading it, `None` if it wasn't
+    /// there. `None` before it is first read.
+    modified: Option<Option<SystemTime>>,
+    names: Vec<String>,
+}
+
+impl Dir {
+    fn new(path: &str) -> Self {
+        Self {
+            // An empty entry is the current directory.
+            path: PathBuf::from(if path.is_empty() { "." } else { path }),
+            modified: None,
+            names: Vec::new(),
+        }
+    }
+
+    /// Reads the directory again if it changed. Returns whether it did.
+    fn refresh(&mut self) -> bool {
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        if self.modified == Some(modified) {
+            return false;
+        }
+        self.modified = Some(modified);
+        self.names = fs::read_dir(&self.path)
+            .map(|list| {
+                list.flatten()
+                    .filter(|entry| is_executable(&entry.path()))
+                    .filter_map(|entry| entry.file_name().into_string().ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+        true
+    }
+}
+
+/// A file, or a link to one, that someone may execute.
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+impl CommandTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Catches up with `$PATH` and the directories in it.
+    pub fn refresh(&mut self) {
+        self.update(&env::var("PATH").unwrap_or_default());
+    }
+
+    fn update(&mut self, path: &str) {
+        let mut changed = false;
+        if self.path.as_deref() != Some(path) {
+            // Directories that stay in `$PATH` are not read again.
+            let mut old = mem::take(&mut self.dirs);
+            self.dirs = path
+                .split(':')
+                .map(|p| match old.iter().position(|d| d.path == Dir::new(p).path) {
+                    Some(i) => old.swap_remove(i),
+                    None => Dir::new(p),
+                })
+                .collect();
+            self.path = Some(path.into());
+            self.hits.clear();
+            changed = true;
+        }
+        for dir in &mut self.dirs {
+            changed |= dir.refresh();
+        }
+
+        if changed {
+            self.commands.clear();
+            for (i, dir) in self.dirs.iter().enumerate().rev() {
+                for name in &dir.names {
+                    self.commands.insert(name.clone(), i);
+                }
+            }
+        }
+    }
+
+    /// Where the command `name` is, without remembering it for `hash`.
+    pub fn path_of(&mut self, name: &str) -> Option<PathBuf> {
+        if name.contains('/') {
+            return None;
+        }
+        self.refresh();
+        self.commands.get(name).map(|&i| self.dirs[i].path.join(name))
+    }
+
+    /// Where the command `name` is, without counting it as run.
+    pub fn lookup(&mut self, name: &str) -> Option<PathBuf> {
+        let path = self.path_of(name)?;
+        self.hits.entry(name.into()).or_insert(0);
+        Some(path)
+    }
+
+    /// Where the command `name` is, to run it.
+    pub fn find(&mut self, name: &str) -> Option<PathBuf> {
+        let path = self.lookup(name)?;
+        *self.hits.get_mut(name).unwrap() += 1;
+        Some(path)
+    }
+
+    /// The names of all the commands.
+    pub fn names(&mut self) -> Keys<'_, String, usize> {
+        self.refresh();
+        self.commands.keys()
+    }
+
+    /// The commands looked up so far, with how many times each was run and
+    /// where it is.
+    pub fn remembered(&mut self) -> Vec<(String, usize, PathBuf)> {
+        self.refresh();
+        let (commands, dirs) = (&self.commands, &self.dirs);
+        self.hits
+            .iter()
+            .filter_map(|(name, &hits)| {
+                commands.get(name).map(|&i| (name.clone(), hits, dirs[i].path.join(name)))
+            })
+            .collect()
+    }
+
+    /// Forgets everything, to read all the directories again.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+#[test]
+fn test_command_table() {
+    use std::fs::File;
+    use std::thread::sleep;
+    use std::time::Duration;
+    use testing::TempDir;
+
+    let base = TempDir::new("hash");
+    let (first, second) = (base.join("first"), base.join("second"));
+    fs::create_dir_all(&first).unwrap();
+    fs::create_dir_all(&second).unwrap();
+    let create = |path: PathBuf, mode: u32| {
+        File::create(&path).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+    };
+    create(first.join("tool"), 0o755);
+    create(first.join("notes"), 0o644);
+    create(second.join("tool"), 0o755);
+    create(second.join("other"), 0o700);
+
+    let path = format!("{}:{}", first.display(), second.display());
+    let mut table = CommandTable::new();
+    table.update(&path);
+    assert_eq!(table.commands.keys().collect::<Vec<_>>(), vec!["other", "tool"]);
+    assert_eq!(table.commands.get("tool"), Some(&0));
+
+    // A new file changes the directory.
+    sleep(Duration::from_millis(10));
+    create(second.join("new"), 0o755);
+    table.update(&path);
+    assert!(table.commands.contains_key("new"));
+
+    // So does a PATH without the first directory.
+    table.update(&second.display().to_string());
+    assert_eq!(table.commands.get("tool"), Some(&0));
+    assert_eq!(table.dirs.len(), 1);
+
+    // A directory that isn't there is read once.
+    let mut missing = Dir::new(&base.join("missing").display().to_string());
+    assert!(missing.refresh());
+    assert!(!missing.refresh());
+}
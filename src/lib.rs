@@ -1,7 +1,17 @@
 pub mod readline;
 pub mod commands;
+pub mod error;
+pub mod expand;
+pub mod hash;
+pub mod options;
 pub mod parser;
 pub mod process;
+pub mod shell;
 pub mod token;
+#[cfg(test)]
+mod testing;
+pub mod trap;
 
 extern crate nix;
+extern crate unicode_segmentation;
+extern crate unicode_width;
@@ -1,21 +1,24 @@
 extern crate mican;
 
+use std::env;
+use std::cell::RefCell;
 use std::error::Error;
 use std::fs;
+use std::io;
 use std::io::prelude::*;
 use std::path::Path;
+use std::rc::Rc;
 
-use mican::commands;
-use mican::parser;
-use mican::process::Process;
+use mican::commands::complete;
+use mican::shell::Shell;
 use mican::readline::reader::Reader;
-use mican::readline::context::Context;
-use mican::readline::completer::Bin as BinCompleter;
+use mican::readline::context::{Context, EditingMode};
+use mican::readline::completer::Dispatcher;
 
 fn display_logo() {
     let path = Path::new("logo.txt");
 
-    let mut file = match fs::File::open(&path) {
+    let mut file = match fs::File::open(path) {
         Err(why) => panic!("couldn't open: {}", <dyn Error>::to_string(&why)),
         Ok(file) => file,
     };
@@ -36,42 +39,114 @@ fn display_logo() {
     };
 }
 
-fn waitpids(children: Vec<Process>) {
-    for c in children {
-        if let Err(e) = c.wait() {
-            println!("Error!: {:?}", e);
-        };
+/// Runs a script line by line and stops at the first parse error.
+fn run_script(shell: &mut Shell, path: &str) -> i32 {
+    let mut source = String::new();
+    if let Err(e) = fs::File::open(path).and_then(|mut f| f.read_to_string(&mut source)) {
+        eprintln!("mican: {}: {}", path, e);
+        return 127;
+    }
+
+    let mut offset = 0;
+    for line in source.split('\n') {
+        if let Err(e) = shell.run(line) {
+            eprint!("{}", e.offset(offset).render(&source, Some(path)));
+            return 2;
+        }
+        offset += line.len() + 1;
+    }
+    shell.last_status
+}
+
+/// Runs `~/.micanrc`, if there is one, before the first prompt.
+fn run_rc_file(shell: &mut Shell) {
+    if let Ok(home) = env::var("HOME") {
+        let path = format!("{}/.micanrc", home);
+        if Path::new(&path).is_file() {
+            run_script(shell, &path);
+        }
     }
 }
 
 fn main() {
-    display_logo();
-    println!("Welcome to Mican Unix Shell.");
-    let mut reader = Reader::new(Context::new(Box::new(BinCompleter::new())));
+    let mut shell = Shell::new();
 
-    loop {
-        if let Some(input) = reader.read_line() {
-            let commands = parser::Parser::new(input).parse();
+    let mut args = env::args().skip(1);
+    if let Some(path) = args.next() {
+        if let Err(e) = shell.install_signals() {
+            eprintln!("mican: {}", e);
+        }
+        shell.name = path.clone();
+        shell.positional = args.collect();
+        let status = run_script(&mut shell, &path);
+        shell.exit(status);
+    }
 
-            let mut children: Vec<Process> = Vec::new();
-            for c in commands {
-                let p = match c.program.as_str() {
-                    "cd" => Process::new(commands::cd::run),
-                    "ls" => Process::new(commands::ls::run),
-                    "pwd" => Process::new(commands::pwd::run),
-                    "clear" => Process::new(commands::clear::run),
-                    "bye" => Process::new(commands::bye::run),
-                    "tanakh" => Process::new(commands::tanakh::run),
-                    "syar" => Process::new(commands::syar::run),
-                    _ => Process::new(commands::other::run),
-                };
-                if p.in_child() {
-                    let _ = p.run(c).map_err(|err| eprintln!("{}", err));
-                } else {
-                    children.push(p)
+    let mut reader = Reader::new(
+        Context::new(Box::new(Dispatcher::new(
+            shell.completions.clone(),
+            shell.command_table.clone(),
+            shell.vars.clone(),
+        ))),
+        shell.keymaps.clone(),
+    );
+    shell.interactive = reader.is_interactive();
+    if shell.interactive {
+        display_logo();
+        println!("Welcome to Mican Unix Shell.");
+        for e in shell.keymaps.borrow_mut().read_inputrc() {
+            eprintln!("mican: {}", e);
+        }
+        if let Some(mode) = shell.keymaps.borrow_mut().editing_mode.take() {
+            shell.options.vi = mode == EditingMode::Vi;
+        }
+        run_rc_file(&mut shell);
+    }
+
+    // Completion runs `complete -F` commands in the shell while a line is
+    // read, when nothing else is using it.
+    let completions = shell.completions.clone();
+    let shell = Rc::new(RefCell::new(shell));
+    let weak = Rc::downgrade(&shell);
+    completions.borrow_mut().function_runner = Some(Box::new(move |function, context| {
+        match weak.upgrade() {
+            Some(shell) => match shell.try_borrow_mut() {
+                Ok(mut shell) => complete::call_function(&mut shell, function, context),
+                Err(_) => Vec::new(),
+            },
+            None => Vec::new(),
+        }
+    }));
+
+    loop {
+        reader.set_editing_mode(if shell.borrow().options.vi {
+            EditingMode::Vi
+        } else {
+            EditingMode::Emacs
+        });
+        reader.set_matching(shell.borrow().shopts.matching());
+        let line = reader.read_line();
+        let mut shell = shell.borrow_mut();
+        match line {
+            Ok(Some(input)) => {
+                // The line editor installs its own handlers while reading.
+                if let Err(e) = shell.install_signals() {
+                    eprintln!("mican: {}", e);
+                }
+                if let Err(e) = shell.run(&input) {
+                    eprint!("{}", e.render(&input, None));
                 }
             }
-            waitpids(children);
+            Ok(None) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                let status = shell.last_status;
+                shell.exit(status);
+            }
+            Err(e) => {
+                eprintln!("mican: {}", e);
+                shell.exit(1);
+            }
         }
+        shell.run_pending_traps();
     }
 }
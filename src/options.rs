@@ -0,0 +1,187 @@
+//! Shell options changed by `set` and `shopt`.
+
+use readline::completer::Matching;
+
+/// Options toggled by `set`. Each has a long name for `set -o` and most
+/// have a single letter flag.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// `-e`: exit as soon as a command fails.
+    pub errexit: bool,
+    /// `-u`: expanding an unset variable is an error.
+    pub nounset: bool,
+    /// `-x`: print each expanded command to stderr, prefixed with `PS4`.
+    pub xtrace: bool,
+    /// `-o pipefail`: a pipeline fails if any of its commands fails.
+    pub pipefail: bool,
+    /// `-C`: `>` refuses to overwrite an existing file.
+    pub noclobber: bool,
+    /// `-f`: disable pathname expansion.
+    pub noglob: bool,
+    /// `-v`: print input lines as they are read.
+    pub verbose: bool,
+    /// `-o vi`: edit lines with vi keys. `-o emacs`, the default, turns it
+    /// off again.
+    pub vi: bool,
+}
+
+/// (flag, long name) of every option, in the order `set -o` lists them.
+const OPTIONS: &[(Option<char>, &str)] = &[
+    (Some('e'), "errexit"),
+    (Some('f'), "noglob"),
+    (Some('C'), "noclobber"),
+    (None, "emacs"),
+    (Some('u'), "nounset"),
+    (None, "pipefail"),
+    (Some('v'), "verbose"),
+    (None, "vi"),
+    (Some('x'), "xtrace"),
+];
+
+impl Options {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn names() -> Vec<&'static str> {
+        OPTIONS.iter().map(|&(_, name)| name).collect()
+    }
+
+    pub fn name_of_flag(flag: char) -> Option<&'static str> {
+        OPTIONS.iter().find(|&&(f, _)| f == Some(flag)).map(
+            |&(_, name)| name,
+        )
+    }
+
+    pub fn get(&self, name: &str) -> Option<bool> {
+        match name {
+            "errexit" => Some(self.errexit),
+            "nounset" => Some(self.nounset),
+            "xtrace" => Some(self.xtrace),
+            "pipefail" => Some(self.pipefail),
+            "noclobber" => Some(self.noclobber),
+            "noglob" => Some(self.noglob),
+            "verbose" => Some(self.verbose),
+            "vi" => Some(self.vi),
+            "emacs" => Some(!self.vi),
+            _ => None,
+        }
+    }
+
+    /// Returns false when there is no option called `name`.
+    pub fn set(&mut self, name: &str, on: bool) -> bool {
+        if name == "emacs" {
+            self.vi = !on;
+            return true;
+        }
+
+        let opt = match name {
+            "errexit" => &mut self.errexit,
+            "nounset" => &mut self.nounset,
+            "xtrace" => &mut self.xtrace,
+            "pipefail" => &mut self.pipefail,
+            "noclobber" => &mut self.noclobber,
+            "noglob" => &mut self.noglob,
+            "verbose" => &mut self.verbose,
+            "vi" => &mut self.vi,
+            _ => return false,
+        };
+        *opt = on;
+        true
+    }
+
+    /// The flags that are on, as `$-` expands them.
+    pub fn flags(&self) -> String {
+        OPTIONS
+            .iter()
+            .filter_map(|&(f, name)| match f {
+                Some(f) if self.get(name) == Some(true) => Some(f),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// mican-specific behaviors toggled by `shopt -s`/`shopt -u`.
+#[derive(Debug, Clone, Default)]
+pub struct Shopts {
+    /// Let `*` and `?` match file names starting with a dot.
+    pub dotglob: bool,
+    /// Drop patterns that match nothing instead of passing them on as-is.
+    pub nullglob: bool,
+    /// Complete with names that start with the word in any case.
+    pub nocasecomplete: bool,
+    /// Complete with names that have the word anywhere in them.
+    pub substringcomplete: bool,
+    /// Complete with names that have the chars of the word in order, best
+    /// matches first.
+    pub fuzzycomplete: bool,
+}
+
+const SHOPTS: &[&str] = &["dotglob", "fuzzycomplete", "nocasecomplete", "nullglob", "substringcomplete"];
+
+impl Shopts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn names() -> Vec<&'static str> {
+        SHOPTS.to_vec()
+    }
+
+    pub fn get(&self, name: &str) -> Option<bool> {
+        match name {
+            "dotglob" => Some(self.dotglob),
+            "nullglob" => Some(self.nullglob),
+            "nocasecomplete" => Some(self.nocasecomplete),
+            "substringcomplete" => Some(self.substringcomplete),
+            "fuzzycomplete" => Some(self.fuzzycomplete),
+            _ => None,
+        }
+    }
+
+    /// Returns false when there is no option called `name`.
+    pub fn set(&mut self, name: &str, on: bool) -> bool {
+        let opt = match name {
+            "dotglob" => &mut self.dotglob,
+            "nullglob" => &mut self.nullglob,
+            "nocasecomplete" => &mut self.nocasecomplete,
+            "substringcomplete" => &mut self.substringcomplete,
+            "fuzzycomplete" => &mut self.fuzzycomplete,
+            _ => return false,
+        };
+        *opt = on;
+        true
+    }
+
+    /// How completion matches, the loosest of the options that are on.
+    pub fn matching(&self) -> Matching {
+        if self.fuzzycomplete {
+            Matching::Fuzzy
+        } else if self.substringcomplete {
+            Matching::Substring
+        } else if self.nocasecomplete {
+            Matching::CaseInsensitive
+        } else {
+            Matching::Prefix
+        }
+    }
+}
+
+#[test]
+fn test_options() {
+    let mut options = Options::new();
+    assert_eq!(Options::name_of_flag('e'), Some("errexit"));
+    assert_eq!(Options::name_of_flag('o'), None);
+    assert!(options.set("errexit", true));
+    assert!(options.set("pipefail", true));
+    assert!(!options.set("nosuchoption", true));
+    assert_eq!(options.get("errexit"), Some(true));
+    assert_eq!(options.flags(), "e");
+
+    assert_eq!(options.get("emacs"), Some(true));
+    assert!(options.set("vi", true));
+    assert_eq!(options.get("emacs"), Some(false));
+    assert!(options.set("emacs", true));
+    assert_eq!(options.get("vi"), Some(false));
+}
@@ -1,12 +1,24 @@
-use nix::unistd::pipe;
+use nix::fcntl::OFlag;
+use nix::unistd::pipe2;
 
-use token::{CommandData, Token, Input};
+use error::ParseError;
+use token::{CommandData, Token, TokenKind, Input, Redirect, RedirectKind};
 
 use std::fs;
 use std::os::unix::io::{FromRawFd, RawFd};
 use std::io;
 
 const PIPE: char = '|';
+const SEMICOLON: char = ';';
+const COMMENT: char = '#';
+const REDIRECT_IN: char = '<';
+const REDIRECT_OUT: char = '>';
+
+/// A parsed pipeline: commands connected by pipes, in order.
+#[derive(Debug, PartialEq)]
+pub struct Ast {
+    pub commands: Vec<CommandData>,
+}
 
 pub struct Parser {
     pub pos: usize,
@@ -23,23 +35,81 @@ impl Parser {
         }
     }
 
-    pub fn parse(&mut self) -> Vec<CommandData> {
-        let tokens = self.parse_tokens();
-        self.build_pipes(tokens)
+    /// Parses one pipeline, up to a `;` or the end of the line.
+    pub fn parse(&mut self) -> Result<Ast, ParseError> {
+        let tokens = self.parse_tokens()?;
+        if self.semicolon() {
+            if tokens.is_empty() {
+                let start = self.pos;
+                return Err(
+                    ParseError::new((start, start + 1), vec![TokenKind::Command], TokenKind::Semicolon)
+                        .with_hint("`;` has to follow a command"),
+                );
+            }
+            self.consume_char();
+        }
+        Ok(Ast { commands: self.build_pipes(tokens) })
     }
 
-    pub fn parse_tokens(&mut self) -> Vec<Token> {
+    /// Parses every pipeline in the input, separated by `;` or newlines.
+    pub fn parse_list(&mut self) -> Result<Vec<Ast>, ParseError> {
+        let mut list = Vec::new();
+        loop {
+            self.consume_whitespace();
+            if self.eof() {
+                return Ok(list);
+            } else if self.starts_with("\n") {
+                self.consume_char();
+            } else if self.next_char() == Some(COMMENT) {
+                self.consume_while(|c| c != '\n');
+            } else {
+                list.push(self.parse()?);
+            }
+        }
+    }
+
+    pub fn parse_tokens(&mut self) -> Result<Vec<Token>, ParseError> {
         let mut commands: Vec<Token> = vec![];
         loop {
             self.consume_whitespace();
-            if self.eof() || self.starts_with("\n") {
-                return commands;
+            if self.eol() || self.semicolon() {
+                if let Some(&Token::Pipe) = commands.last() {
+                    let end = self.pos;
+                    return Err(
+                        ParseError::new((end, end), vec![TokenKind::Command], TokenKind::EndOfLine)
+                            .with_hint("add a command after `|` or remove it"),
+                    );
+                }
+                return Ok(commands);
             }
-            commands.push(self.parse_token());
+
+            let start = self.pos;
+            let token = self.parse_token()?;
+            if token == Token::Pipe {
+                match commands.last() {
+                    Some(&Token::Command(_)) => (),
+                    prev => {
+                        let hint = if prev.is_none() {
+                            "a pipeline has to start with a command"
+                        } else {
+                            "`||` is not supported; put a command between the pipes"
+                        };
+                        return Err(
+                            ParseError::new((start, self.pos), vec![TokenKind::Command], TokenKind::Pipe)
+                                .with_hint(hint),
+                        );
+                    }
+                }
+            }
+            commands.push(token);
         }
     }
 
     fn build_pipes(&mut self, mut commands: Vec<Token>) -> Vec<CommandData> {
+        for _ in commands.iter().filter(|t| **t == Token::Pipe) {
+            // Close-on-exec, so a program never holds another command's pipe.
+            self.pipes.push(pipe2(OFlag::O_CLOEXEC).unwrap());
+        }
         commands.reverse();
         self.set_pipe(Input::Stdin(io::stdin()), commands)
     }
@@ -72,55 +142,163 @@ impl Parser {
         }
     }
 
-    fn parse_token(&mut self) -> Token {
+    fn parse_token(&mut self) -> Result<Token, ParseError> {
         match self.next_char() {
-            PIPE => self.parse_pipe(),
-            _ => Token::Command(self.parse_command()),
+            Some(PIPE) => Ok(self.parse_pipe()),
+            _ => Ok(Token::Command(self.parse_command()?)),
         }
     }
 
     fn parse_pipe(&mut self) -> Token {
-        let fds = pipe().unwrap();
-        self.pipes.push(fds);
         self.consume_char();
         Token::Pipe
     }
 
-    fn parse_command(&mut self) -> CommandData {
-        let program_ = self.consume_space_or_pipe();
-        let mut options_: Vec<String> = vec![];
+    fn parse_command(&mut self) -> Result<CommandData, ParseError> {
+        let mut words: Vec<String> = vec![];
+        let mut redirects_: Vec<Redirect> = vec![];
         loop {
             self.consume_whitespace();
-            if self.pipe() {
+            if self.eol() || self.pipe() || self.semicolon() {
                 break;
             }
-            let s = self.consume_space_or_pipe();
-            options_.push(s);
-            if self.eof() {
-                break;
+            if self.redirect() {
+                redirects_.push(self.parse_redirect()?);
+            } else {
+                words.push(self.parse_word()?);
             }
         }
 
-        CommandData {
+        let program_ = if words.is_empty() {
+            String::new()
+        } else {
+            words.remove(0)
+        };
+
+        Ok(CommandData {
             program: program_,
-            options: options_,
+            options: words,
+            redirects: redirects_,
             input: None,
             out: None,
+        })
+    }
+
+    fn parse_redirect(&mut self) -> Result<Redirect, ParseError> {
+        let kind = if self.starts_with(">>") {
+            RedirectKind::Append
+        } else if self.starts_with(">|") {
+            RedirectKind::Clobber
+        } else if self.starts_with(">") {
+            RedirectKind::Output
+        } else {
+            RedirectKind::Input
+        };
+        self.pos += match kind {
+            RedirectKind::Append | RedirectKind::Clobber => 2,
+            _ => 1,
+        };
+
+        self.consume_whitespace();
+        if self.eol() || self.pipe() || self.semicolon() || self.redirect() {
+            let start = self.pos;
+            let found = if self.pipe() {
+                TokenKind::Pipe
+            } else if self.semicolon() {
+                TokenKind::Semicolon
+            } else if self.redirect() {
+                TokenKind::Redirect
+            } else {
+                TokenKind::EndOfLine
+            };
+            let end = if found == TokenKind::EndOfLine { start } else { start + 1 };
+            return Err(
+                ParseError::new((start, end), vec![TokenKind::FileName], found)
+                    .with_hint("a redirection needs a file to read from or write to"),
+            );
         }
+
+        Ok(Redirect {
+            kind,
+            target: self.parse_word()?,
+        })
     }
 
-    fn next_char(&self) -> char {
-        self.input[self.pos..].chars().next().unwrap()
+    /// Reads one word as written, keeping quotes and backslashes for `expand`.
+    fn parse_word(&mut self) -> Result<String, ParseError> {
+        let mut word = String::new();
+        while let Some(c) = self.next_char() {
+            match c {
+                '\'' | '"' => {
+                    let start = self.pos;
+                    word.push(self.consume_char());
+                    loop {
+                        match self.next_char() {
+                            None => {
+                                return Err(
+                                    ParseError::new(
+                                        (start, start + 1),
+                                        vec![TokenKind::ClosingQuote(c)],
+                                        TokenKind::EndOfLine,
+                                    ).with_hint(&format!("the quote opened here is never closed with `{}`", c)),
+                                );
+                            }
+                            Some('\\') if c == '"' => {
+                                word.push(self.consume_char());
+                                if !self.eof() {
+                                    word.push(self.consume_char());
+                                }
+                            }
+                            Some(q) => {
+                                word.push(self.consume_char());
+                                if q == c {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                }
+                '\\' => {
+                    word.push(self.consume_char());
+                    if !self.eof() {
+                        word.push(self.consume_char());
+                    }
+                }
+                c if char::is_whitespace(c) || c == PIPE || c == SEMICOLON || c == REDIRECT_IN ||
+                         c == REDIRECT_OUT => {
+                    break
+                }
+                _ => word.push(self.consume_char()),
+            }
+        }
+        Ok(word)
+    }
+
+    fn next_char(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
     }
 
     fn eof(&self) -> bool {
         self.pos >= self.input.len()
     }
 
+    /// Whether the rest of the line has nothing left to parse.
+    fn eol(&self) -> bool {
+        self.eof() || self.starts_with("\n") || self.next_char() == Some(COMMENT)
+    }
+
     fn pipe(&mut self) -> bool {
         self.starts_with("|")
     }
 
+    fn semicolon(&self) -> bool {
+        self.next_char() == Some(SEMICOLON)
+    }
+
+    fn redirect(&self) -> bool {
+        self.next_char() == Some(REDIRECT_IN) || self.next_char() == Some(REDIRECT_OUT)
+    }
+
     fn starts_with(&self, s: &str) -> bool {
         self.input[self.pos..].starts_with(s)
     }
@@ -130,45 +308,76 @@ impl Parser {
         F: Fn(char) -> bool,
     {
         let mut result = String::new();
-        while !self.eof() && f(self.next_char()) {
+        while let Some(c) = self.next_char() {
+            if !f(c) {
+                break;
+            }
             result.push(self.consume_char());
         }
         result
     }
 
     fn consume_char(&mut self) -> char {
-        let mut iter = self.input[self.pos..].char_indices();
-        let (_, cur_char) = iter.next().unwrap();
-        let (next_pos, _) = iter.next().unwrap_or((1, ' '));
-        self.pos += next_pos;
+        let cur_char = self.next_char().unwrap();
+        self.pos += cur_char.len_utf8();
         cur_char
     }
 
     fn consume_whitespace(&mut self) {
-        self.consume_while(char::is_whitespace);
+        self.consume_while(|c| char::is_whitespace(c) && c != '\n');
     }
 
-    fn consume_space_or_pipe(&mut self) -> String {
-        self.consume_while(|c| !char::is_whitespace(c) && c != PIPE)
-    }
 }
 
 #[test]
 fn test_parse_tokens() {
     let input = "ls -al | grep main.rs".to_string();
-    let result = Parser::new(input).parse_tokens();
+    let result = Parser::new(input).parse_tokens().unwrap();
     let ls = Token::Command(CommandData {
         program: "ls".to_string(),
         options: vec!["-al".to_string()],
+        redirects: vec![],
         input: None,
         out: None,
     });
     let grep = Token::Command(CommandData {
         program: "grep".to_string(),
         options: vec!["main.rs".to_string()],
+        redirects: vec![],
         input: None,
         out: None,
     });
 
     assert_eq!(result, vec![ls, Token::Pipe, grep]);
 }
+
+#[test]
+fn test_parse_errors() {
+    let err = Parser::new("ls -al |".to_string()).parse().unwrap_err();
+    assert_eq!(err.span, (8, 8));
+    assert_eq!(err.expected, vec![TokenKind::Command]);
+    assert_eq!(err.found, TokenKind::EndOfLine);
+
+    let err = Parser::new("| grep main.rs".to_string()).parse().unwrap_err();
+    assert_eq!(err.span, (0, 1));
+    assert_eq!(err.found, TokenKind::Pipe);
+
+    let err = Parser::new("ls || grep".to_string()).parse().unwrap_err();
+    assert_eq!(err.span, (4, 5));
+
+    let ast = Parser::new("echo \u{1f34a} # comment".to_string()).parse().unwrap();
+    assert_eq!(ast.commands.len(), 1);
+    assert_eq!(ast.commands[0].options, vec!["\u{1f34a}".to_string()]);
+}
+
+#[test]
+fn test_parse_list() {
+    let list = Parser::new("cd /; ls | grep a\npwd # comment".to_string()).parse_list().unwrap();
+    assert_eq!(list.len(), 3);
+    assert_eq!(list[1].commands.len(), 2);
+    assert_eq!(list[2].commands[0].program, "pwd");
+
+    let err = Parser::new("ls;; pwd".to_string()).parse_list().unwrap_err();
+    assert_eq!(err.span, (3, 4));
+    assert_eq!(err.found, TokenKind::Semicolon);
+}
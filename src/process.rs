@@ -1,10 +1,13 @@
+use nix::errno::Errno;
 use nix::sys::wait::waitpid;
 use nix::unistd::{fork, getpid, ForkResult, Pid};
 use nix::sys::wait::WaitStatus;
+use nix::Error as NixError;
 use nix::Result as NixResult;
 
 use std::process::exit;
 use token::CommandData;
+use trap;
 
 pub struct Process {
     pub pid: Pid,
@@ -17,7 +20,10 @@ impl Process {
         let result = fork().unwrap();
         let pid_ = match result {
             ForkResult::Parent { child } => child,
-            ForkResult::Child => getpid(),
+            ForkResult::Child => {
+                trap::reset_in_child();
+                getpid()
+            }
         };
         Self {
             pid: pid_,
@@ -30,17 +36,39 @@ impl Process {
         self.fork_result.is_child()
     }
 
-    pub fn run(&self, cmd: CommandData) -> Result<(), String> {
-        let result = (self.f)(cmd);
-        self.exit();
-        result
+    /// Runs the command in the child and exits with its status.
+    pub fn run(&self, cmd: CommandData) -> ! {
+        Self::exit_with((self.f)(cmd))
+    }
+
+    /// Exits the child with the status of how its command went.
+    pub fn exit_with(result: Result<(), String>) -> ! {
+        match result {
+            Ok(()) => exit(0),
+            Err(e) => {
+                if !e.is_empty() {
+                    eprintln!("mican: {}", e);
+                }
+                exit(1)
+            }
+        }
     }
 
     pub fn wait(&self) -> NixResult<WaitStatus> {
-        waitpid(self.pid, None)
+        loop {
+            match waitpid(self.pid, None) {
+                Err(NixError::Sys(Errno::EINTR)) => (),
+                res => return res,
+            }
+        }
     }
+}
 
-    fn exit(&self) {
-        exit(self.pid.into())
+/// Converts how a child ended into an exit status, `128 + n` for signal `n`.
+pub fn exit_status(status: &WaitStatus) -> i32 {
+    match *status {
+        WaitStatus::Exited(_, code) => code,
+        WaitStatus::Signaled(_, sig, _) => 128 + sig as i32,
+        _ => 0,
     }
 }
@@ -1,5 +1,7 @@
 use std::iter::FromIterator;
 
+use unicode_segmentation::UnicodeSegmentation;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CursorPosition {
     InWord(usize),
@@ -37,6 +39,13 @@ impl CursorPosition {
     }
 }
 
+/// What separates the words `Ctrl-W` rubs out: whitespace only.
+pub const UNIX_WORD_DELIMITERS: &str = " \t\n";
+
+/// What separates the words `Alt-D` kills and the other word commands move
+/// over: whitespace and shell punctuation.
+pub const WORD_DELIMITERS: &str = " \t\n\"'`$@<>=;|&(){}/:.,-";
+
 /// A buffer for text in the line editor.
 #[derive(Clone, Default)]
 pub struct Buffer {
@@ -76,6 +85,10 @@ impl Buffer {
         self.data.len()
     }
 
+    pub fn as_slice(&self) -> &[char] {
+        &self.data
+    }
+
     // TODO
     pub fn as_str(&self) -> String {
         String::from(self.clone())
@@ -91,7 +104,63 @@ impl Buffer {
         }
     }
 
+    /// The char ranges of the grapheme clusters, which the cursor moves
+    /// over as single characters.
+    pub fn graphemes(&self) -> Vec<(usize, usize)> {
+        let mut res = Vec::new();
+        let mut start = 0;
+        for g in self.as_str().graphemes(true) {
+            let end = start + g.chars().count();
+            res.push((start, end));
+            start = end;
+        }
+        res
+    }
+
+    /// The start of the grapheme cluster before `pos`.
+    pub fn prev_boundary(&self, pos: usize) -> usize {
+        self.graphemes()
+            .into_iter()
+            .rev()
+            .map(|(start, _)| start)
+            .find(|&start| start < pos)
+            .unwrap_or(0)
+    }
+
+    /// The end of the grapheme cluster after `pos`.
+    pub fn next_boundary(&self, pos: usize) -> usize {
+        self.graphemes()
+            .into_iter()
+            .map(|(_, end)| end)
+            .find(|&end| end > pos)
+            .unwrap_or_else(|| self.len())
+    }
+
+    /// Returns the quote left open at the end of the buffer, if any.
+    pub fn open_quote(&self) -> Option<char> {
+        let mut quote = None;
+        let mut chars = self.data.iter();
+        while let Some(&c) = chars.next() {
+            match (quote, c) {
+                (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+                (Some('\''), _) => (),
+                (_, '\\') => {
+                    chars.next();
+                }
+                (None, '\'') | (None, '"') => quote = Some(c),
+                _ => (),
+            }
+        }
+        quote
+    }
+
     pub fn get_words(&self) -> Vec<(usize, usize)> {
+        self.get_words_by(" ")
+    }
+
+    /// Splits the buffer into words separated by any of `delimiters`. A
+    /// delimiter escaped with a backslash is part of the word.
+    pub fn get_words_by(&self, delimiters: &str) -> Vec<(usize, usize)> {
         let mut res = Vec::new();
 
         let mut word_start = None;
@@ -103,12 +172,13 @@ impl Buffer {
                 continue;
             }
 
+            let is_delimiter = delimiters.contains(c) && !is_backslash;
             if let Some(start) = word_start {
-                if c == ' ' && !is_backslash {
+                if is_delimiter {
                     res.push((start, i));
                     word_start = None;
                 }
-            } else if c != ' ' {
+            } else if !is_delimiter {
                 word_start = Some(i);
             }
 
@@ -128,3 +198,26 @@ impl Buffer {
         (words, pos)
     }
 }
+
+#[test]
+fn test_graphemes() {
+    // "e" with a combining acute accent, then a ZWJ family and a CJK char.
+    let buf = Buffer::from("e\u{301}\u{1f468}\u{200d}\u{1f469}日");
+    assert_eq!(buf.graphemes(), vec![(0, 2), (2, 5), (5, 6)]);
+    assert_eq!(buf.prev_boundary(6), 5);
+    assert_eq!(buf.prev_boundary(5), 2);
+    assert_eq!(buf.prev_boundary(0), 0);
+    assert_eq!(buf.next_boundary(0), 2);
+    assert_eq!(buf.next_boundary(2), 5);
+    assert_eq!(buf.next_boundary(6), 6);
+}
+
+#[test]
+fn test_get_words_by() {
+    let buf = Buffer::from("cd ~/src/mican; ls");
+    assert_eq!(buf.get_words_by(UNIX_WORD_DELIMITERS), vec![(0, 2), (3, 15), (16, 18)]);
+    assert_eq!(
+        buf.get_words_by(WORD_DELIMITERS),
+        vec![(0, 2), (3, 4), (5, 8), (9, 14), (16, 18)]
+    );
+}
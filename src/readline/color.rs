@@ -13,3 +13,8 @@ pub fn gray(s: &str) -> String {
 pub fn white(s: &str) -> String {
     format!("\x1B[7m{}\x1B[m", s)
 }
+
+/// Bold yellow text that keeps the background it is on.
+pub fn highlight(s: &str) -> String {
+    format!("\x1B[1;33m{}\x1B[22;39m", s)
+}
@@ -1,172 +1,1803 @@
+use std::cell::RefCell;
 use std::env;
-use std::collections::HashSet;
-use std::fs::read_dir;
-use std::path::is_separator;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::CStr;
+use std::fmt;
+use std::fs::{self, read_dir};
+use std::path::{is_separator, PathBuf};
 use std::iter::Iterator;
+use std::ops::Range;
+use std::rc::Rc;
 
+use nix::libc;
+
+use commands::{self, BUILTINS};
+use hash::CommandTable;
+use readline::help::Help;
 use readline::terminal;
 use readline::color;
+use readline::width::{pad, str_width, truncate};
+
+/// What is known about the word being completed.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CompletionContext {
+    /// The whole line.
+    pub line: String,
+    /// The words of the command the cursor is in, as typed, up to and
+    /// including the one being completed.
+    pub words: Vec<String>,
+    /// Which of `words` is being completed, always the last.
+    pub index: usize,
+    /// The word being completed as typed, up to the cursor.
+    pub word: String,
+    /// `word` without its quotes and backslashes.
+    pub text: String,
+    /// Where the cursor is in the line, in chars.
+    pub cursor: usize,
+    /// The quote left open before the cursor.
+    pub quote: Option<char>,
+    /// Where in `word` the `$` that `text` starts with is, when it expands:
+    /// not escaped nor in single quotes.
+    pub dollar: Option<usize>,
+    /// Whether the word is the target of `<` or `>`.
+    pub redirect: bool,
+    /// How candidates are picked by what is typed.
+    pub matching: Matching,
+}
+
+impl CompletionContext {
+    /// Splits the line up to `cursor` into words the way the shell would, as
+    /// far as completing needs: `;`, `|`, `&`, `(` and `)` start a new
+    /// command, and `<` and `>` a redirection target.
+    pub fn new(line: &[char], cursor: usize) -> Self {
+        let mut words: Vec<String> = Vec::new();
+        let mut word = String::new();
+        let mut text = String::new();
+        let mut in_word = false;
+        let mut quote = None;
+        let mut redirect = false;
+        let mut escaped = false;
+        let mut dollar = None;
+
+        for &c in &line[..cursor] {
+            if escaped {
+                word.push(c);
+                text.push(c);
+                escaped = false;
+                continue;
+            }
+            match (quote, c) {
+                (Some(q), _) if c == q => {
+                    quote = None;
+                    word.push(c);
+                }
+                (Some('\''), _) => {
+                    word.push(c);
+                    text.push(c);
+                }
+                (_, '\\') => {
+                    word.push(c);
+                    escaped = true;
+                    in_word = true;
+                }
+                (Some(_), _) => {
+                    if c == '$' && text.is_empty() {
+                        dollar = Some(word.len());
+                    }
+                    word.push(c);
+                    text.push(c);
+                }
+                (None, '\'') | (None, '"') => {
+                    quote = Some(c);
+                    word.push(c);
+                    in_word = true;
+                }
+                (None, ' ') | (None, '\t') | (None, '\n') => {
+                    if in_word {
+                        words.push(word.clone());
+                        word.clear();
+                        text.clear();
+                        dollar = None;
+                        in_word = false;
+                        redirect = false;
+                    }
+                }
+                (None, ';') | (None, '|') | (None, '&') | (None, '(') | (None, ')') => {
+                    words.clear();
+                    word.clear();
+                    text.clear();
+                    dollar = None;
+                    in_word = false;
+                    redirect = false;
+                }
+                (None, '<') | (None, '>') => {
+                    if in_word && !word.chars().all(|c| c.is_ascii_digit()) {
+                        words.push(word.clone());
+                    }
+                    word.clear();
+                    text.clear();
+                    dollar = None;
+                    in_word = false;
+                    redirect = true;
+                }
+                _ => {
+                    if c == '$' && text.is_empty() {
+                        dollar = Some(word.len());
+                    }
+                    word.push(c);
+                    text.push(c);
+                    in_word = true;
+                }
+            }
+        }
+
+        words.push(word.clone());
+        Self {
+            line: line.iter().collect(),
+            index: words.len() - 1,
+            words,
+            word,
+            text,
+            cursor,
+            quote,
+            dollar,
+            redirect,
+            matching: Matching::default(),
+        }
+    }
+
+    /// The name of the command the word is an argument of.
+    pub fn command(&self) -> Option<&str> {
+        if self.index > 0 {
+            Some(&self.words[0])
+        } else {
+            None
+        }
+    }
+
+    /// The words before the one being completed.
+    pub fn preceding(&self) -> &[String] {
+        &self.words[..self.index]
+    }
+
+    /// Whether the word is where a command name goes.
+    pub fn is_command_position(&self) -> bool {
+        self.index == 0 && !self.redirect
+    }
+
+    /// Whether `name` is a candidate for `typed`, and how well it matches.
+    pub fn matches(&self, typed: &str, name: &str) -> Option<Matched> {
+        self.matching.matches(typed, name)
+    }
+
+    /// Writes `text` back the way the word is typed: inside its quote, or
+    /// with backslashes.
+    pub fn requote(&self, text: &str) -> String {
+        match self.quote {
+            Some(q) => format!("{}{}", q, text),
+            None => escape(text),
+        }
+    }
+}
+
+/// What a candidate is, shown next to it in the menu.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Builtin,
+    Function,
+    Alias,
+    Executable,
+    File,
+    Dir,
+    Variable,
+    Option,
+    User,
+    Host,
+    /// From a `complete -W` list or `-F` command.
+    Word,
+}
+
+impl Kind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Builtin => "builtin",
+            Kind::Function => "function",
+            Kind::Alias => "alias",
+            Kind::Executable => "bin",
+            Kind::File => "file",
+            Kind::Dir => "dir",
+            Kind::Variable => "variable",
+            Kind::Option => "option",
+            Kind::User => "user",
+            Kind::Host => "host",
+            Kind::Word => "word",
+        }
+    }
+}
+
+/// One way to complete the word.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    /// What the menu shows, like a file name without its directory.
+    pub display: String,
+    /// What replaces the word in the line, quoted as the word is.
+    pub insertion: String,
+    pub kind: Kind,
+    pub description: Option<String>,
+    /// How well it matches what is typed, better first in the menu.
+    pub score: i64,
+    /// The chars of `display` that match what is typed, highlighted in the
+    /// menu.
+    pub positions: Vec<usize>,
+}
+
+impl Candidate {
+    pub fn new(display: &str, insertion: String, kind: Kind) -> Self {
+        Self {
+            display: display.into(),
+            insertion,
+            kind,
+            description: None,
+            score: 0,
+            positions: Vec::new(),
+        }
+    }
+
+    pub fn with_description(mut self, description: &str) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Records how the name matched, `offset` chars into `display`, after a
+    /// `$` or `~` that isn't part of the name.
+    pub fn with_match(mut self, matched: Matched, offset: usize) -> Self {
+        self.score = matched.score;
+        self.positions = matched.positions.iter().map(|p| p + offset).collect();
+        self
+    }
+}
+
+/// How what is typed picks candidates, chosen with `shopt`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Matching {
+    /// Names that start with it.
+    #[default]
+    Prefix,
+    /// Names that start with it, ignoring case.
+    CaseInsensitive,
+    /// Names that have it anywhere, earlier first.
+    Substring,
+    /// Names that have its chars in order, ranked the way fzf does.
+    Fuzzy,
+}
+
+/// How a name matched what is typed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Matched {
+    /// Higher is better.
+    pub score: i64,
+    /// The chars of the name that matched.
+    pub positions: Vec<usize>,
+}
+
+impl Matching {
+    pub fn matches(self, typed: &str, name: &str) -> Option<Matched> {
+        let len = typed.chars().count();
+        let from = |start: usize, score: i64| Matched {
+            score,
+            positions: (start..start + len).collect(),
+        };
+        match self {
+            Matching::Prefix if name.starts_with(typed) => Some(from(0, 0)),
+            Matching::CaseInsensitive => {
+                let mut chars = name.chars();
+                let same = typed
+                    .chars()
+                    .all(|t| chars.next().is_some_and(|c| eq_ignore_case(c, t)));
+                if same { Some(from(0, 0)) } else { None }
+            }
+            Matching::Substring => name.find(typed).map(|i| {
+                let start = name[..i].chars().count();
+                from(start, -(start as i64))
+            }),
+            Matching::Fuzzy => fuzzy_match(typed, name),
+            _ => None,
+        }
+    }
+}
+
+fn eq_ignore_case(a: char, b: char) -> bool {
+    a == b || a.to_lowercase().eq(b.to_lowercase())
+}
+
+const SCORE_MATCH: i64 = 16;
+const SCORE_GAP_START: i64 = -3;
+const SCORE_GAP_EXTENSION: i64 = -1;
+const BONUS_BOUNDARY: i64 = 8;
+const BONUS_CAMEL: i64 = 7;
+const BONUS_CONSECUTIVE: i64 = 4;
+const BONUS_FIRST_CHAR_MULTIPLIER: i64 = 2;
+
+/// Finds the chars of `typed` in order in `name`, in the shortest stretch
+/// that ends where they are first all found, and scores it like fzf's v1
+/// algorithm: matches after a word boundary or in a run count for more,
+/// gaps for less. Case only matters if `typed` has a capital.
+fn fuzzy_match(typed: &str, name: &str) -> Option<Matched> {
+    let pattern: Vec<char> = typed.chars().collect();
+    let chars: Vec<char> = name.chars().collect();
+    if pattern.is_empty() {
+        return Some(Matched::default());
+    }
+    let case_sensitive = pattern.iter().any(|c| c.is_uppercase());
+    let eq = |a: char, b: char| if case_sensitive { a == b } else { eq_ignore_case(a, b) };
+
+    // Forward to where the last char is first matched...
+    let mut p = 0;
+    let mut end = None;
+    for (i, &c) in chars.iter().enumerate() {
+        if eq(c, pattern[p]) {
+            p += 1;
+            if p == pattern.len() {
+                end = Some(i);
+                break;
+            }
+        }
+    }
+    let end = end?;
+    // ...then back to the latest start before it.
+    let mut start = end;
+    for i in (0..=end).rev() {
+        if eq(chars[i], pattern[p - 1]) {
+            p -= 1;
+            if p == 0 {
+                start = i;
+                break;
+            }
+        }
+    }
+
+    let mut matched = Matched::default();
+    let mut in_gap = false;
+    let mut consecutive = false;
+    for (i, &c) in chars.iter().enumerate().take(end + 1).skip(start) {
+        if p < pattern.len() && eq(c, pattern[p]) {
+            let mut bonus = bonus_at(&chars, i);
+            if consecutive {
+                bonus = bonus.max(BONUS_CONSECUTIVE);
+            }
+            if p == 0 {
+                bonus *= BONUS_FIRST_CHAR_MULTIPLIER;
+            }
+            matched.score += SCORE_MATCH + bonus;
+            matched.positions.push(i);
+            p += 1;
+            in_gap = false;
+            consecutive = true;
+        } else {
+            matched.score += if in_gap { SCORE_GAP_EXTENSION } else { SCORE_GAP_START };
+            in_gap = true;
+            consecutive = false;
+        }
+    }
+    Some(matched)
+}
+
+/// What matching the char at `i` is worth besides the match itself.
+fn bonus_at(chars: &[char], i: usize) -> i64 {
+    let c = chars[i];
+    match i.checked_sub(1).map(|i| chars[i]) {
+        None => BONUS_BOUNDARY,
+        Some(prev) if !prev.is_alphanumeric() && c.is_alphanumeric() => BONUS_BOUNDARY,
+        Some(prev) if prev.is_lowercase() && c.is_uppercase() => BONUS_CAMEL,
+        Some(prev) if !prev.is_numeric() && c.is_numeric() => BONUS_CAMEL,
+        _ => 0,
+    }
+}
 
 pub trait Completer {
-    fn complete(&mut self, path: &str) -> Vec<String>;
+    fn complete(&mut self, context: &CompletionContext) -> Vec<Candidate>;
+}
+
+/// How the `complete` builtin says to complete a command's arguments.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Spec {
+    /// `-W`: a list of words, split on whitespace.
+    pub words: Option<String>,
+    /// `-F`: a command the shell runs to fill `COMPREPLY`.
+    pub function: Option<String>,
+    /// `-f`: file names.
+    pub files: bool,
+    /// `-d`: directory names.
+    pub directories: bool,
+    /// `-c`: command names.
+    pub commands: bool,
+    /// `-v`: variable names.
+    pub variables: bool,
+}
 
-    fn max_len(&self) -> usize;
+/// Prints the spec as the options that make it, for `complete -p`.
+impl fmt::Display for Spec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flags: String = [
+            (self.files, 'f'),
+            (self.directories, 'd'),
+            (self.commands, 'c'),
+            (self.variables, 'v'),
+        ].iter()
+            .filter(|&&(on, _)| on)
+            .map(|&(_, flag)| flag)
+            .collect();
+        let mut options = Vec::new();
+        if !flags.is_empty() {
+            options.push(format!("-{}", flags));
+        }
+        if let Some(ref words) = self.words {
+            options.push(format!("-W '{}'", words.replace('\'', "'\\''")));
+        }
+        if let Some(ref function) = self.function {
+            options.push(format!("-F '{}'", function.replace('\'', "'\\''")));
+        }
+        write!(f, "{}", options.join(" "))
+    }
 }
 
+/// Runs the command of a `-F` spec and returns what it put in `COMPREPLY`.
+pub type FunctionRunner = Box<dyn FnMut(&str, &CompletionContext) -> Vec<String>>;
+
+/// The specs `complete` registered, by command name.
+#[derive(Default)]
+pub struct Specs {
+    specs: BTreeMap<String, Spec>,
+    /// Set by the shell, which is the one that can run commands.
+    pub function_runner: Option<FunctionRunner>,
+}
+
+impl Specs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, command: &str) -> Option<&Spec> {
+        self.specs.get(command)
+    }
+
+    pub fn set(&mut self, command: &str, spec: Spec) {
+        self.specs.insert(command.into(), spec);
+    }
+
+    pub fn remove(&mut self, command: &str) -> Option<Spec> {
+        self.specs.remove(command)
+    }
+
+    pub fn clear(&mut self) {
+        self.specs.clear();
+    }
+
+    /// The specs sorted by command name.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Spec)> {
+        self.specs.iter()
+    }
+}
+
+/// Commands whose arguments are host names.
+const HOST_COMMANDS: &[&str] = &["ftp", "mosh", "ping", "sftp", "ssh", "telnet"];
+
+/// Picks the completer for the word: variables after `$`, the spec
+/// `complete` gave the command, users after `~`, hosts after `@` or for `ssh`
+/// and the like, the command's options after `-`, the completer registered
+/// for the command, command names where a command goes, and paths anywhere
+/// else.
+pub struct Dispatcher {
+    pub commands: Box<dyn Completer>,
+    pub options: CommandOptions,
+    pub paths: Path,
+    pub variables: Variable,
+    pub users: User,
+    pub hosts: Host,
+    /// Completers for the arguments of particular commands.
+    pub per_command: HashMap<String, Box<dyn Completer>>,
+    /// Shared with the shell's `complete` builtin.
+    pub specs: Rc<RefCell<Specs>>,
+}
+
+impl Default for Dispatcher {
+    fn default() -> Self {
+        Self::new(
+            Rc::new(RefCell::new(Specs::new())),
+            Rc::new(RefCell::new(CommandTable::new())),
+            Rc::new(RefCell::new(HashMap::new())),
+        )
+    }
+}
+
+impl Dispatcher {
+    /// Completes with what the shell shares: the `complete` specs, the
+    /// command hash table and the variables that aren't exported.
+    pub fn new(
+        specs: Rc<RefCell<Specs>>,
+        command_table: Rc<RefCell<CommandTable>>,
+        shell_vars: Rc<RefCell<HashMap<String, String>>>,
+    ) -> Self {
+        let mut dispatcher = Self {
+            commands: Box::new(Commands::new(command_table.clone())),
+            options: CommandOptions::new(command_table),
+            paths: Path::new(),
+            variables: Variable::new(shell_vars),
+            users: User::new(),
+            hosts: Host::new(),
+            per_command: HashMap::new(),
+            specs,
+        };
+        dispatcher.register("cd", Box::new(Path::directories()));
+        dispatcher
+    }
+
+    pub fn register(&mut self, command: &str, completer: Box<dyn Completer>) {
+        self.per_command.insert(command.into(), completer);
+    }
+
+    /// Collects what each part of `spec` offers. Words from `-W` are
+    /// matched against the word; what a `-F` command gives is taken as is.
+    fn complete_spec(&mut self, spec: &Spec, context: &CompletionContext) -> Vec<Candidate> {
+        let text = &context.text;
+        let word = |w: &str| Candidate::new(w, context.requote(w), Kind::Word);
+        let mut res = Vec::new();
+
+        if let Some(ref words) = spec.words {
+            res.extend(words.split_whitespace().filter_map(|w| {
+                context.matches(text, w).map(|m| word(w).with_match(m, 0))
+            }));
+        }
+        if let Some(ref function) = spec.function {
+            // Taken out while it runs, since the command may call `complete`.
+            let runner = self.specs.borrow_mut().function_runner.take();
+            if let Some(mut runner) = runner {
+                res.extend(runner(function, context).iter().map(|w| word(w)));
+                self.specs.borrow_mut().function_runner = Some(runner);
+            }
+        }
+        if spec.files {
+            res.append(&mut self.paths.complete(context));
+        } else if spec.directories {
+            res.append(&mut Path::directories().complete(context));
+        }
+        if spec.commands {
+            res.append(&mut self.commands.complete(context));
+        }
+        if spec.variables {
+            res.extend(
+                self.variables.all().into_iter().filter_map(|(name, value)| {
+                    let matched = context.matches(text, &name)?;
+                    Some(
+                        Candidate::new(&name, name.clone(), Kind::Variable)
+                            .with_description(&value)
+                            .with_match(matched, 0),
+                    )
+                }),
+            );
+        }
+        res
+    }
+}
+
+impl Completer for Dispatcher {
+    fn complete(&mut self, context: &CompletionContext) -> Vec<Candidate> {
+        let text = &context.text;
+        let command = context.command().unwrap_or("");
+        let is_variable = context.dollar.is_some();
+        let spec = self.specs.borrow().get(command).cloned();
+        if let (Some(spec), false) = (spec, is_variable || context.redirect) {
+            return self.complete_spec(&spec, context);
+        }
+
+        // An option of a command nothing is known about is completed like
+        // any other word.
+        if text.starts_with('-') && !is_variable && context.index > 0 && !context.redirect {
+            let options = self.options.complete(context);
+            if !options.is_empty() {
+                return options;
+            }
+        }
+
+        let completer: &mut dyn Completer = if is_variable {
+            &mut self.variables
+        } else if text.starts_with('~') && !text.contains('/') {
+            &mut self.users
+        } else if text.contains('@') && !text.contains('/') ||
+            HOST_COMMANDS.contains(&command) && !text.starts_with('-')
+        {
+            &mut self.hosts
+        } else if context.redirect {
+            &mut self.paths
+        } else if let Some(completer) = self.per_command.get_mut(command) {
+            &mut **completer
+        } else if context.is_command_position() && !text.contains('/') {
+            &mut *self.commands
+        } else {
+            &mut self.paths
+        };
+        completer.complete(context)
+    }
+}
+
+/// Completes command names: mican's builtins, then the executables in
+/// `$PATH`. There are no shell functions or aliases to add yet.
+#[derive(Default)]
+pub struct Commands {
+    pub bin: Bin,
+}
+
+impl Commands {
+    pub fn new(command_table: Rc<RefCell<CommandTable>>) -> Self {
+        Self { bin: Bin::new(command_table) }
+    }
+}
+
+impl Completer for Commands {
+    /// A builtin hides the executable of the same name, as it does when run.
+    fn complete(&mut self, context: &CompletionContext) -> Vec<Candidate> {
+        let mut res: Vec<Candidate> = BUILTINS
+            .iter()
+            .filter_map(|&(name, _)| {
+                let matched = context.matches(&context.text, name)?;
+                Some(Candidate::new(name, context.requote(name), Kind::Builtin).with_match(matched, 0))
+            })
+            .collect();
+        let builtins = res.len();
+        for candidate in self.bin.complete(context) {
+            if res[..builtins].iter().all(|c| c.display != candidate.display) {
+                res.push(candidate);
+            }
+        }
+        res
+    }
+}
+
+/// Completes the options of a command with their descriptions, the way fish
+/// does: from what `cmd --help` prints, or else from its man page. Those of
+/// a subcommand the command lists, like `cargo build`, are its own.
+#[derive(Default)]
+pub struct CommandOptions {
+    pub command_table: Rc<RefCell<CommandTable>>,
+    /// What each command and subcommand takes, once found out.
+    found: HashMap<String, Rc<Help>>,
+}
+
+impl CommandOptions {
+    pub fn new(command_table: Rc<RefCell<CommandTable>>) -> Self {
+        Self {
+            command_table,
+            found: HashMap::new(),
+        }
+    }
+
+    fn help(&mut self, words: &[&str], path: &std::path::Path) -> Rc<Help> {
+        let key = words.join(" ");
+        if let Some(help) = self.found.get(&key) {
+            return help.clone();
+        }
+        let gzip = self.command_table.borrow_mut().path_of("gzip");
+        let help = Rc::new(Help::of(words, path, gzip.as_deref()));
+        self.found.insert(key, help.clone());
+        help
+    }
+}
+
+impl Completer for CommandOptions {
+    fn complete(&mut self, context: &CompletionContext) -> Vec<Candidate> {
+        let command = match context.command() {
+            Some(command) if commands::builtin(command).is_none() => command,
+            _ => return Vec::new(),
+        };
+        let path = match self.command_table.borrow_mut().path_of(command) {
+            Some(path) => path,
+            None => return Vec::new(),
+        };
+
+        let mut help = self.help(&[command], &path);
+        let subcommand = context.preceding()[1..].iter().find(|w| !w.starts_with('-'));
+        if let Some(subcommand) = subcommand {
+            if help.subcommands.contains(subcommand) {
+                let own = self.help(&[command, subcommand], &path);
+                if !own.options.is_empty() {
+                    help = own;
+                }
+            }
+        }
+
+        help.options
+            .iter()
+            .filter_map(|(flag, description)| {
+                let matched = context.matches(&context.text, flag)?;
+                let candidate = Candidate::new(flag, context.requote(flag), Kind::Option).with_match(matched, 0);
+                Some(if description.is_empty() { candidate } else { candidate.with_description(description) })
+            })
+            .collect()
+    }
+}
+
+/// Completes the names of the executables in `$PATH`.
 #[derive(Default)]
 pub struct Bin {
-    pub max_len: usize,
+    /// Shared with the shell, which runs commands from it.
+    pub command_table: Rc<RefCell<CommandTable>>,
 }
+
 impl Bin {
-    pub fn new() -> Self {
-        Self { max_len: 0 }
+    pub fn new(command_table: Rc<RefCell<CommandTable>>) -> Self {
+        Self { command_table }
     }
 }
 
 impl Completer for Bin {
-    fn max_len(&self) -> usize {
-        self.max_len
+    fn complete(&mut self, context: &CompletionContext) -> Vec<Candidate> {
+        let mut table = self.command_table.borrow_mut();
+        table
+            .names()
+            .filter_map(|name| {
+                let matched = context.matches(&context.text, name)?;
+                Some(Candidate::new(name, context.requote(name), Kind::Executable).with_match(matched, 0))
+            })
+            .collect()
+    }
+}
+
+/// Completes file and directory names, relative, absolute or under `~/`.
+#[derive(Default)]
+pub struct Path {
+    /// Only directories, for `cd`.
+    pub directories_only: bool,
+}
+
+impl Path {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn directories() -> Self {
+        Self { directories_only: true }
     }
+}
 
-    fn complete(&mut self, path: &str) -> Vec<String> {
-        let (_, fname) = match path.rfind(is_separator) {
-            Some(pos) => (Some(&path[..pos + 1]), &path[pos + 1..]),
-            None => (None, path),
+impl Completer for Path {
+    /// The completions are written the way the word is, quoted or with
+    /// backslashes, and shown by their file names. Directories end in `/`.
+    fn complete(&mut self, context: &CompletionContext) -> Vec<Candidate> {
+        let word = &context.text;
+        let (dir, fname) = match word.rfind(is_separator) {
+            Some(pos) => (&word[..pos + 1], &word[pos + 1..]),
+            None => ("", &word[..]),
         };
 
-        let env_path = env::var("PATH").unwrap();
-        let vec_path: Vec<&str> = env_path.split(':').collect();
-        let paths: HashSet<&str> = vec_path.into_iter().collect();
-        let mut res: Vec<String> = Vec::new();
-
-        let mut len = 0;
-
-        for p in &paths {
-            if let Ok(list) = read_dir(p) {
-                for entry in list {
-                    if let Ok(entry) = entry {
-                        if let Ok(name) = entry.file_name().into_string() {
-                            if name.starts_with(fname) {
-                                if name.len() > len {
-                                    len = name.len();
-                                }
-                                res.push(name);
-                            }
-                        }
-                    }
+        let dir_path = match (dir.strip_prefix('~'), env::var("HOME")) {
+            (Some(rest), Ok(home)) if rest.is_empty() || rest.starts_with('/') => {
+                PathBuf::from(format!("{}{}", home, rest))
+            }
+            _ if dir.is_empty() => PathBuf::from("."),
+            _ => PathBuf::from(dir),
+        };
+
+        let mut res: Vec<Candidate> = Vec::new();
+        if let Ok(list) = read_dir(&dir_path) {
+            for entry in list.flatten() {
+                let name = match entry.file_name().into_string() {
+                    Ok(name) => name,
+                    Err(_) => continue,
+                };
+                // Dotfiles only when a dot is typed.
+                if name.starts_with('.') && !fname.starts_with('.') {
+                    continue;
                 }
+                let matched = match context.matches(fname, &name) {
+                    Some(matched) => matched,
+                    None => continue,
+                };
+
+                // Follows symlinks to tell directories.
+                let is_dir = fs::metadata(entry.path()).is_ok_and(|m| m.is_dir());
+                if self.directories_only && !is_dir {
+                    continue;
+                }
+                let mut insertion = context.requote(&format!("{}{}", dir, name));
+                let candidate = match context.quote {
+                    _ if is_dir => {
+                        insertion.push('/');
+                        Candidate::new(&format!("{}/", name), insertion, Kind::Dir)
+                    }
+                    // A whole file name closes its quote.
+                    Some(q) => {
+                        insertion.push(q);
+                        Candidate::new(&name, insertion, Kind::File)
+                    }
+                    None => Candidate::new(&name, insertion, Kind::File),
+                };
+                res.push(candidate.with_match(matched, 0));
             }
         }
+        res
+    }
+}
+
+/// Completes the names of shell and environment variables after `$` or
+/// `${`.
+#[derive(Default)]
+pub struct Variable {
+    /// The shell's variables that aren't exported.
+    pub shell_vars: Rc<RefCell<HashMap<String, String>>>,
+}
+
+impl Variable {
+    pub fn new(shell_vars: Rc<RefCell<HashMap<String, String>>>) -> Self {
+        Self { shell_vars }
+    }
+
+    /// Every variable and its value.
+    pub fn all(&self) -> Vec<(String, String)> {
+        let mut vars: Vec<(String, String)> = env::vars().collect();
+        for (name, value) in self.shell_vars.borrow().iter() {
+            if env::var_os(name).is_none() {
+                vars.push((name.clone(), value.clone()));
+            }
+        }
+        vars
+    }
+}
+
+impl Completer for Variable {
+    /// Each comes with its value.
+    fn complete(&mut self, context: &CompletionContext) -> Vec<Candidate> {
+        let braced = context.text.starts_with("${");
+        let prefix = context.text.trim_start_matches('$').trim_start_matches('{');
+        let before = match context.dollar {
+            Some(i) => &context.word[..i],
+            None => return Vec::new(),
+        };
+
+        self.all()
+            .into_iter()
+            .filter_map(|(name, value)| {
+                let matched = context.matches(prefix, &name)?;
+                let insertion = if braced {
+                    format!("{}${{{}}}", before, name)
+                } else {
+                    format!("{}${}", before, name)
+                };
+                Some(
+                    Candidate::new(&format!("${}", name), insertion, Kind::Variable)
+                        .with_description(&value)
+                        .with_match(matched, 1),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Completes user names after `~`, from the passwd database.
+pub struct User {
+    /// Where the users and their home directories come from.
+    users: fn() -> Vec<(String, String)>,
+}
+
+impl Default for User {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl User {
+    pub fn new() -> Self {
+        Self { users }
+    }
+}
 
-        self.max_len = len;
+impl Completer for User {
+    /// Each comes with its home directory.
+    fn complete(&mut self, context: &CompletionContext) -> Vec<Candidate> {
+        let prefix = &context.text[1..];
+        let mut res: Vec<Candidate> = Vec::new();
+        for (user, home) in (self.users)() {
+            let matched = match context.matches(prefix, &user) {
+                Some(matched) => matched,
+                None => continue,
+            };
+            let name = format!("~{}", user);
+            if res.iter().all(|c| c.display != name) {
+                res.push(
+                    Candidate::new(&name, format!("{}/", name), Kind::User)
+                        .with_description(&home)
+                        .with_match(matched, 1),
+                );
+            }
+        }
         res
     }
 }
 
+/// Every user in the passwd database, local or not, and their home
+/// directory.
+fn users() -> Vec<(String, String)> {
+    let mut users = Vec::new();
+    // getpwent isn't reentrant, but completion only runs on the shell's
+    // thread.
+    unsafe {
+        let field = |p: *const libc::c_char| CStr::from_ptr(p).to_string_lossy().into_owned();
+        libc::setpwent();
+        loop {
+            let pw = libc::getpwent();
+            if pw.is_null() {
+                break;
+            }
+            users.push((field((*pw).pw_name), field((*pw).pw_dir)));
+        }
+        libc::endpwent();
+    }
+    users
+}
+
+/// Completes host names, from `/etc/hosts`, the `Host` entries of
+/// `~/.ssh/config` and `~/.ssh/known_hosts`. A `user@` before the host is
+/// kept.
+pub struct Host {
+    /// Where the hosts and their addresses come from.
+    hosts: fn() -> Vec<(String, Option<String>)>,
+}
+
+impl Default for Host {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Host {
+    pub fn new() -> Self {
+        Self { hosts }
+    }
+}
+
+impl Completer for Host {
+    /// Each comes with its address or `HostName`, when there is one.
+    fn complete(&mut self, context: &CompletionContext) -> Vec<Candidate> {
+        let (user, prefix) = match context.text.rfind('@') {
+            Some(i) => (&context.text[..i + 1], &context.text[i + 1..]),
+            None => ("", &context.text[..]),
+        };
+
+        let mut res: Vec<Candidate> = Vec::new();
+        for (name, address) in (self.hosts)() {
+            let matched = match context.matches(prefix, &name) {
+                Some(matched) => matched,
+                None => continue,
+            };
+            if res.iter().all(|c| c.display != name) {
+                let candidate = Candidate::new(&name, format!("{}{}", user, name), Kind::Host)
+                    .with_match(matched, 0);
+                res.push(match address {
+                    Some(address) => candidate.with_description(&address),
+                    None => candidate,
+                });
+            }
+        }
+        res
+    }
+}
+
+/// Every host in `/etc/hosts` and the ssh files, with its address when
+/// known.
+fn hosts() -> Vec<(String, Option<String>)> {
+    let read = |path: &str| fs::read_to_string(path).unwrap_or_default();
+    let ssh = env::var("HOME").map(|home| format!("{}/.ssh", home)).unwrap_or_default();
+    let mut hosts = parse_hosts(&read("/etc/hosts"));
+    hosts.append(&mut parse_ssh_config(&read(&format!("{}/config", ssh))));
+    hosts.append(&mut parse_known_hosts(&read(&format!("{}/known_hosts", ssh))));
+    hosts
+}
+
+/// The names in a hosts file, each with its address.
+fn parse_hosts(text: &str) -> Vec<(String, Option<String>)> {
+    let mut hosts = Vec::new();
+    for line in text.lines() {
+        let mut fields = line.split('#').next().unwrap_or("").split_whitespace();
+        if let Some(address) = fields.next() {
+            hosts.extend(fields.map(|name| (name.to_string(), Some(address.to_string()))));
+        }
+    }
+    hosts
+}
+
+/// The names of the `Host` entries in an ssh config, without patterns, each
+/// with the `HostName` it connects to.
+fn parse_ssh_config(text: &str) -> Vec<(String, Option<String>)> {
+    let mut hosts: Vec<(String, Option<String>)> = Vec::new();
+    // Where the entry being read starts.
+    let mut entry = 0;
+    for line in text.lines() {
+        let line = line.trim();
+        let (keyword, args) = match line.find(|c: char| c.is_whitespace() || c == '=') {
+            Some(i) => (&line[..i], line[i..].trim_start_matches(|c: char| c.is_whitespace() || c == '=')),
+            None => continue,
+        };
+        if keyword.eq_ignore_ascii_case("Host") {
+            entry = hosts.len();
+            hosts.extend(
+                args.split_whitespace()
+                    .filter(|name| !name.contains(|c| "*?!".contains(c)))
+                    .map(|name| (name.to_string(), None)),
+            );
+        } else if keyword.eq_ignore_ascii_case("Match") {
+            entry = hosts.len();
+        } else if keyword.eq_ignore_ascii_case("HostName") {
+            for host in &mut hosts[entry..] {
+                host.1 = Some(args.to_string());
+            }
+        }
+    }
+    hosts
+}
+
+/// The names in a `known_hosts` file, except hashed ones and patterns.
+fn parse_known_hosts(text: &str) -> Vec<(String, Option<String>)> {
+    let mut hosts = Vec::new();
+    for line in text.lines() {
+        let mut fields = line.split_whitespace();
+        let names = match fields.next() {
+            // `@cert-authority` or `@revoked`.
+            Some(marker) if marker.starts_with('@') => fields.next(),
+            names => names,
+        };
+        let names = match names {
+            Some(names) if !names.starts_with('#') && !names.starts_with('|') => names,
+            _ => continue,
+        };
+        for name in names.split(',') {
+            // `[host]:port`
+            let name = match (name.strip_prefix('['), name.find(']')) {
+                (Some(_), Some(end)) => &name[1..end],
+                _ => name,
+            };
+            if !name.is_empty() && !name.contains(|c| "*?!".contains(c)) {
+                hosts.push((name.to_string(), None));
+            }
+        }
+    }
+    hosts
+}
+
+/// Characters a backslash has to keep the shell from reading specially.
+const SPECIAL_CHARS: &str = " \t\n\\'\"`$&;|()<>*?[]#!{}";
+
+/// Puts a backslash before each special character in `s`.
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        if SPECIAL_CHARS.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Takes out the backslashes `escape` put in.
+pub fn unescape(s: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// Where a key moves the selection in the completion menu.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    /// The next candidate, on to the next row after the end of one.
+    Next,
+    Prev,
+    Up,
+    Down,
+    /// Down or up a number of rows, stopping at the ends of the column.
+    PageDown(usize),
+    PageUp(usize),
+}
+
+/// Moves the selection among `len` candidates laid out left to right in
+/// rows of `columns`. Nothing selected yet starts from the first or the
+/// last, and the ends wrap around.
+pub fn move_selection(selected: Option<usize>, len: usize, columns: usize, direction: Direction) -> Option<usize> {
+    if len == 0 {
+        return None;
+    }
+    let columns = columns.max(1);
+    let i = match (selected, direction) {
+        (None, Direction::Next) | (None, Direction::Down) | (None, Direction::PageDown(_)) => return Some(0),
+        (None, _) => return Some(len - 1),
+        (Some(i), _) => i,
+    };
+    let bottom = i % columns + (len - 1 - i % columns) / columns * columns;
+    Some(match direction {
+        Direction::Next => (i + 1) % len,
+        Direction::Prev => (i + len - 1) % len,
+        // Back to the top of the column.
+        Direction::Down if i + columns >= len => i % columns,
+        Direction::Down => i + columns,
+        // Down to the bottom of the column.
+        Direction::Up if i < columns => bottom,
+        Direction::Up => i - columns,
+        Direction::PageDown(rows) => bottom.min(i + rows * columns),
+        Direction::PageUp(rows) if i < rows * columns => i % columns,
+        Direction::PageUp(rows) => i - rows * columns,
+    })
+}
+
+/// The longest start all of `words` share. A backslash left at the end
+/// with nothing to escape is dropped.
+pub fn common_prefix<'a, I: IntoIterator<Item = &'a str>>(words: I) -> String {
+    let mut words = words.into_iter();
+    let mut prefix: Vec<char> = match words.next() {
+        Some(word) => word.chars().collect(),
+        None => return String::new(),
+    };
+    for word in words {
+        let len = prefix
+            .iter()
+            .zip(word.chars())
+            .take_while(|&(&a, b)| a == b)
+            .count();
+        prefix.truncate(len);
+    }
+    let backslashes = prefix.iter().rev().take_while(|&&c| c == '\\').count();
+    if backslashes % 2 == 1 {
+        prefix.pop();
+    }
+    prefix.into_iter().collect()
+}
+
+/// The rows of the completion menu on screen, out of all of them.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Viewport {
+    /// The first row on screen.
+    pub first: usize,
+    /// How many rows are on screen.
+    pub height: usize,
+    /// How many rows there are.
+    pub rows: usize,
+}
+
+impl Viewport {
+    /// Fits `rows` rows into at most `height`, keeping the first row on
+    /// screen where it was if it can.
+    pub fn resize(&mut self, rows: usize, height: usize) {
+        self.rows = rows;
+        self.height = height.min(rows);
+        self.first = self.first.min(rows - self.height);
+    }
+
+    /// Scrolls as little as it takes to show `row`.
+    pub fn scroll_to(&mut self, row: usize) {
+        if row < self.first {
+            self.first = row;
+        } else if row >= self.first + self.height {
+            self.first = row + 1 - self.height;
+        }
+    }
+
+    pub fn visible(&self) -> Range<usize> {
+        self.first..self.first + self.height
+    }
+
+    /// Whether some rows are off screen.
+    pub fn is_scrolled(&self) -> bool {
+        self.rows > self.height
+    }
+
+    /// How many rows are off screen below.
+    pub fn below(&self) -> usize {
+        self.rows - self.first - self.height
+    }
+
+    /// The visible rows the scroll bar's thumb takes. Its size is in
+    /// proportion to how many of the rows are shown, and it goes from the
+    /// top to the bottom as the viewport does.
+    pub fn thumb(&self) -> Range<usize> {
+        if !self.is_scrolled() {
+            return 0..self.height;
+        }
+        let size = ((self.height * self.height + self.rows / 2) / self.rows).clamp(1, self.height);
+        let track = self.height - size;
+        let scroll = self.rows - self.height;
+        let start = (self.first * track + scroll / 2) / scroll;
+        start..start + size
+    }
+}
+
 #[derive(Default)]
 pub struct CompletionArea {
-    pub max_len: usize,
-    pub completion_area_first: usize,
+    pub viewport: Viewport,
+    /// How many candidates there are to a row in the menu last drawn.
+    pub columns: usize,
+    /// How many rows the menu last drawn took up.
+    pub height: usize,
 }
 
 impl CompletionArea {
     pub fn new() -> Self {
         Self {
-            max_len: 0,
-            completion_area_first: 0,
+            viewport: Viewport::default(),
+            columns: 1,
+            height: 0,
         }
     }
 
     /// completion_area
     ///
-    ///    start_pos           desc  scroll_bar
-    ///       |                  |      |
-    ///       v                  v      v
-    ///   +-> +----------------+------+--+
-    /// p |   |  completion1   | bin  |  | <---- self.completion_area_first
-    /// a |   +----------------+------+  |
-    /// g |   |  completion2   | bin  |  | <---- pos(example)
-    /// e |   +----------------+------+--| <---+
-    /// | |   |  completion3   | bin  |  |     |
-    /// s |   +----------------+------+  |     |
-    /// i |   |  completion4   | bin  |  |     | blank_n
-    /// z |   +----------------+------+  |     |
-    /// e |   |  completion5   | path |  |     |
-    ///   +-> +----------------+------+--+ <---+
+    /// Candidates with descriptions are listed one to a row:
     ///
+    ///    start_pos          kind  description  scroll_bar
+    ///       |                 |        |        |
+    ///       v                 v        v        v
+    ///   +-> +---------------+------+-----------+--+
+    /// v |   |  completion3  | bin  |           |  | <---- viewport.first
+    /// i |   +---------------+------+-----------+  |
+    /// e |   |  completion4  | bin  |           |##| <---- selected(example)
+    /// w |   +---------------+------+-----------+##| <---- viewport.thumb()
+    /// p |   |  completion5  | dir  |           |  |
+    /// o |   +---------------+------+-----------+  |
+    /// r |   |  $HOME        | var… | /home/me  |  |
+    /// t |   +---------------+------+-----------+--+
+    ///   +-> |  12 more                            |
+    ///       +-------------------------------------+
+    ///
+    /// The description column, then the completion column, are cut to fit
+    /// in `cols`. Candidates without are laid out in a grid across the whole
+    /// width instead, left to right and then down:
+    ///
+    ///   +-------------+-------------+-------------+--+
+    ///   | completion1 | completion2 | completion3 |##|
+    ///   +-------------+-------------+-------------+  |
+    ///   | completion4 | completion5 |             |  |
+    ///   +-------------+-------------+-------------+--+
+    ///
+    /// At most `page_size` rows are shown, and the last says how many
+    /// candidates are left below when they don't all fit.
     pub fn create_completion_area(
         &mut self,
-        completions: &[String],
-        pos: usize,
+        completions: &[Candidate],
+        selected: Option<usize>,
         start_pos: usize,
         page_size: usize,
-        max_len: usize,
+        cols: usize,
     ) -> String {
+        let layout = Layout::fit(completions, start_pos, cols);
+        self.columns = match layout {
+            Layout::Grid { columns, .. } => columns,
+            Layout::List(..) => 1,
+        };
+        let rows = completions.len().div_ceil(self.columns);
 
-        let mut completion_area = String::new();
-        if completions.len() > page_size * 2 - 1 {
-            return completion_area;
+        // The "more" line takes one of the rows.
+        let mut height = page_size.max(2);
+        if rows > height {
+            height -= 1;
         }
-
-        completion_area.push_str(&terminal::move_under_line_first(1));
-
-        let is_needed_scroll = page_size < completions.len();
-
-        let mut scroll_bar_start = 0;
-        let mut scroll_bar_end = completions.len();
-
-        let create_range = |start: usize| start..(start + page_size);
-        let mut completion_area_range = create_range(self.completion_area_first);
-
-        if is_needed_scroll {
-            let is_overed = pos >= self.completion_area_first + page_size;
-            let is_undered = pos < self.completion_area_first;
-            let is_head = pos == self.completion_area_first;
-            // Move a window edge.
-            if is_overed {
-                let exceeded_n = pos - page_size;
-                self.completion_area_first = exceeded_n;
-                completion_area_range = create_range(exceeded_n);
-            } else if is_undered {
-                let fall_n = self.completion_area_first - pos + 1;
-                self.completion_area_first -= fall_n;
-                completion_area_range = create_range(self.completion_area_first);
-            } else if is_head {
-                self.completion_area_first = 0;
-                completion_area_range = create_range(0);
-            }
-
-            let blank_n = completions.len() - page_size + 1;
-            scroll_bar_start = self.completion_area_first;
-            scroll_bar_end = self.completion_area_first + page_size - blank_n;
-        } else {
-            // TODO
-            self.completion_area_first = 0;
-            completion_area_range = 0..completions.len();
+        self.viewport.resize(rows, height);
+        if let Some(i) = selected {
+            self.viewport.scroll_to(i / self.columns);
         }
 
-        for (i, completion) in completions[completion_area_range].iter().enumerate() {
-            completion_area.push_str(&terminal::move_to(start_pos));
+        let mut completion_area = String::new();
+        completion_area.push_str(&terminal::move_under_line_first(1));
+        // A menu narrowed down by typing is shorter than the one before.
+        completion_area.push_str(&terminal::clear_to_screen_end());
 
-            let padded_completion = format!("{:width$}", completion, width = max_len + 1);
+        let thumb = self.viewport.thumb();
+        for (i, row) in self.viewport.visible().enumerate() {
+            let first = row * self.columns;
+            let last = completions.len().min(first + self.columns);
+            let is_selected = |index: usize| selected == Some(index);
 
-            if (pos == 0 && i == 0) || i + self.completion_area_first + 1 == pos {
-                completion_area.push_str(&color::white(padded_completion.as_ref()));
-            } else {
-                completion_area.push_str(&color::light_blue(padded_completion.as_ref()));
+            match layout {
+                Layout::List(start_pos, ref columns) => {
+                    let completion = &completions[first];
+                    let row_color = if is_selected(first) { color::white } else { color::light_blue };
+                    completion_area.push_str(&terminal::move_to(start_pos));
+                    completion_area.push_str(&row_color(&columns.name(completion)));
+                    completion_area.push_str(&color::dark_blue(&columns.kind(completion)));
+                    if columns.description > 0 {
+                        completion_area.push_str(&row_color(&columns.description(completion)));
+                    }
+                }
+                Layout::Grid { columns, width } => {
+                    completion_area.push_str(&terminal::move_to(1));
+                    for (index, completion) in completions.iter().enumerate().take(last).skip(first) {
+                        let name = highlight(&truncate(&completion.display, width - 1), &completion.positions);
+                        let cell = pad(&format!(" {}", name), width);
+                        let cell_color = if is_selected(index) { color::white } else { color::light_blue };
+                        completion_area.push_str(&cell_color(&cell));
+                    }
+                    let blank = (columns - (last - first)) * width;
+                    completion_area.push_str(&color::light_blue(&" ".repeat(blank)));
+                }
             }
 
-            completion_area.push_str(&color::dark_blue(" bin "));
-
-            if scroll_bar_start <= i && i <= scroll_bar_end {
+            if self.viewport.is_scrolled() && thumb.contains(&i) {
                 completion_area.push_str(&color::gray(" "));
             } else {
                 completion_area.push_str(&color::light_blue(" "));
             }
 
-            completion_area.push_str("\n");
+            completion_area.push('\n');
+        }
+
+        self.height = self.viewport.height;
+        if self.viewport.is_scrolled() {
+            // The last row of a grid may be only partly full.
+            let below = completions.len().saturating_sub(self.viewport.visible().end * self.columns);
+            let more = if below > 0 {
+                format!(" {} more", below)
+            } else {
+                format!(" {} more above", self.viewport.first * self.columns)
+            };
+            completion_area.push_str(&terminal::move_to(1));
+            completion_area.push_str(&color::dark_blue(&more));
+            completion_area.push('\n');
+            self.height += 1;
         }
 
         completion_area
     }
 }
+
+/// Highlights the chars of `text` at `positions`.
+fn highlight(text: &str, positions: &[usize]) -> String {
+    text.chars()
+        .enumerate()
+        .map(|(i, c)| if positions.contains(&i) { color::highlight(&c.to_string()) } else { c.to_string() })
+        .collect()
+}
+
+/// How the completion menu lays out the candidates.
+#[derive(Debug, PartialEq)]
+enum Layout {
+    /// One to a row, from a start column, with kinds and descriptions.
+    List(usize, Columns),
+    /// Only the names, `columns` to a row, each `width` wide.
+    Grid { columns: usize, width: usize },
+}
+
+impl Layout {
+    /// A grid when none of the candidates has a description and more than
+    /// one fits in a row, a list otherwise.
+    fn fit(completions: &[Candidate], start_pos: usize, cols: usize) -> Self {
+        if completions.iter().all(|c| c.description.is_none()) {
+            let width = completions.iter().map(|c| str_width(&c.display)).max().unwrap_or(0) + 2;
+            // One column is for the scroll bar.
+            let columns = cols.saturating_sub(1) / width;
+            if columns > 1 {
+                return Layout::Grid { columns, width };
+            }
+        }
+        let (start_pos, columns) = Columns::fit(completions, start_pos, cols);
+        Layout::List(start_pos, columns)
+    }
+}
+
+/// The widths of the columns of the completion menu.
+#[derive(Debug, PartialEq)]
+struct Columns {
+    name: usize,
+    kind: usize,
+    /// No column when 0.
+    description: usize,
+}
+
+impl Columns {
+    /// Sizes the columns for `completions` and cuts them down to fit
+    /// between `start_pos`, 1-based, and `cols`. Moves `start_pos` left if
+    /// even that isn't enough.
+    fn fit(completions: &[Candidate], start_pos: usize, cols: usize) -> (usize, Self) {
+        let widest = |width: &dyn Fn(&Candidate) -> usize| {
+            completions.iter().map(width).max().unwrap_or(0)
+        };
+        let mut columns = Self {
+            name: widest(&|c| str_width(&c.display)) + 1,
+            kind: widest(&|c| c.kind.name().len()) + 2,
+            description: match widest(&|c| c.description.as_ref().map_or(0, |d| str_width(d))) {
+                0 => 0,
+                w => w + 2,
+            },
+        };
+
+        // One more column for the scroll bar.
+        let available = (cols + 1).saturating_sub(start_pos);
+        let mut over = (columns.name + columns.kind + columns.description + 1)
+            .saturating_sub(available);
+        if over > 0 && columns.description > 0 {
+            let cut = over.min(columns.description);
+            // Too narrow to show anything useful.
+            columns.description = match columns.description - cut {
+                w if w < 4 => 0,
+                w => w,
+            };
+            over = (columns.name + columns.kind + columns.description + 1)
+                .saturating_sub(available);
+        }
+        if over > 0 {
+            let cut = over.min(columns.name.saturating_sub(4));
+            columns.name -= cut;
+            over -= cut;
+        }
+        (start_pos.saturating_sub(over).max(1), columns)
+    }
+
+    fn name(&self, candidate: &Candidate) -> String {
+        let name = truncate(&candidate.display, self.name - 1);
+        pad(&highlight(&name, &candidate.positions), self.name)
+    }
+
+    fn kind(&self, candidate: &Candidate) -> String {
+        pad(&format!(" {}", candidate.kind.name()), self.kind)
+    }
+
+    fn description(&self, candidate: &Candidate) -> String {
+        let description = candidate.description.as_ref().map_or(String::new(), |d| {
+            d.replace(|c: char| c.is_control(), " ")
+        });
+        pad(&format!(" {}", truncate(&description, self.description - 2)), self.description)
+    }
+}
+
+#[test]
+fn test_escape() {
+    assert_eq!(escape("my file (1).txt"), "my\\ file\\ \\(1\\).txt");
+    assert_eq!(unescape("my\\ file\\ \\(1\\).txt"), "my file (1).txt");
+    assert_eq!(unescape(&escape("a'b\"c$d\\e")), "a'b\"c$d\\e");
+}
+
+#[cfg(test)]
+use testing::TempDir;
+
+#[cfg(test)]
+fn context(line: &str) -> CompletionContext {
+    let chars: Vec<char> = line.chars().collect();
+    CompletionContext::new(&chars, chars.len())
+}
+
+#[cfg(test)]
+fn insertions(candidates: Vec<Candidate>) -> Vec<String> {
+    candidates.into_iter().map(|c| c.insertion).collect()
+}
+
+#[test]
+fn test_context() {
+    let con = context("ls -l 'my fi");
+    assert_eq!(con.words, vec!["ls", "-l", "'my fi"]);
+    assert_eq!(con.index, 2);
+    assert_eq!(con.command(), Some("ls"));
+    assert_eq!(con.preceding(), &["ls".to_string(), "-l".to_string()]);
+    assert_eq!(con.text, "my fi");
+    assert_eq!(con.quote, Some('\''));
+    assert_eq!(con.requote("my file"), "'my file");
+
+    let con = context("cat a\\ b | gr");
+    assert_eq!(con.words, vec!["gr"]);
+    assert!(con.is_command_position());
+    assert_eq!(con.command(), None);
+
+    let con = context("echo hi 2>ou");
+    assert_eq!(con.words, vec!["echo", "hi", "ou"]);
+    assert!(con.redirect);
+
+    let con = context("cd my\\ d");
+    assert_eq!(con.word, "my\\ d");
+    assert_eq!(con.text, "my d");
+    assert_eq!(con.requote("my dir/"), "my\\ dir/");
+    assert_eq!(context("echo ").words, vec!["echo", ""]);
+
+    assert_eq!(context("echo $HO").dollar, Some(0));
+    assert_eq!(context("echo \"$H\\O").dollar, Some(1));
+    assert_eq!(context("echo \\$HO").dollar, None);
+    assert_eq!(context("echo '$HO").dollar, None);
+    assert_eq!(context("echo a$HO").dollar, None);
+}
+
+#[test]
+fn test_matching() {
+    let positions = |m: Option<Matched>| m.map(|m| m.positions);
+    assert_eq!(positions(Matching::Prefix.matches("ca", "cargo")), Some(vec![0, 1]));
+    assert_eq!(Matching::Prefix.matches("Ca", "cargo"), None);
+    assert_eq!(positions(Matching::CaseInsensitive.matches("Ca", "cargo")), Some(vec![0, 1]));
+    assert_eq!(Matching::CaseInsensitive.matches("cargos", "cargo"), None);
+    assert_eq!(
+        Matching::Substring.matches("go", "cargo"),
+        Some(Matched { score: -3, positions: vec![3, 4] })
+    );
+    assert_eq!(Matching::Substring.matches("og", "cargo"), None);
+
+    assert_eq!(positions(Matching::Fuzzy.matches("crg", "cargo")), Some(vec![0, 2, 3]));
+    assert_eq!(Matching::Fuzzy.matches("cgr", "cargo"), None);
+    // The tightest stretch is taken.
+    assert_eq!(positions(Matching::Fuzzy.matches("ab", "a_xab")), Some(vec![3, 4]));
+    // Smart case.
+    assert!(Matching::Fuzzy.matches("CR", "cargo").is_none());
+    assert!(Matching::Fuzzy.matches("cr", "CaRgo").is_some());
+
+    let score = |typed: &str, name: &str| Matching::Fuzzy.matches(typed, name).unwrap().score;
+    // Runs beat gaps, and word starts beat the middle of words.
+    assert!(score("car", "cargo") > score("car", "cxaxr"));
+    assert!(score("fb", "foo-bar") > score("fb", "fobbar"));
+    assert!(score("gb", "git-blame") > score("gb", "gitblame"));
+}
+
+#[test]
+fn test_dispatcher() {
+    env::set_var("MICAN_TEST_DISPATCHER", "1");
+    let mut dispatcher = Dispatcher::default();
+    assert_eq!(
+        dispatcher.complete(&context("echo $MICAN_TEST_DISP")),
+        vec![
+            Candidate::new("$MICAN_TEST_DISPATCHER", "$MICAN_TEST_DISPATCHER".into(), Kind::Variable)
+                .with_description("1")
+                .with_match(Matched { score: 0, positions: (0..15).collect() }, 1),
+        ]
+    );
+    assert_eq!(
+        insertions(dispatcher.complete(&context("echo \"${MICAN_TEST_DISP"))),
+        vec!["\"${MICAN_TEST_DISPATCHER}"]
+    );
+    dispatcher.variables.shell_vars.borrow_mut().insert("mican_test_dispatcher".into(), "2".into());
+    assert_eq!(insertions(dispatcher.complete(&context("echo $mican_test"))), vec!["$mican_test_dispatcher"]);
+    dispatcher.users.users = || vec![("mican".into(), "/home/mican".into())];
+    assert_eq!(insertions(dispatcher.complete(&context("echo ~mi"))), vec!["~mican/"]);
+    dispatcher.hosts.hosts = || vec![("localhost".into(), Some("127.0.0.1".into()))];
+    assert_eq!(insertions(dispatcher.complete(&context("ssh me@localh"))), vec!["me@localhost"]);
+    let commands = dispatcher.complete(&context("tan"));
+    assert_eq!(commands.first().map(|c| (c.display.as_str(), c.kind)), Some(("tanakh", Kind::Builtin)));
+
+    let dir = TempDir::new("dispatcher");
+    fs::create_dir_all(dir.join("dir")).unwrap();
+    fs::write(dir.join("file"), "").unwrap();
+    let prefix = format!("{}/", dir.display());
+    let mut names = dispatcher.complete(&context(&format!("ls {}", prefix)));
+    names.sort_by(|a, b| a.display.cmp(&b.display));
+    assert_eq!(
+        names,
+        vec![
+            Candidate::new("dir/", format!("{}dir/", prefix), Kind::Dir),
+            Candidate::new("file", format!("{}file", prefix), Kind::File),
+        ]
+    );
+    assert_eq!(
+        insertions(dispatcher.complete(&context(&format!("cd {}", prefix)))),
+        vec![format!("{}dir/", prefix)]
+    );
+}
+
+#[test]
+fn test_hosts() {
+    let hosts = |list: Vec<(String, Option<String>)>| {
+        list.into_iter()
+            .map(|(name, address)| format!("{} {}", name, address.unwrap_or_default()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        hosts(parse_hosts("127.0.0.1 localhost lo # loopback\n# 10.0.0.1 gone\n")),
+        vec!["localhost 127.0.0.1", "lo 127.0.0.1"]
+    );
+    assert_eq!(
+        hosts(parse_ssh_config("Host web web1\n  HostName web.example.com\nHost *.internal db\nHost=git\n")),
+        vec!["web web.example.com", "web1 web.example.com", "db ", "git "]
+    );
+    assert_eq!(
+        hosts(parse_known_hosts(
+            "example.com,93.184.216.34 ssh-ed25519 AAAA\n[alt.example.com]:2222 ssh-rsa AAAA\n\
+             |1|hashed= ssh-rsa AAAA\n@revoked bad.example.com ssh-rsa AAAA\n"
+        )),
+        vec!["example.com ", "93.184.216.34 ", "alt.example.com ", "bad.example.com "]
+    );
+}
+
+#[test]
+fn test_specs() {
+    let mut dispatcher = Dispatcher::default();
+    let specs = dispatcher.specs.clone();
+    specs.borrow_mut().set(
+        "svc",
+        Spec {
+            words: Some("start stop status".into()),
+            function: Some("_svc".into()),
+            ..Spec::default()
+        },
+    );
+    specs.borrow_mut().function_runner = Some(Box::new(|function, context| {
+        vec![format!("{}:{}:{}", function, context.index, context.words.join(","))]
+    }));
+
+    assert_eq!(
+        insertions(dispatcher.complete(&context("svc -q st"))),
+        vec!["start", "stop", "status", "_svc:2:svc,-q,st"]
+    );
+    assert!(specs.borrow().function_runner.is_some());
+    // Redirections still take file names.
+    assert!(!insertions(dispatcher.complete(&context("svc > st"))).contains(&"start".to_string()));
+    assert_eq!(
+        specs.borrow().get("svc").unwrap().to_string(),
+        "-W 'start stop status' -F '_svc'"
+    );
+}
+
+#[test]
+fn test_path() {
+    let dir = TempDir::new("path");
+    fs::create_dir_all(dir.join("sub dir")).unwrap();
+    fs::write(dir.join("file.txt"), "").unwrap();
+    fs::write(dir.join(".hidden"), "").unwrap();
+    ::std::os::unix::fs::symlink(dir.join("sub dir"), dir.join("link")).unwrap();
+
+    let prefix = format!("{}/", dir.display());
+    let mut path = Path::new();
+    let mut complete = |word: &str| insertions(path.complete(&context(&format!("ls {}", word))));
+    let mut names: Vec<String> = complete(&prefix)
+        .into_iter()
+        .map(|c| c[prefix.len()..].to_string())
+        .collect();
+    names.sort();
+    assert_eq!(names, vec!["file.txt", "link/", "sub\\ dir/"]);
+    assert_eq!(complete(&format!("{}.h", prefix)), vec![format!("{}.hidden", prefix)]);
+    assert_eq!(complete(&format!("{}sub\\ ", prefix)), vec![format!("{}sub\\ dir/", prefix)]);
+    assert_eq!(complete(&format!("'{}fi", prefix)), vec![format!("'{}file.txt'", prefix)]);
+}
+
+#[test]
+fn test_columns() {
+    let candidates = vec![
+        Candidate::new("src/", "src/".into(), Kind::Dir),
+        Candidate::new("$HOME", "$HOME".into(), Kind::Variable).with_description("/home/mican"),
+    ];
+    let columns = |start_pos, cols| Columns::fit(&candidates, start_pos, cols);
+    let full = Columns {
+        name: 6,
+        kind: 10,
+        description: 13,
+    };
+    assert_eq!(columns(1, 80), (1, full));
+    // The description goes first, then the names.
+    assert_eq!(
+        columns(11, 35),
+        (11, Columns { name: 6, kind: 10, description: 8 })
+    );
+    assert_eq!(
+        columns(11, 28),
+        (11, Columns { name: 6, kind: 10, description: 0 })
+    );
+    assert_eq!(
+        columns(11, 25),
+        (11, Columns { name: 4, kind: 10, description: 0 })
+    );
+    assert_eq!(columns(11, 20), (6, Columns { name: 4, kind: 10, description: 0 }));
+
+    let narrow = Columns { name: 4, kind: 10, description: 8 };
+    assert_eq!(narrow.name(&candidates[1]), "$H… ");
+    assert_eq!(narrow.description(&candidates[1]), " /home… ");
+    let matched = candidates[1].clone().with_match(Matched { score: 0, positions: vec![0] }, 1);
+    assert_eq!(narrow.name(&matched), format!("${}… ", color::highlight("H")));
+}
+
+#[test]
+fn test_layout() {
+    let candidates: Vec<Candidate> = ["ls", "less", "lsblk"]
+        .iter()
+        .map(|name| Candidate::new(name, name.to_string(), Kind::Executable))
+        .collect();
+    assert_eq!(Layout::fit(&candidates, 5, 80), Layout::Grid { columns: 11, width: 7 });
+    assert!(matches!(Layout::fit(&candidates, 5, 10), Layout::List(..)));
+    let described = vec![candidates[0].clone().with_description("list")];
+    assert!(matches!(Layout::fit(&described, 5, 80), Layout::List(5, _)));
+}
+
+#[test]
+fn test_move_selection() {
+    // 0 1 2
+    // 3 4
+    let moved = |selected, direction| move_selection(selected, 5, 3, direction);
+    assert_eq!(moved(None, Direction::Next), Some(0));
+    assert_eq!(moved(None, Direction::Prev), Some(4));
+    assert_eq!(moved(Some(4), Direction::Next), Some(0));
+    assert_eq!(moved(Some(0), Direction::Prev), Some(4));
+    assert_eq!(moved(Some(1), Direction::Down), Some(4));
+    assert_eq!(moved(Some(4), Direction::Down), Some(1));
+    assert_eq!(moved(Some(2), Direction::Down), Some(2));
+    assert_eq!(moved(Some(4), Direction::Up), Some(1));
+    assert_eq!(moved(Some(0), Direction::Up), Some(3));
+    assert_eq!(moved(Some(2), Direction::Up), Some(2));
+    assert_eq!(move_selection(Some(1), 3, 1, Direction::Down), Some(2));
+    assert_eq!(move_selection(None, 0, 1, Direction::Down), None);
+
+    // 0 1 2 ... 9
+    // ...
+    // 40 41 42
+    let paged = |selected, direction| move_selection(selected, 43, 10, direction);
+    assert_eq!(paged(None, Direction::PageDown(2)), Some(0));
+    assert_eq!(paged(Some(1), Direction::PageDown(2)), Some(21));
+    assert_eq!(paged(Some(21), Direction::PageDown(2)), Some(41));
+    assert_eq!(paged(Some(35), Direction::PageDown(2)), Some(35));
+    assert_eq!(paged(Some(41), Direction::PageUp(3)), Some(11));
+    assert_eq!(paged(Some(15), Direction::PageUp(3)), Some(5));
+    assert_eq!(paged(None, Direction::PageUp(3)), Some(42));
+}
+
+#[test]
+fn test_common_prefix() {
+    assert_eq!(common_prefix(vec!["keymap.rs", "kill_ring.rs"]), "k");
+    assert_eq!(common_prefix(vec!["src/", "src/"]), "src/");
+    assert_eq!(common_prefix(vec!["my\\ file", "my\\ folder"]), "my\\ f");
+    assert_eq!(common_prefix(vec!["a\\ b", "a\\(b"]), "a");
+    assert_eq!(common_prefix(vec!["a\\\\x", "a\\\\y"]), "a\\\\");
+    assert_eq!(common_prefix(Vec::new()), "");
+}
+
+#[test]
+fn test_viewport() {
+    let mut viewport = Viewport::default();
+    viewport.resize(3, 5);
+    assert_eq!(viewport.visible(), 0..3);
+    assert!(!viewport.is_scrolled());
+    assert_eq!(viewport.thumb(), 0..3);
+
+    viewport.resize(100, 10);
+    assert_eq!(viewport.visible(), 0..10);
+    assert_eq!(viewport.below(), 90);
+    assert_eq!(viewport.thumb(), 0..1);
+    viewport.scroll_to(9);
+    assert_eq!(viewport.first, 0);
+    viewport.scroll_to(10);
+    assert_eq!(viewport.visible(), 1..11);
+    viewport.scroll_to(55);
+    assert_eq!(viewport.visible(), 46..56);
+    assert_eq!(viewport.thumb(), 5..6);
+    viewport.scroll_to(99);
+    assert_eq!(viewport.visible(), 90..100);
+    assert_eq!(viewport.below(), 0);
+    assert_eq!(viewport.thumb(), 9..10);
+    viewport.scroll_to(3);
+    assert_eq!(viewport.visible(), 3..13);
+
+    // Half the rows shown, half the bar.
+    viewport.resize(20, 10);
+    assert_eq!(viewport.visible(), 3..13);
+    assert_eq!(viewport.thumb(), 2..7);
+    viewport.scroll_to(19);
+    assert_eq!(viewport.thumb(), 5..10);
+
+    // Fewer rows than before: the viewport moves up to stay full.
+    viewport.resize(12, 10);
+    assert_eq!(viewport.visible(), 2..12);
+    viewport.resize(0, 10);
+    assert_eq!(viewport.visible(), 0..0);
+}
+
+#[test]
+fn test_completion_area() {
+    let candidates: Vec<Candidate> = (0..1000)
+        .map(|i| Candidate::new(&i.to_string(), i.to_string(), Kind::Word).with_description("n"))
+        .collect();
+    let mut area = CompletionArea::new();
+    let menu = area.create_completion_area(&candidates, None, 1, 10, 80);
+    assert_eq!(area.height, 10);
+    assert!(menu.contains(" 991 more"));
+    let menu = area.create_completion_area(&candidates, Some(999), 1, 10, 80);
+    assert_eq!(area.viewport.visible(), 991..1000);
+    assert!(menu.contains(" 991 more above"));
+
+    // A grid whose last row is partly full.
+    let candidates: Vec<Candidate> = (0..300)
+        .map(|i| Candidate::new(&format!("{:04}", i), i.to_string(), Kind::Executable))
+        .collect();
+    let mut area = CompletionArea::new();
+    let menu = area.create_completion_area(&candidates, Some(299), 1, 10, 80);
+    assert!(area.columns > 1 && 300 % area.columns != 0);
+    assert_eq!(area.viewport.below(), 0);
+    assert!(menu.contains(&format!(" {} more above", area.viewport.first * area.columns)));
+}
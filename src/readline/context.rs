@@ -1,17 +1,46 @@
 use readline::editor::Editor;
 use readline::history::History;
 use readline::completer::Completer;
+use readline::buffer::{UNIX_WORD_DELIMITERS, WORD_DELIMITERS};
+use readline::event::Kind;
+use readline::kill_ring::KillRing;
+use readline::vi::Vi;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Mode {
     Normal,
     Completion,
+    /// Waiting for the answer to `Display all N possibilities? (y/n)`.
+    CompletionQuery,
+    /// Typing text in vi mode.
+    ViInsert,
+    /// Vi commands, after Escape.
+    ViCommand,
+}
+
+/// The key bindings `set -o emacs` and `set -o vi` choose between.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EditingMode {
+    Emacs,
+    Vi,
 }
 
 pub struct Context {
     pub editor: Editor,
     pub history: History,
     pub mode: Mode,
+    /// Completes the word at the cursor.
     pub completer: Box<dyn Completer>,
+    pub kill_ring: KillRing,
+    /// The command run by the previous key, so that kills in a row join up
+    /// and `Alt-Y` only follows a yank.
+    pub last_kind: Option<Kind>,
+    /// What separates words for `Alt-D` and the other word commands.
+    pub word_delimiters: String,
+    /// What separates words for `Ctrl-W`.
+    pub unix_word_delimiters: String,
+    pub editing_mode: EditingMode,
+    pub vi: Vi,
 }
 
 impl Context {
@@ -21,6 +50,20 @@ impl Context {
             history: History::new(),
             mode: Mode::Normal,
             completer: comp,
+            kill_ring: KillRing::new(),
+            last_kind: None,
+            word_delimiters: WORD_DELIMITERS.into(),
+            unix_word_delimiters: UNIX_WORD_DELIMITERS.into(),
+            editing_mode: EditingMode::Emacs,
+            vi: Vi::new(),
+        }
+    }
+
+    /// The mode text is typed in: `Normal` for emacs, `ViInsert` for vi.
+    pub fn insert_mode(&self) -> Mode {
+        match self.editing_mode {
+            EditingMode::Emacs => Mode::Normal,
+            EditingMode::Vi => Mode::ViInsert,
         }
     }
 }
@@ -1,14 +1,21 @@
 //! Editing a line and display history and completion.
 
-use std::io::{self, Write};
+use std::env;
+use std::io;
+use std::os::unix::io::RawFd;
 use std::rc::Rc;
 
+use unicode_segmentation::UnicodeSegmentation;
+
 use nix::libc::STDOUT_FILENO;
 
-use readline::terminal;
-use readline::completer::{Completer, CompletionArea};
+use readline::terminal::{self, CursorShape};
+use readline::completer::{self, Candidate, Completer, CompletionArea, CompletionContext, Direction, Matching,
+                          Viewport};
 use readline::history::History;
 use readline::buffer::{Buffer, CursorPosition};
+use readline::undo::{Edit, UndoStack};
+use readline::width::{cluster_width, strip_escapes};
 
 pub struct Editor {
     /// Current cursor posion.
@@ -19,47 +26,128 @@ pub struct Editor {
 
     /// Writting `stdout` when function `write` is called.
     buffer_for_stdout: String,
+    /// Where the line is drawn, standard output unless `set_output` says
+    /// otherwise.
+    output: RawFd,
 
     /// Terminal's size infomation.
     pub win_size: terminal::Winsize,
 
+    /// Screen row of the terminal's cursor, counted from the prompt's first row.
+    cursor_row: usize,
+    /// Last screen row taken up by the prompt and the buffer.
+    last_row: usize,
+
+    undo_stack: UndoStack,
+
     // completer: CompleterBin,
-    completions: Rc<Vec<String>>,
-    completer_index: usize,
+    completions: Rc<Vec<Candidate>>,
+    /// The candidate picked in the menu.
+    completion_selected: Option<usize>,
     completer_is_after: bool,
+    /// Where the word being completed starts in the buffer.
+    completion_start: usize,
     completion_area: CompletionArea,
+    /// How many completions make it ask before showing them.
+    completion_query_items: usize,
+    completion_matching: Matching,
 
     pub history: History,
 }
 
+/// What `Alt-U`, `Alt-L` and `Alt-C` turn a word into.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Case {
+    Upper,
+    Lower,
+    Capitalize,
+}
+
 pub trait Complete {
-    fn complete(&mut self, completer: &mut dyn Completer);
+    /// Finds the ways `completer` has to complete the word before the
+    /// cursor, and returns how many there are.
+    fn complete(&mut self, completer: &mut dyn Completer) -> usize;
 
-    fn completion_disply(&mut self, completer: &mut dyn Completer);
+    /// Puts in what all the candidates start with, or the one candidate and
+    /// a space after it.
+    fn completion_insert_common(&mut self);
 
-    fn completion_clear(&mut self);
+    /// Whether the last completion left more than one candidate.
+    fn completion_is_ambiguous(&self) -> bool;
+
+    /// Whether there are so many candidates that showing them should be
+    /// asked first.
+    fn completion_needs_query(&self) -> bool;
+
+    /// Asks whether to show all the candidates, under the line.
+    fn completion_query(&mut self);
 
-    fn completion_next(&mut self, completer: &mut dyn Completer);
+    /// Shows the candidates, none selected yet.
+    fn completion_menu(&mut self);
 
-    fn completion_prev(&mut self, completer: &mut dyn Completer);
+    fn completion_disply(&mut self);
+
+    fn completion_clear(&mut self);
+
+    /// Selects another candidate and puts it in place of the word.
+    fn completion_move(&mut self, direction: Direction);
 
     fn swap_completion(&mut self, index: usize);
 }
 
 impl Complete for Editor {
-    fn complete(&mut self, completer: &mut dyn Completer) {
-        if self.completer_is_after {
-            return;
-        }
-        self.completer_is_after = true;
+    fn complete(&mut self, completer: &mut dyn Completer) -> usize {
+        let mut context = CompletionContext::new(self.buffer.as_slice(), self.pos);
+        context.matching = self.completion_matching;
+        self.completion_start = self.pos - context.word.chars().count();
+        let mut complitions = completer.complete(&context);
+        complitions.sort_by(|a, b| {
+            b.score.cmp(&a.score).then_with(|| (&a.display, &a.insertion).cmp(&(&b.display, &b.insertion)))
+        });
+        complitions.dedup_by(|a, b| a.insertion == b.insertion);
+        self.completions = Rc::new(complitions);
+        self.completions.len()
+    }
 
-        let (words, pos) = self.buffer.get_words_and_pos(self.pos);
-        if let CursorPosition::OnWordRightEdge(i) = pos {
-            let range = words[i];
-            let mut complitions = completer.complete(&self.buffer.as_str()[range.0..range.1]);
-            complitions.sort();
-            self.completions = Rc::new(complitions);
+    fn completion_insert_common(&mut self) {
+        let completions = self.completions.clone();
+        let text = match completions.len() {
+            0 => return,
+            1 if completions[0].insertion.ends_with('/') => completions[0].insertion.clone(),
+            1 => format!("{} ", completions[0].insertion),
+            _ => completer::common_prefix(completions.iter().map(|c| c.insertion.as_str())),
         };
+        // Never takes away from what was typed.
+        if completions.len() == 1 || text.chars().count() > self.pos - self.completion_start {
+            let len = self.pos - self.completion_start;
+            self.delete(len);
+            self.put(&text);
+        }
+    }
+
+    fn completion_is_ambiguous(&self) -> bool {
+        self.completions.len() > 1
+    }
+
+    fn completion_needs_query(&self) -> bool {
+        self.completions.len() >= self.completion_query_items
+    }
+
+    fn completion_query(&mut self) {
+        let question = format!(
+            "{}{}Display all {} possibilities? (y/n)",
+            terminal::move_under_line_first(1),
+            terminal::clear_to_screen_end(),
+            self.completions.len()
+        );
+        self.write_sub(&question, 1);
+    }
+
+    fn completion_menu(&mut self) {
+        self.completer_is_after = true;
+        self.completion_selected = None;
+        self.completion_area.viewport = Viewport::default();
+        self.completion_disply();
     }
 
     fn completion_clear(&mut self) {
@@ -67,87 +155,55 @@ impl Complete for Editor {
             return;
         }
 
-        if self.completer_is_after {
-            self.buffer_for_stdout.push_str(
-                &terminal::move_under_line_first(1),
-            );
-            self.buffer_for_stdout.push_str(
-                &terminal::clear_to_screen_end(),
-            );
-            self.buffer_for_stdout.push_str(&terminal::move_up(1));
-            self.come_back();
-        }
-
-        self.completer_index = 0;
+        self.clear_below();
+        self.completion_selected = None;
         self.completer_is_after = false;
     }
 
-    fn completion_disply(&mut self, completer: &mut dyn Completer) {
-        let page_size = 10;
-        let height = if self.completions.len() < page_size {
-            self.completions.len() + 1
-        } else {
-            page_size + 1
-        };
+    fn completion_disply(&mut self) {
+        if !self.completer_is_after {
+            return;
+        }
 
-        let (words, pos) = self.buffer.get_words_and_pos(self.pos);
-        let completion_start_pos = self.prompt.len() + 1 +
-            match pos {
-                CursorPosition::OnWordRightEdge(i) => words[i].0,
-                _ => 0,
-            };
+        // Leave room for the line being edited.
+        let page_size = 10.min((self.win_size.ws_row as usize).saturating_sub(2).max(1));
+        let completion_start_pos = 1 + self.locate(self.completion_start).1;
 
         let completions = self.completion_area.create_completion_area(
             &self.completions,
-            self.completer_index,
+            self.completion_selected,
             completion_start_pos,
             page_size,
-            completer.max_len(),
+            self.win_size.ws_col as usize,
         );
-        self.write_sub(&completions, height);
-    }
-
-    fn completion_next(&mut self, completer: &mut dyn Completer) {
-        if !self.completer_is_after {
-            return;
+        if !completions.is_empty() {
+            let height = self.completion_area.height + 1;
+            self.write_sub(&completions, height);
         }
-
-        self.completer_index += 1;
-        let index = if self.completer_index > self.completions.len() {
-            self.completer_index = 1;
-            0
-        } else {
-            self.completer_index - 1
-        };
-        self.swap_completion(index);
-        self.completion_disply(completer);
     }
 
-    fn completion_prev(&mut self, completer: &mut dyn Completer) {
+    fn completion_move(&mut self, direction: Direction) {
         if !self.completer_is_after {
             return;
         }
 
-        let index = if self.completer_index <= 1 {
-            self.completer_index = self.completions.len();
-            self.completer_index
-        } else {
-            self.completer_index -= 1;
-            self.completer_index
-        };
-
-        self.swap_completion(index);
-        self.completion_disply(completer);
+        self.completion_selected = completer::move_selection(
+            self.completion_selected,
+            self.completions.len(),
+            self.completion_area.columns,
+            direction,
+        );
+        if let Some(index) = self.completion_selected {
+            self.swap_completion(index);
+        }
+        self.completion_disply();
     }
 
     fn swap_completion(&mut self, index: usize) {
         if let Some(cmd) = self.completions.clone().get(index) {
-            let (words, pos) = self.buffer.get_words_and_pos(self.pos);
-            if let CursorPosition::OnWordRightEdge(i) = pos {
-                let range = words[i];
-                self.delete(range.1 - range.0);
-                self.put(cmd);
-            }
+            let len = self.pos - self.completion_start;
+            self.delete(len);
+            self.put(&cmd.insertion);
         }
     }
 }
@@ -159,42 +215,104 @@ impl Editor {
             prompt: prompt_,
             buffer: Buffer::new(),
             buffer_for_stdout: String::new(),
+            output: STDOUT_FILENO,
 
-            win_size: terminal::get_winsize(STDOUT_FILENO).unwrap(),
+            win_size: terminal::get_winsize(STDOUT_FILENO).unwrap_or_default(),
+            cursor_row: 0,
+            last_row: 0,
+
+            undo_stack: UndoStack::new(),
 
             // completer: CompleterBin::new(),
             completions: Rc::new(Vec::new()),
-            completer_index: 0,
+            completion_selected: None,
             completer_is_after: false,
+            completion_start: 0,
             completion_area: CompletionArea::new(),
+            completion_query_items: 100,
+            completion_matching: Matching::default(),
 
             history: History::new(),
         }
     }
 
+    /// Clears what is drawn under the line, like the completion menu.
+    pub fn clear_below(&mut self) {
+        self.move_to_last_row();
+        self.buffer_for_stdout.push_str(
+            &terminal::move_under_line_first(1),
+        );
+        self.buffer_for_stdout.push_str(
+            &terminal::clear_to_screen_end(),
+        );
+        self.buffer_for_stdout.push_str(&terminal::move_up(1));
+        self.come_back();
+    }
+
+    /// Asks before showing this many completions or more.
+    pub fn set_completion_query_items(&mut self, items: usize) {
+        self.completion_query_items = items;
+    }
+
+    /// How candidates are picked by the word being completed.
+    pub fn set_completion_matching(&mut self, matching: Matching) {
+        self.completion_matching = matching;
+    }
+
+    /// How many rows of the completion menu a page is.
+    pub fn completion_page(&self) -> usize {
+        self.completion_area.viewport.height.max(1)
+    }
+
+    /// Draws on the terminal `fd` instead.
+    pub fn set_output(&mut self, fd: RawFd) {
+        self.output = fd;
+        self.update_win_size();
+    }
+
+    /// Queries the terminal again, after it was resized.
+    pub fn update_win_size(&mut self) {
+        if let Ok(win_size) = terminal::get_winsize(self.output) {
+            self.win_size = win_size;
+        }
+    }
+
+    /// Exports the terminal size as `COLUMNS` and `LINES`.
+    pub fn export_win_size(&self) {
+        env::set_var("COLUMNS", self.win_size.ws_col.to_string());
+        env::set_var("LINES", self.win_size.ws_row.to_string());
+    }
+
+    /// Redraws the prompt and the line from scratch, keeping the cursor
+    /// where it was.
+    pub fn redraw(&mut self) {
+        let pos = self.pos;
+        self.move_to_row(0);
+        self.buffer_for_stdout.push('\r');
+        self.clear_to_screen_end();
+        self.write_prompt();
+        self.write_line();
+        self.move_to(pos);
+    }
+
     pub fn reset(&mut self) {
         self.pos = 0;
         self.buffer = Buffer::new();
+        self.undo_stack.clear();
     }
 
     pub fn line(&self) -> String {
         self.buffer.as_str()
     }
 
+    /// Whether the line ends inside a quote, so Enter should continue it.
+    pub fn open_quote(&self) -> Option<char> {
+        self.buffer.open_quote()
+    }
+
     pub fn put(&mut self, s: &str) {
-        if self.is_last() {
-            self.buffer.insert_str(self.pos, &s);
-            self.write_str(&s);
-        } else {
-            self.buffer.insert_str(self.pos, &s);
-            let line = self.buffer.clone();
-            let old_pos = self.pos;
-            self.clear_to_screen_end();
-            self.buffer_for_stdout.push_str(
-                &line.as_str().get(old_pos..).unwrap(),
-            );
-            self.move_to(old_pos + s.len());
-        }
+        let pos = self.pos;
+        self.edit(pos, pos, s);
     }
 
     pub fn delete(&mut self, n: usize) {
@@ -202,42 +320,246 @@ impl Editor {
             return;
         }
 
-        let delete_range = self.pos - n..self.pos;
-        if let Some(first_tab_index) = self.buffer.as_str()[delete_range].find('\t') {
-            if let Some(last_tab_index) = self.buffer.as_str().rfind('\t') {
-                if first_tab_index == last_tab_index {
-                    self.buffer_for_stdout.push_str(&terminal::move_left(5));
-                } else {
-                    self.buffer_for_stdout.push_str(&terminal::move_left(7));
+        let mut start = self.pos;
+        for _ in 0..n {
+            start = self.buffer.prev_boundary(start);
+        }
+        let end = self.pos;
+        self.kill(start, end);
+    }
+
+    /// Removes the text between `start` and `end`, leaving the cursor at
+    /// `start`, and returns it.
+    pub fn kill(&mut self, start: usize, end: usize) -> String {
+        self.edit(start, end, "")
+    }
+
+    /// Deletes the character under the cursor.
+    pub fn delete_char(&mut self) {
+        let end = self.buffer.next_boundary(self.pos);
+        if end > self.pos {
+            let pos = self.pos;
+            self.kill(pos, end);
+        }
+    }
+
+    /// Swaps the characters before and under the cursor, or the last two
+    /// when at the end of the line, and moves past them.
+    pub fn transpose_chars(&mut self) {
+        let mut pos = self.pos;
+        if pos == self.buffer.len() {
+            pos = self.buffer.prev_boundary(pos);
+        }
+        if pos == 0 {
+            return;
+        }
+
+        let start = self.buffer.prev_boundary(pos);
+        let end = self.buffer.next_boundary(pos);
+        let swapped = self.text(pos, end) + &self.text(start, pos);
+        self.edit(start, end, &swapped);
+    }
+
+    /// Swaps the word before the cursor with the one under or after it, or
+    /// the last two when there is none after, and moves past them.
+    pub fn transpose_words(&mut self, delimiters: &str) {
+        let words = self.buffer.get_words_by(delimiters);
+        let (left, right) = match CursorPosition::get(self.pos, &words) {
+            CursorPosition::InWord(i) |
+            CursorPosition::OnWordLeftEdge(i) if i > 0 => (i - 1, i),
+            CursorPosition::OnWordRightEdge(i) if i + 1 < words.len() => (i, i + 1),
+            CursorPosition::OnWordRightEdge(i) if i > 0 => (i - 1, i),
+            CursorPosition::InSpace(Some(i), Some(j)) => (i, j),
+            CursorPosition::InSpace(Some(i), None) if i > 0 => (i - 1, i),
+            _ => return,
+        };
+
+        let (left, right) = (words[left], words[right]);
+        let swapped = self.text(right.0, right.1) + &self.text(left.1, right.0) +
+            &self.text(left.0, left.1);
+        self.edit(left.0, right.1, &swapped);
+    }
+
+    /// Changes the case of the text from the cursor to the end of the word
+    /// and moves past it.
+    pub fn change_case(&mut self, delimiters: &str, case: Case) {
+        let start = self.pos;
+        let end = self.word_end(delimiters);
+        let text = self.text(start, end);
+
+        let changed = match case {
+            Case::Upper => text.to_uppercase(),
+            Case::Lower => text.to_lowercase(),
+            Case::Capitalize => {
+                let mut changed = String::new();
+                let mut in_word = false;
+                for c in text.chars() {
+                    if delimiters.contains(c) {
+                        changed.push(c);
+                    } else if in_word {
+                        changed.extend(c.to_lowercase());
+                    } else {
+                        changed.extend(c.to_uppercase());
+                        in_word = true;
+                    }
                 }
+                changed
             }
+        };
+        self.edit(start, end, &changed);
+    }
+
+    /// The text between `start` and `end`.
+    pub fn text(&self, start: usize, end: usize) -> String {
+        self.buffer.as_slice()[start..end].iter().collect()
+    }
+
+    /// Replaces the text between `start` and `end` with `s`, leaving the
+    /// cursor after it, and returns the text replaced. Every change to the
+    /// buffer goes through here so that it can be undone.
+    fn edit(&mut self, start: usize, end: usize, s: &str) -> String {
+        let removed = self.apply(start, end, s);
+        self.undo_stack.record(Edit {
+            start,
+            removed: removed.clone(),
+            inserted: s.into(),
+        });
+        removed
+    }
+
+    /// Changes the buffer and the screen without recording anything.
+    fn apply(&mut self, start: usize, end: usize, s: &str) -> String {
+        // Typing at the end only needs the new text drawn.
+        let at_end = start == end && self.pos == start && start == self.buffer.len();
+
+        let removed = self.buffer.remove(start, end).into_iter().collect();
+        self.buffer.insert_str(start, s);
+
+        if !at_end {
+            self.move_to(start);
+            self.clear_to_screen_end();
         }
+        self.render_from(start);
+        if !at_end {
+            self.move_to(start + s.chars().count());
+        }
+        removed
+    }
 
-        self.buffer.remove(self.pos - n, self.pos);
+    /// Ends the undo unit being recorded. Edits are grouped until the next
+    /// call.
+    pub fn close_undo_unit(&mut self) {
+        self.undo_stack.close();
+    }
 
-        self.move_left(n);
-        self.clear_to_screen_end();
-        if !self.is_last() {
-            let line = self.buffer.clone();
-            let pos = self.pos;
-            self.buffer_for_stdout.push_str(
-                &line.as_str().get(pos..).unwrap(),
-            );
-            self.move_to(pos);
+    /// Reverts the last unit of edits. Returns false when there is none.
+    pub fn undo(&mut self) -> bool {
+        let unit = match self.undo_stack.undo() {
+            Some(unit) => unit,
+            None => return false,
+        };
+        for e in unit.iter().rev() {
+            let end = e.start + e.inserted.chars().count();
+            self.apply(e.start, end, &e.removed);
         }
+        true
+    }
+
+    /// Applies again the last unit undone. Returns false when there is none.
+    pub fn redo(&mut self) -> bool {
+        let unit = match self.undo_stack.redo() {
+            Some(unit) => unit,
+            None => return false,
+        };
+        for e in &unit {
+            let end = e.start + e.removed.chars().count();
+            self.apply(e.start, end, &e.inserted);
+        }
+        true
+    }
+
+    pub fn chars(&self) -> &[char] {
+        self.buffer.as_slice()
+    }
+
+    /// The start of the grapheme cluster before `pos`.
+    pub fn prev_boundary(&self, pos: usize) -> usize {
+        self.buffer.prev_boundary(pos)
+    }
+
+    /// The end of the grapheme cluster after `pos`.
+    pub fn next_boundary(&self, pos: usize) -> usize {
+        self.buffer.next_boundary(pos)
     }
 
+    /// Shows `s` in place of the prompt and the line, like vi's `/` does
+    /// while the pattern is typed. `redraw` brings the line back.
+    pub fn show_status(&mut self, s: &str) {
+        self.move_to_row(0);
+        self.buffer_for_stdout.push('\r');
+        self.clear_to_screen_end();
+        self.buffer_for_stdout.push_str(s);
+        self.cursor_row = 0;
+        self.last_row = 0;
+    }
+
+    pub fn set_cursor_shape(&mut self, shape: CursorShape) {
+        self.buffer_for_stdout.push_str(&terminal::cursor_shape(shape));
+    }
+
+    pub fn set_bracketed_paste(&mut self, on: bool) {
+        self.buffer_for_stdout.push_str(&terminal::bracketed_paste(on));
+    }
+
+    /// Where the cursor is, counted in chars.
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    /// The start of the word before the cursor, or of the one it is in.
+    pub fn word_start(&self, delimiters: &str) -> usize {
+        self.buffer
+            .get_words_by(delimiters)
+            .into_iter()
+            .rev()
+            .map(|(start, _)| start)
+            .find(|&start| start < self.pos)
+            .unwrap_or(0)
+    }
+
+    /// The end of the word after the cursor, or of the one it is in.
+    pub fn word_end(&self, delimiters: &str) -> usize {
+        self.buffer
+            .get_words_by(delimiters)
+            .into_iter()
+            .map(|(_, end)| end)
+            .find(|&end| end > self.pos)
+            .unwrap_or_else(|| self.buffer.len())
+    }
+
+    /// The start of the line the cursor is on, in a multi-line buffer.
+    pub fn line_start(&self) -> usize {
+        line_start(self.buffer.as_slice(), self.pos)
+    }
+
+    /// The end of the line the cursor is on, in a multi-line buffer.
+    pub fn line_end(&self) -> usize {
+        let chars = self.buffer.as_slice();
+        chars[self.pos..]
+            .iter()
+            .position(|&c| c == '\n')
+            .map_or(chars.len(), |i| self.pos + i)
+    }
+
+    /// Writes the whole buffer after the prompt and leaves the cursor at its end.
     pub fn write_line(&mut self) {
-        self.buffer_for_stdout.push_str(&self.buffer.as_str());
+        self.render_from(0);
     }
 
     pub fn write_prompt(&mut self) {
         self.buffer_for_stdout.push_str(&self.prompt);
-    }
-
-    pub fn write_str(&mut self, s: &str) {
-        self.pos += s.len();
-        self.buffer_for_stdout.push_str(s)
+        self.cursor_row = self.locate(0).0;
+        self.last_row = self.cursor_row;
     }
 
     pub fn come_back(&mut self) {
@@ -250,21 +572,24 @@ impl Editor {
     }
 
     pub fn replace(&mut self, s: &str) {
-        self.clear_line().unwrap();
-        self.buffer = Buffer::from(s);
-        self.write_line();
+        let len = self.buffer.len();
+        self.edit(0, len, s);
     }
 
     pub fn new_line(&mut self) {
-        self.buffer_for_stdout.push_str("\n");
+        self.move_to_last_row();
+        self.buffer_for_stdout.push('\n');
+        self.cursor_row = 0;
+        self.last_row = 0;
     }
 
     pub fn clear_line(&mut self) -> io::Result<()> {
-        self.buffer = Buffer::new();
         let old_pos = self.pos;
         self.move_to_first();
+        self.buffer = Buffer::new();
         self.clear_to_screen_end();
         self.pos = old_pos;
+        self.last_row = self.cursor_row;
         Ok(())
     }
 
@@ -275,6 +600,8 @@ impl Editor {
             "\x1b[2J\x1b[1;1H{}",
             self.prompt
         ));
+        self.cursor_row = self.locate(0).0;
+        self.last_row = self.cursor_row;
     }
 
     pub fn clear_to_screen_end(&mut self) {
@@ -287,16 +614,22 @@ impl Editor {
         if self.is_start() {
             return;
         }
-        self.pos -= n;
-        self.buffer_for_stdout.push_str(&terminal::move_left(n));
+        let mut pos = self.pos;
+        for _ in 0..n {
+            pos = self.buffer.prev_boundary(pos);
+        }
+        self.move_to(pos);
     }
 
     pub fn move_right(&mut self, n: usize) {
         if self.is_last() {
             return;
         }
-        self.pos += n;
-        self.buffer_for_stdout.push_str(&terminal::move_right(n));
+        let mut pos = self.pos;
+        for _ in 0..n {
+            pos = self.buffer.next_boundary(pos);
+        }
+        self.move_to(pos);
     }
 
     pub fn move_to_first(&mut self) {
@@ -308,11 +641,120 @@ impl Editor {
         self.move_to(n);
     }
 
-    fn move_to(&mut self, n: usize) {
+    /// Moves to the same column of the previous line of a multi-line
+    /// buffer. Returns false when already on the first line.
+    pub fn move_line_up(&mut self) -> bool {
+        let chars = self.buffer.as_slice();
+        let start = line_start(chars, self.pos);
+        if start == 0 {
+            return false;
+        }
+        let prev_start = line_start(chars, start - 1);
+        let pos = (prev_start + self.pos - start).min(start - 1);
+        let pos = self.snap(pos);
+        self.move_to(pos);
+        true
+    }
+
+    /// Moves to the same column of the next line of a multi-line buffer.
+    /// Returns false when already on the last line.
+    pub fn move_line_down(&mut self) -> bool {
+        let chars = self.buffer.as_slice();
+        let next_start = match chars[self.pos..].iter().position(|&c| c == '\n') {
+            Some(i) => self.pos + i + 1,
+            None => return false,
+        };
+        let next_end = chars[next_start..]
+            .iter()
+            .position(|&c| c == '\n')
+            .map_or(chars.len(), |i| next_start + i);
+        let pos = (next_start + self.pos - line_start(chars, self.pos)).min(next_end);
+        let pos = self.snap(pos);
+        self.move_to(pos);
+        true
+    }
+
+    /// Moves the cursor to the `n`th char of the buffer.
+    pub fn move_to(&mut self, n: usize) {
         self.pos = n;
-        self.buffer_for_stdout.push_str(&terminal::move_to(
-            self.prompt.len() + n + 1,
-        ));
+        let (row, col) = self.locate(n);
+        self.move_to_row(row);
+        self.buffer_for_stdout.push_str(&terminal::move_to(col + 1));
+    }
+
+    fn move_to_row(&mut self, row: usize) {
+        if row < self.cursor_row {
+            self.buffer_for_stdout.push_str(&terminal::move_up(self.cursor_row - row));
+        } else if row > self.cursor_row {
+            self.buffer_for_stdout.push_str(&terminal::move_down(row - self.cursor_row));
+        }
+        self.cursor_row = row;
+    }
+
+    fn move_to_last_row(&mut self) {
+        let row = self.last_row;
+        self.move_to_row(row);
+    }
+
+    /// Moves `pos` back to the start of the grapheme cluster it is in.
+    fn snap(&self, pos: usize) -> usize {
+        self.buffer
+            .graphemes()
+            .into_iter()
+            .map(|(start, _)| start)
+            .take_while(|&start| start <= pos)
+            .last()
+            .unwrap_or(0)
+    }
+
+    /// Where the character at `pos` is drawn, as (row, col) counted from
+    /// the start of the prompt. Rows wrap at the terminal's width and
+    /// escape sequences in the prompt take no room.
+    pub fn locate(&self, pos: usize) -> (usize, usize) {
+        let cols = self.cols();
+        let prompt = strip_escapes(&self.prompt);
+        let at = prompt.graphemes(true).fold((0, 0), |at, g| advance(at, g, cols));
+
+        let line: String = self.buffer.as_slice()[..pos].iter().collect();
+        line.graphemes(true).fold(at, |at, g| advance(at, g, cols))
+    }
+
+    fn cols(&self) -> usize {
+        (self.win_size.ws_col as usize).max(1)
+    }
+
+    /// Writes the buffer from `start` to its end, assuming the terminal's
+    /// cursor is at `start`. Rows are broken explicitly so the terminal's
+    /// cursor always ends up where `locate` says.
+    fn render_from(&mut self, start: usize) {
+        let cols = self.cols();
+        let mut at = self.locate(start);
+        let rest: String = self.buffer.as_slice()[start..].iter().collect();
+        for g in rest.graphemes(true) {
+            let next = advance(at, g, cols);
+            if g == "\n" {
+                self.buffer_for_stdout.push_str("\r\n");
+            } else {
+                if next.0 > at.0 && next.1 != 0 {
+                    // Doesn't fit in what is left of the row.
+                    self.buffer_for_stdout.push_str("\r\n");
+                }
+                if g == "\t" {
+                    let width = if next.1 == 0 { cols - at.1 } else { next.1 - at.1 };
+                    self.buffer_for_stdout.push_str(&" ".repeat(width));
+                } else {
+                    self.buffer_for_stdout.push_str(g);
+                }
+                if next.1 == 0 {
+                    self.buffer_for_stdout.push_str("\r\n");
+                }
+            }
+            at = next;
+        }
+
+        self.pos = self.buffer.len();
+        self.cursor_row = at.0;
+        self.last_row = at.0;
     }
 
     fn is_start(&self) -> bool {
@@ -330,6 +772,7 @@ impl Editor {
     }
 
     pub fn write_sub(&mut self, s: &str, height: usize) {
+        self.move_to_last_row();
         self.buffer_for_stdout.push_str(s);
         self.buffer_for_stdout.push_str(&terminal::move_up(height));
         let pos = self.pos;
@@ -337,12 +780,42 @@ impl Editor {
     }
 
     fn write(&self, s: &str) -> io::Result<()> {
-        let stdout = io::stdout();
-        let mut lock = stdout.lock();
+        terminal::write_all(self.output, s.as_bytes())
+    }
+}
+
+const TAB_WIDTH: usize = 8;
 
-        write!(lock, "{}", s)?;
-        lock.flush()
+/// Where the cursor goes after drawing the grapheme cluster `g` at `at` on
+/// a screen `cols` wide.
+fn advance((row, col): (usize, usize), g: &str, cols: usize) -> (usize, usize) {
+    if g == "\n" {
+        return (row + 1, 0);
     }
+
+    // A tab is drawn as spaces up to the next tab stop.
+    let width = if g == "\t" {
+        (TAB_WIDTH - col % TAB_WIDTH).min(cols - col)
+    } else {
+        cluster_width(g)
+    };
+    let (row, col) = if col + width > cols {
+        (row + 1, 0)
+    } else {
+        (row, col)
+    };
+    if col + width >= cols {
+        (row + 1, 0)
+    } else {
+        (row, col + width)
+    }
+}
+
+fn line_start(chars: &[char], pos: usize) -> usize {
+    chars[..pos].iter().rposition(|&c| c == '\n').map_or(
+        0,
+        |i| i + 1,
+    )
 }
 #[cfg(test)]
 mod test {
@@ -433,6 +906,185 @@ mod test {
         assert_eq!(ed.pos, 0);
     }
 
+    #[test]
+    fn test_locate_wrapped() {
+        let mut ed = Editor::new("> ".into());
+        ed.win_size.ws_col = 10;
+        ed.put("0123456789abc");
+        assert_eq!(ed.locate(0), (0, 2));
+        assert_eq!(ed.locate(7), (0, 9));
+        assert_eq!(ed.locate(8), (1, 0));
+        assert_eq!(ed.locate(13), (1, 5));
+        assert_eq!(ed.cursor_row, 1);
+
+        ed.move_to_first();
+        assert_eq!(ed.cursor_row, 0);
+        ed.move_right(9);
+        assert_eq!(ed.cursor_row, 1);
+    }
+
+    #[test]
+    fn test_multi_line() {
+        let mut ed = Editor::new("> ".into());
+        ed.put("echo 'a\nbc\nd'");
+        assert_eq!(ed.locate(ed.line().chars().count()), (2, 2));
+        assert_eq!(ed.open_quote(), None);
+
+        assert!(ed.move_line_up());
+        assert_eq!(ed.pos, 10);
+        assert!(ed.move_line_up());
+        assert_eq!(ed.pos, 2);
+        assert!(!ed.move_line_up());
+        assert!(ed.move_line_down());
+        assert_eq!(ed.pos, 10);
+
+        ed.move_to_end();
+        ed.delete(1);
+        assert_eq!(ed.open_quote(), Some('\''));
+    }
+
+    #[test]
+    fn test_wide_chars() {
+        let mut ed = Editor::new("\x1b[32m🍊\x1b[m ".into());
+        ed.win_size.ws_col = 10;
+        ed.put("日本語");
+        assert_eq!(ed.locate(0), (0, 3));
+        assert_eq!(ed.locate(3), (0, 9));
+
+        // The next wide char doesn't fit in the last column and wraps.
+        ed.put("x語");
+        assert_eq!(ed.locate(4), (1, 0));
+        assert_eq!(ed.locate(5), (1, 2));
+
+        ed.move_left(2);
+        assert_eq!(ed.pos, 3);
+        ed.move_right(1);
+        assert_eq!(ed.pos, 4);
+    }
+
+    #[test]
+    fn test_tabs() {
+        let mut ed = Editor::new("> ".into());
+        ed.win_size.ws_col = 12;
+        ed.put("a\tb\t");
+        assert_eq!(ed.locate(2), (0, 8));
+        // The second tab stops at the edge and wraps.
+        assert_eq!(ed.locate(4), (1, 0));
+        assert!(ed.buffer_for_stdout.ends_with("a     b   \r\n"));
+    }
+
+    #[test]
+    fn test_grapheme_clusters() {
+        let mut ed = Editor::new("> ".into());
+        // "e" with a combining accent and a ZWJ family emoji.
+        ed.put("e\u{301}\u{1f468}\u{200d}\u{1f469}");
+        assert_eq!(ed.locate(ed.pos), (0, 5));
+
+        ed.move_left(1);
+        assert_eq!(ed.pos, 2);
+        assert_eq!(ed.locate(ed.pos), (0, 3));
+
+        ed.move_to_end();
+        ed.delete(1);
+        assert_eq!(ed.line(), "e\u{301}");
+        ed.delete(1);
+        assert_eq!(ed.line(), "");
+    }
+
+    #[test]
+    fn test_kill() {
+        use readline::buffer::{UNIX_WORD_DELIMITERS, WORD_DELIMITERS};
+
+        let mut ed = Editor::new("> ".into());
+        ed.put("git commit -m 'fix bug'");
+        ed.move_left(5);
+        assert_eq!(ed.word_start(UNIX_WORD_DELIMITERS), 14);
+        assert_eq!(ed.word_start(WORD_DELIMITERS), 15);
+        assert_eq!(ed.word_end(WORD_DELIMITERS), 22);
+
+        assert_eq!(ed.kill(ed.line_start(), ed.pos()), "git commit -m 'fix");
+        assert_eq!(ed.line(), " bug'");
+        assert_eq!(ed.pos(), 0);
+        assert_eq!(ed.line_end(), 5);
+    }
+
+    #[test]
+    fn test_transpose() {
+        use readline::buffer::WORD_DELIMITERS;
+
+        let mut ed = Editor::new("> ".into());
+        ed.put("sl -la");
+        ed.move_to_first();
+        ed.move_right(1);
+        ed.transpose_chars();
+        assert_eq!(ed.line(), "ls -la");
+        assert_eq!(ed.pos(), 2);
+
+        ed.move_to_end();
+        ed.transpose_chars();
+        assert_eq!(ed.line(), "ls -al");
+
+        ed.replace("cat bar foo");
+        ed.transpose_words(WORD_DELIMITERS);
+        assert_eq!(ed.line(), "cat foo bar");
+        assert_eq!(ed.pos(), 11);
+
+        ed.move_to_first();
+        ed.move_right(5);
+        ed.transpose_words(WORD_DELIMITERS);
+        assert_eq!(ed.line(), "foo cat bar");
+        assert_eq!(ed.pos(), 7);
+    }
+
+    #[test]
+    fn test_change_case() {
+        use readline::buffer::WORD_DELIMITERS;
+
+        let mut ed = Editor::new("> ".into());
+        ed.put("echo hello wORLD");
+        ed.move_to_first();
+        ed.change_case(WORD_DELIMITERS, Case::Upper);
+        assert_eq!(ed.line(), "ECHO hello wORLD");
+        ed.change_case(WORD_DELIMITERS, Case::Capitalize);
+        assert_eq!(ed.line(), "ECHO Hello wORLD");
+        ed.change_case(WORD_DELIMITERS, Case::Lower);
+        assert_eq!(ed.line(), "ECHO Hello world");
+        assert_eq!(ed.pos(), 16);
+    }
+
+    #[test]
+    fn test_undo() {
+        let mut ed = Editor::new("> ".into());
+        ed.put("e");
+        ed.put("c");
+        ed.put("ho");
+        ed.close_undo_unit();
+        ed.put(" hi");
+        ed.close_undo_unit();
+        ed.delete(3);
+        ed.close_undo_unit();
+        ed.replace("ls");
+        ed.close_undo_unit();
+
+        assert!(ed.undo());
+        assert_eq!(ed.line(), "echo");
+        assert!(ed.undo());
+        assert_eq!(ed.line(), "echo hi");
+        assert_eq!(ed.pos(), 7);
+        assert!(ed.undo());
+        assert_eq!(ed.line(), "echo");
+        assert!(ed.undo());
+        assert_eq!(ed.line(), "");
+        assert!(!ed.undo());
+
+        assert!(ed.redo());
+        assert_eq!(ed.line(), "echo");
+        assert!(ed.redo());
+        assert_eq!(ed.line(), "echo hi");
+        ed.put("!");
+        assert!(!ed.redo());
+    }
+
     #[test]
     fn test_move_to_end() {
         let mut ed = setup();
@@ -1,12 +1,17 @@
 use std::io;
 
-use readline::editor::Complete;
+use readline::completer::Direction;
+use readline::editor::{Case, Complete};
 use readline::context::{Context, Mode};
+use readline::vi;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Kind {
     Enter,
+    InsertNewline,
     Delete,
+    DeleteChar,
+    DeleteCharOrEof,
     Complete,
     CtrlL,
     ForwardChar,
@@ -15,11 +20,129 @@ pub enum Kind {
     NextHistory,
     BeginningOFLine,
     EndOfLine,
+    BackwardWord,
+    ForwardWord,
+    TransposeChars,
+    TransposeWords,
+    UpcaseWord,
+    DowncaseWord,
+    CapitalizeWord,
+    BeginningOfHistory,
+    EndOfHistory,
+    KillLine,
+    UnixLineDiscard,
+    UnixWordRubout,
+    KillWord,
+    BackwardKillWord,
+    Yank,
+    YankPop,
+    Undo,
+    Redo,
+    /// Escape in vi insert mode.
+    ViEscape,
+    /// Any key in vi command mode.
+    ViCommand,
     Interrupt,
+    Resize,
+    SelfInsert,
+    /// Text pasted between `\e[200~` and `\e[201~`.
+    BracketedPaste,
+    /// Page Up and Page Down in the completion menu.
+    MenuPageUp,
+    MenuPageDown,
     // TODO
     Something,
 }
 
+/// The names `bind` and inputrc files use for the commands, mostly the ones
+/// GNU Readline has.
+const NAMES: &[(&str, Kind)] = &[
+    ("accept-line", Kind::Enter),
+    ("backward-char", Kind::BackwardChar),
+    ("backward-delete-char", Kind::Delete),
+    ("backward-kill-word", Kind::BackwardKillWord),
+    ("backward-word", Kind::BackwardWord),
+    ("beginning-of-history", Kind::BeginningOfHistory),
+    ("beginning-of-line", Kind::BeginningOFLine),
+    ("bracketed-paste-begin", Kind::BracketedPaste),
+    ("capitalize-word", Kind::CapitalizeWord),
+    ("clear-screen", Kind::CtrlL),
+    ("complete", Kind::Complete),
+    ("delete-char", Kind::DeleteChar),
+    ("delete-char-or-eof", Kind::DeleteCharOrEof),
+    ("do-nothing", Kind::Something),
+    ("downcase-word", Kind::DowncaseWord),
+    ("end-of-history", Kind::EndOfHistory),
+    ("end-of-line", Kind::EndOfLine),
+    ("forward-char", Kind::ForwardChar),
+    ("forward-word", Kind::ForwardWord),
+    ("insert-newline", Kind::InsertNewline),
+    ("kill-line", Kind::KillLine),
+    ("kill-word", Kind::KillWord),
+    ("menu-page-down", Kind::MenuPageDown),
+    ("menu-page-up", Kind::MenuPageUp),
+    ("next-history", Kind::NextHistory),
+    ("previous-history", Kind::PreviousHistory),
+    ("redo", Kind::Redo),
+    ("self-insert", Kind::SelfInsert),
+    ("transpose-chars", Kind::TransposeChars),
+    ("transpose-words", Kind::TransposeWords),
+    ("undo", Kind::Undo),
+    ("unix-line-discard", Kind::UnixLineDiscard),
+    ("unix-word-rubout", Kind::UnixWordRubout),
+    ("upcase-word", Kind::UpcaseWord),
+    ("vi-movement-mode", Kind::ViEscape),
+    ("yank", Kind::Yank),
+    ("yank-pop", Kind::YankPop),
+];
+
+impl Kind {
+    pub fn from_name(name: &str) -> Option<Kind> {
+        NAMES.iter().find(|(n, _)| *n == name).map(|(_, k)| k.clone())
+    }
+
+    /// The name of a command that can be bound to a key.
+    pub fn name(&self) -> Option<&'static str> {
+        NAMES.iter().find(|(_, k)| k == self).map(|(n, _)| *n)
+    }
+
+    /// Every name, in alphabetical order.
+    pub fn names() -> Vec<&'static str> {
+        NAMES.iter().map(|(n, _)| *n).collect()
+    }
+
+    fn is_kill(&self) -> bool {
+        matches!(
+            *self,
+            Kind::KillLine | Kind::UnixLineDiscard | Kind::UnixWordRubout | Kind::KillWord |
+                Kind::BackwardKillWord
+        )
+    }
+}
+
+/// Drops the completion menu, if it is open, and goes back to typing, so
+/// that Up and Down move through the history again.
+fn close_menu(con: &mut Context) {
+    con.editor.completion_clear();
+    if con.mode == Mode::Completion {
+        con.mode = con.insert_mode();
+    }
+}
+
+/// Kills from the cursor to `to` and saves the text in the kill ring.
+fn kill_to(con: &mut Context, to: usize) {
+    close_menu(con);
+    let pos = con.editor.pos();
+    let backward = to < pos;
+    let text = if backward {
+        con.editor.kill(to, pos)
+    } else {
+        con.editor.kill(pos, to)
+    };
+    let append = con.last_kind.as_ref().is_some_and(Kind::is_kill);
+    con.kill_ring.kill(&text, backward, append);
+}
+
 pub type Handler = fn(&mut Context, Vec<u8>) -> io::Result<Option<String>>;
 
 pub struct Event {
@@ -31,39 +154,69 @@ impl Event {
     pub fn from_event_kind(k: &Option<Kind>) -> Self {
         let h: Handler = match *k {
             Some(Kind::Interrupt) => {
-                |Context { editor, history, .. }, _| {
-                    editor.completion_clear();
-                    editor.reset();
-                    editor.new_line();
-                    history.reset();
+                |con, _| {
+                    close_menu(con);
+                    con.editor.reset();
+                    con.editor.new_line();
+                    con.history.reset();
+                    Ok(None)
+                }
+            }
+            Some(Kind::Resize) => {
+                |con, _| {
+                    con.editor.update_win_size();
+                    con.editor.export_win_size();
+                    con.editor.redraw();
+                    if let Mode::Completion = con.mode {
+                        con.editor.completion_disply();
+                    }
                     Ok(None)
                 }
             }
             Some(Kind::Complete) => {
                 |con, _| {
-                    if !con.editor.line().trim().len() == con.editor.line().len() {
-                        // TODO
-                        // editor.put("\t".into())?;
-                        return Ok(None);
-                    } else {
-                        con.editor.complete(&mut *con.completer);
-                        con.editor.completion_next(&mut *con.completer);
-                        con.mode = Mode::Completion;
+                    if con.mode == Mode::Completion {
+                        con.editor.completion_move(Direction::Next);
+                    } else if con.last_kind == Some(Kind::Complete) && con.editor.completion_is_ambiguous() {
+                        // The second Tab shows what the first couldn't choose between.
+                        if con.editor.completion_needs_query() {
+                            con.editor.completion_query();
+                            con.mode = Mode::CompletionQuery;
+                        } else {
+                            con.editor.completion_menu();
+                            con.mode = Mode::Completion;
+                        }
+                    } else if con.editor.complete(&mut *con.completer) > 0 {
+                        con.editor.completion_insert_common();
                     }
                     Ok(None)
                 }
             }
             Some(Kind::Enter) => {
-                |Context { editor, history, .. }, _| {
-                    let result = editor.line().clone();
-                    editor.completion_clear();
-                    editor.reset();
-                    editor.new_line();
-                    history.push(result.clone());
-                    history.reset();
+                |con, _| {
+                    close_menu(con);
+                    if con.editor.open_quote().is_some() {
+                        // The command goes on to the next line.
+                        con.editor.move_to_end();
+                        con.editor.put("\n");
+                        return Ok(None);
+                    }
+                    let result = con.editor.line().clone();
+                    con.editor.reset();
+                    con.editor.new_line();
+                    con.history.push(result.clone());
+                    con.history.reset();
                     Ok(Some(result))
                 }
             }
+            Some(Kind::InsertNewline) => {
+                |con, _| {
+                    con.editor.completion_clear();
+                    con.mode = con.insert_mode();
+                    con.editor.put("\n");
+                    Ok(None)
+                }
+            }
             Some(Kind::CtrlL) => {
                 |Context { editor, .. }, _| {
                     editor.clear_screen();
@@ -72,31 +225,59 @@ impl Event {
                 }
             }
             Some(Kind::Delete) => {
-                |Context { editor, .. }, _| {
-                    editor.completion_clear();
-                    editor.delete(1);
+                |con, _| {
+                    close_menu(con);
+                    con.vi.record_delete();
+                    con.editor.delete(1);
+                    Ok(None)
+                }
+            }
+            Some(Kind::DeleteChar) => {
+                |con, _| {
+                    close_menu(con);
+                    con.editor.delete_char();
+                    Ok(None)
+                }
+            }
+            Some(Kind::DeleteCharOrEof) => {
+                |con, _| {
+                    close_menu(con);
+                    if con.editor.line().is_empty() {
+                        con.editor.new_line();
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "end of input"));
+                    }
+                    con.editor.delete_char();
                     Ok(None)
                 }
             }
             Some(Kind::ForwardChar) => {
-                |Context { editor, .. }, _| {
-                    editor.move_right(1);
+                |con, _| {
+                    match con.mode {
+                        Mode::Completion => con.editor.completion_move(Direction::Next),
+                        _ => con.editor.move_right(1),
+                    }
                     Ok(None)
                 }
             }
             Some(Kind::BackwardChar) => {
-                |Context { editor, .. }, _| {
-                    editor.move_left(1);
+                |con, _| {
+                    match con.mode {
+                        Mode::Completion => con.editor.completion_move(Direction::Prev),
+                        _ => con.editor.move_left(1),
+                    }
                     Ok(None)
                 }
             }
             Some(Kind::PreviousHistory) => {
                 |con, _| match con.mode {
                     Mode::Completion => {
-                        con.editor.completion_prev(&mut *con.completer);
+                        con.editor.completion_move(Direction::Up);
                         Ok(None)
                     }
-                    Mode::Normal => {
+                    _ => {
+                        if con.editor.move_line_up() {
+                            return Ok(None);
+                        }
                         if con.history.is_started() {
                             con.history.set_first(con.editor.line().clone());
                         }
@@ -113,10 +294,13 @@ impl Event {
             Some(Kind::NextHistory) => {
                 |con, _| match con.mode {
                     Mode::Completion => {
-                        con.editor.completion_next(&mut *con.completer);
+                        con.editor.completion_move(Direction::Down);
                         Ok(None)
                     }
-                    Mode::Normal => {
+                    _ => {
+                        if con.editor.move_line_down() {
+                            return Ok(None);
+                        }
                         let history = match con.history.next() {
                             Some(h) => h,
                             None => return Ok(None),
@@ -140,18 +324,204 @@ impl Event {
                     Ok(None)
                 }
             }
+            Some(Kind::BackwardWord) => {
+                |con, _| {
+                    let pos = con.editor.word_start(&con.word_delimiters);
+                    con.editor.move_to(pos);
+                    Ok(None)
+                }
+            }
+            Some(Kind::ForwardWord) => {
+                |con, _| {
+                    let pos = con.editor.word_end(&con.word_delimiters);
+                    con.editor.move_to(pos);
+                    Ok(None)
+                }
+            }
+            Some(Kind::TransposeChars) => {
+                |Context { editor, .. }, _| {
+                    editor.transpose_chars();
+                    Ok(None)
+                }
+            }
+            Some(Kind::TransposeWords) => {
+                |con, _| {
+                    con.editor.transpose_words(&con.word_delimiters);
+                    Ok(None)
+                }
+            }
+            Some(Kind::UpcaseWord) => {
+                |con, _| {
+                    con.editor.change_case(&con.word_delimiters, Case::Upper);
+                    Ok(None)
+                }
+            }
+            Some(Kind::DowncaseWord) => {
+                |con, _| {
+                    con.editor.change_case(&con.word_delimiters, Case::Lower);
+                    Ok(None)
+                }
+            }
+            Some(Kind::CapitalizeWord) => {
+                |con, _| {
+                    con.editor.change_case(&con.word_delimiters, Case::Capitalize);
+                    Ok(None)
+                }
+            }
+            Some(Kind::BeginningOfHistory) => {
+                |Context { editor, history, .. }, _| {
+                    if history.is_started() {
+                        history.set_first(editor.line());
+                    }
+                    if let Some(h) = history.beginning() {
+                        editor.replace(h);
+                    }
+                    Ok(None)
+                }
+            }
+            Some(Kind::EndOfHistory) => {
+                |Context { editor, history, .. }, _| {
+                    if let Some(h) = history.end() {
+                        editor.replace(h);
+                    }
+                    Ok(None)
+                }
+            }
+            Some(Kind::KillLine) => {
+                |con, _| {
+                    let to = con.editor.line_end();
+                    kill_to(con, to);
+                    Ok(None)
+                }
+            }
+            Some(Kind::UnixLineDiscard) => {
+                |con, _| {
+                    let to = con.editor.line_start();
+                    kill_to(con, to);
+                    Ok(None)
+                }
+            }
+            Some(Kind::UnixWordRubout) => {
+                |con, _| {
+                    let to = con.editor.word_start(&con.unix_word_delimiters);
+                    kill_to(con, to);
+                    Ok(None)
+                }
+            }
+            Some(Kind::KillWord) => {
+                |con, _| {
+                    let to = con.editor.word_end(&con.word_delimiters);
+                    kill_to(con, to);
+                    Ok(None)
+                }
+            }
+            Some(Kind::BackwardKillWord) => {
+                |con, _| {
+                    let to = con.editor.word_start(&con.word_delimiters);
+                    kill_to(con, to);
+                    Ok(None)
+                }
+            }
+            Some(Kind::Yank) => {
+                |con, _| {
+                    close_menu(con);
+                    if let Some(text) = con.kill_ring.yank() {
+                        con.editor.put(text);
+                    }
+                    Ok(None)
+                }
+            }
+            Some(Kind::YankPop) => {
+                |con, _| {
+                    match con.last_kind {
+                        Some(Kind::Yank) | Some(Kind::YankPop) => (),
+                        _ => return Ok(None),
+                    }
+                    close_menu(con);
+                    let yanked = con.kill_ring.current().map_or(0, |s| s.chars().count());
+                    if let Some(text) = con.kill_ring.yank_pop() {
+                        let pos = con.editor.pos();
+                        con.editor.kill(pos - yanked, pos);
+                        con.editor.put(text);
+                    }
+                    Ok(None)
+                }
+            }
+            Some(Kind::Undo) => {
+                |con, _| {
+                    con.editor.completion_clear();
+                    con.mode = con.insert_mode();
+                    con.editor.undo();
+                    Ok(None)
+                }
+            }
+            Some(Kind::Redo) => {
+                |con, _| {
+                    con.editor.completion_clear();
+                    con.mode = con.insert_mode();
+                    con.editor.redo();
+                    Ok(None)
+                }
+            }
+            Some(Kind::ViEscape) => {
+                |con, _| {
+                    vi::escape(con);
+                    Ok(None)
+                }
+            }
+            Some(Kind::ViCommand) => |con, c| vi::command(con, &c),
+            Some(Kind::BracketedPaste) => {
+                |con, c| {
+                    close_menu(con);
+                    // Newlines and tabs are text here, not Enter and Tab.
+                    let text = String::from_utf8_lossy(&c).replace("\r\n", "\n").replace('\r', "\n");
+                    con.editor.put(&text);
+                    con.history.reset_first();
+                    Ok(None)
+                }
+            }
+            Some(Kind::MenuPageUp) => {
+                |con, _| {
+                    if con.mode == Mode::Completion {
+                        let page = con.editor.completion_page();
+                        con.editor.completion_move(Direction::PageUp(page));
+                    }
+                    Ok(None)
+                }
+            }
+            Some(Kind::MenuPageDown) => {
+                |con, _| {
+                    if con.mode == Mode::Completion {
+                        let page = con.editor.completion_page();
+                        con.editor.completion_move(Direction::PageDown(page));
+                    }
+                    Ok(None)
+                }
+            }
             Some(Kind::Something) => |_, _| Ok(None),
             _ => {
                 |con, c: Vec<u8>| {
-                    con.editor.completion_clear();
-                    con.mode = Mode::Normal;
-
-                    // con.editor.complete();
-                    // con.editor.completion_next();
-                    // con.editor.completion_disply();
+                    let s = String::from_utf8_lossy(&c).into_owned();
+                    // More of the word narrows the menu down instead of
+                    // closing it.
+                    let filters = con.mode == Mode::Completion &&
+                        !s.contains(|c: char| c.is_whitespace() || ";|&<>()".contains(c));
+                    if !filters {
+                        con.editor.completion_clear();
+                        con.mode = con.insert_mode();
+                    }
 
-                    con.editor.put(&String::from_utf8(c).unwrap());
+                    con.vi.record_insert(&s);
+                    con.editor.put(&s);
                     con.history.reset_first();
+                    if filters {
+                        if con.editor.complete(&mut *con.completer) > 0 {
+                            con.editor.completion_menu();
+                        } else {
+                            con.editor.completion_clear();
+                            con.mode = con.insert_mode();
+                        }
+                    }
                     Ok(None)
                 }
             }
@@ -162,3 +532,34 @@ impl Event {
         }
     }
 }
+
+#[test]
+fn test_menu_closes() {
+    use readline::completer::{self, Candidate, Completer, CompletionContext};
+
+    struct Echoes;
+    impl Completer for Echoes {
+        fn complete(&mut self, _: &CompletionContext) -> Vec<Candidate> {
+            ["echo", "ecto"]
+                .iter()
+                .map(|&name| Candidate::new(name, name.into(), completer::Kind::Executable))
+                .collect()
+        }
+    }
+
+    let mut con = Context::new(Box::new(Echoes));
+    con.history.push("ls -l".into());
+    con.editor.put("ec");
+    for kind in &[Kind::Complete, Kind::Complete] {
+        (Event::from_event_kind(&Some(kind.clone())).handler)(&mut con, Vec::new()).unwrap();
+        con.last_kind = Some(kind.clone());
+    }
+    assert_eq!(con.mode, Mode::Completion);
+
+    // Backspace closes the menu, so Up goes back to the history.
+    for kind in &[Kind::Delete, Kind::PreviousHistory] {
+        (Event::from_event_kind(&Some(kind.clone())).handler)(&mut con, Vec::new()).unwrap();
+    }
+    assert_eq!(con.mode, Mode::Normal);
+    assert_eq!(con.editor.line(), "ls -l");
+}
@@ -0,0 +1,533 @@
+//! The options of a command, read from what `cmd --help` prints or from its
+//! roff man page, for completion. Running a command to find out takes a
+//! while, so what is found is kept on disk until the command changes.
+
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+
+/// How long `--help` may take before it is given up on.
+const HELP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Where man pages are when `$MANPATH` doesn't say.
+const MAN_DIRS: &[&str] = &["/usr/local/share/man", "/usr/share/man", "/usr/local/man"];
+
+/// How many `.so` pages are followed before giving up, as they may loop.
+const MAX_SO_DEPTH: usize = 8;
+
+/// What a command takes.
+#[derive(Debug, Default, PartialEq)]
+pub struct Help {
+    /// Each option and what it does.
+    pub options: Vec<(String, String)>,
+    /// The subcommands it lists, like `build` for `cargo`.
+    pub subcommands: Vec<String>,
+}
+
+impl Help {
+    /// Finds out what `words`, a command and maybe a subcommand, take.
+    /// `path` is where the command is, and `gzip` where `gzip` is, to read
+    /// compressed man pages.
+    pub fn of(words: &[&str], path: &Path, gzip: Option<&Path>) -> Self {
+        let stamp = stamp(path);
+        let cache = cache_dir().map(|dir| dir.join(words.join(" ")));
+        if let Some(help) = cache.as_ref().and_then(|file| Self::read_cache(file, &stamp)) {
+            return help;
+        }
+
+        let mut command = Command::new(path);
+        command.args(&words[1..]).arg("--help");
+        let text = output_within(&mut command, HELP_TIMEOUT);
+        let mut help = text.as_ref().map(|text| parse_help(text)).unwrap_or_default();
+        if help.options.is_empty() {
+            if let Some(roff) = man_page(&words.join("-"), gzip, 0) {
+                help.options = parse_man(&roff);
+            }
+        }
+
+        // A command that timed out may answer next time.
+        if let (Some(file), Some(_)) = (cache, text) {
+            help.write_cache(&file, &stamp);
+        }
+        help
+    }
+
+    /// The file is a line with the stamp of the command it was written for,
+    /// then an option and its description, or a subcommand, on each line.
+    fn read_cache(file: &Path, stamp: &str) -> Option<Self> {
+        let text = fs::read_to_string(file).ok()?;
+        let mut lines = text.lines();
+        if lines.next() != Some(stamp) {
+            return None;
+        }
+        let mut help = Self::default();
+        for line in lines {
+            match line.split_once('\t') {
+                Some((flag, description)) => help.options.push((flag.into(), description.into())),
+                None => help.subcommands.push(line.into()),
+            }
+        }
+        Some(help)
+    }
+
+    /// Failing to write is no worse than not having a cache.
+    fn write_cache(&self, file: &Path, stamp: &str) {
+        let mut text = format!("{}\n", stamp);
+        for (flag, description) in &self.options {
+            text.push_str(&format!("{}\t{}\n", flag, description));
+        }
+        for subcommand in &self.subcommands {
+            text.push_str(&format!("{}\n", subcommand));
+        }
+        if let Some(dir) = file.parent() {
+            let _ = fs::create_dir_all(dir).and_then(|_| fs::write(file, text));
+        }
+    }
+}
+
+/// Tells one build of a command from another.
+fn stamp(path: &Path) -> String {
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+    let secs = modified.and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map_or(0, |d| d.as_secs());
+    format!("{} {}", path.display(), secs)
+}
+
+fn cache_dir() -> Option<PathBuf> {
+    let cache = match env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+    };
+    Some(cache.join("mican").join("options"))
+}
+
+/// Runs `command` and returns what it printed, to standard output or else
+/// to standard error. Gives up on it after `timeout`. It runs in a process
+/// group of its own, so that a pager or anything else it starts goes with
+/// it.
+fn output_within(command: &mut Command, timeout: Duration) -> Option<String> {
+    let mut child = command
+        .env("PAGER", "cat")
+        .env("MANPAGER", "cat")
+        .env("GIT_PAGER", "cat")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+        .ok()?;
+    let group = Pid::from_raw(-(child.id() as i32));
+
+    fn read_all<R: Read + Send + 'static>(mut pipe: R) -> thread::JoinHandle<Vec<u8>> {
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            let _ = pipe.read_to_end(&mut buf);
+            buf
+        })
+    }
+    let out = read_all(child.stdout.take()?);
+    let err = read_all(child.stderr.take()?);
+
+    let start = Instant::now();
+    let finished = loop {
+        match child.try_wait() {
+            Ok(Some(_)) => break true,
+            Ok(None) if start.elapsed() < timeout => thread::sleep(Duration::from_millis(10)),
+            _ => break false,
+        }
+    };
+    // Whatever is left of the group would keep the pipes open.
+    let _ = kill(group, Signal::SIGKILL);
+    let _ = child.wait();
+
+    let (out, err) = (out.join().ok()?, err.join().ok()?);
+    if !finished {
+        return None;
+    }
+    let text = if out.is_empty() { err } else { out };
+    Some(String::from_utf8_lossy(&text).into_owned())
+}
+
+/// Reads the options out of `--help` output, from lines like
+///
+/// ```text
+///   -a, --all                  do not ignore entries starting with .
+///       --color[=WHEN]         color the output WHEN
+/// ```
+///
+/// and the subcommands out of lines like `    build, b    Compile ...`. A
+/// description may also be on the line after the option, and flags that
+/// end in a comma go on on the next line.
+pub fn parse_help(text: &str) -> Help {
+    let mut help = Help::default();
+    let lines: Vec<&str> = text.lines().collect();
+    let mut pending: Vec<String> = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        if let Some((mut flags, mut description)) = parse_option_line(line) {
+            if description.is_empty() && line.trim_end().ends_with(',') {
+                pending.append(&mut flags);
+                continue;
+            }
+            flags.splice(0..0, pending.drain(..));
+            if description.is_empty() {
+                if let Some(next) = lines.get(i + 1) {
+                    let next = next.trim();
+                    if !next.starts_with('-') {
+                        description = next.to_string();
+                    }
+                }
+            }
+            for flag in flags {
+                if help.options.iter().all(|(f, _)| *f != flag) {
+                    help.options.push((flag, description.clone()));
+                }
+            }
+        } else if let Some(subcommand) = parse_subcommand_line(line) {
+            help.subcommands.push(subcommand);
+        }
+    }
+    help
+}
+
+/// Splits an option line into its flags and description, which are two
+/// spaces or a tab apart.
+fn parse_option_line(line: &str) -> Option<(Vec<String>, String)> {
+    let line = line.trim_start();
+    if !line.starts_with('-') {
+        return None;
+    }
+    let (flags, description) = match gap(line) {
+        Some(i) => (&line[..i], line[i..].trim()),
+        None => (line.trim_end(), ""),
+    };
+    let flags: Vec<String> = flags.split([',', ' ', '|']).filter_map(flag).collect();
+    if flags.is_empty() {
+        return None;
+    }
+    Some((flags, description.split_whitespace().collect::<Vec<_>>().join(" ")))
+}
+
+/// Where two spaces or a tab are.
+fn gap(line: &str) -> Option<usize> {
+    match (line.find("  "), line.find('\t')) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// The flag in a word like `--color[=WHEN]`, `--jobs=<N>` or `-o`.
+fn flag(word: &str) -> Option<String> {
+    let rest = word.strip_prefix("--").or_else(|| word.strip_prefix('-'))?;
+    let name: String = rest
+        .chars()
+        .take_while(|&c| c.is_alphanumeric() || c == '-' || c == '_')
+        .collect();
+    if name.is_empty() || name.starts_with('-') {
+        return None;
+    }
+    Some(format!("{}{}", &word[..word.len() - rest.len()], name))
+}
+
+/// The subcommand on an indented line like `    build, b    Compile ...`.
+fn parse_subcommand_line(line: &str) -> Option<String> {
+    let text = line.trim_start();
+    if line.len() - text.len() < 2 {
+        return None;
+    }
+    let i = gap(text)?;
+    let name = text[..i].split(',').next()?.trim();
+    let is_word = name.starts_with(|c: char| c.is_ascii_lowercase()) &&
+        name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if is_word && !text[i..].trim().is_empty() {
+        Some(name.into())
+    } else {
+        None
+    }
+}
+
+/// The roff source of the man page of `name` in section 1 or 8, from
+/// `$MANPATH` or the usual places. `gzip` reads compressed ones. `depth`
+/// is how many `.so` pages led here.
+fn man_page(name: &str, gzip: Option<&Path>, depth: usize) -> Option<String> {
+    if depth > MAX_SO_DEPTH {
+        return None;
+    }
+
+    let manpath = env::var("MANPATH").unwrap_or_default();
+    let mut dirs: Vec<&str> = Vec::new();
+    for dir in manpath.split(':') {
+        // An empty entry stands for the usual places.
+        if dir.is_empty() {
+            dirs.extend(MAN_DIRS);
+        } else {
+            dirs.push(dir);
+        }
+    }
+
+    for dir in dirs {
+        for section in &["1", "8"] {
+            for ext in &["", ".gz"] {
+                let path = Path::new(dir).join(format!("man{}/{}.{}{}", section, name, section, ext));
+                if !path.is_file() {
+                    continue;
+                }
+                let roff = match gzip {
+                    _ if ext.is_empty() => fs::read_to_string(&path).ok(),
+                    Some(gzip) => output_within(Command::new(gzip).arg("-dc").arg(&path), HELP_TIMEOUT),
+                    None => None,
+                };
+                let roff = match roff {
+                    Some(roff) => roff,
+                    None => continue,
+                };
+                // A page that is only `.so man1/other.1` is that other page.
+                return match roff.trim().strip_prefix(".so ") {
+                    Some(other) => {
+                        let name = Path::new(other).file_stem()?.to_str()?.to_string();
+                        man_page(name.trim_end_matches(".1").trim_end_matches(".8"), gzip, depth + 1)
+                    }
+                    None => Some(roff),
+                };
+            }
+        }
+    }
+    None
+}
+
+/// Reads the options out of a man page written with the man macros, where
+/// each is a `.TP` or `.IP` paragraph, or with the mdoc macros, where each
+/// is an `.It Fl` item. The first line of the paragraph describes it.
+pub fn parse_man(roff: &str) -> Vec<(String, String)> {
+    let mut options: Vec<(String, String)> = Vec::new();
+    let mut tag: Option<String> = None;
+    let mut lines = roff.lines();
+    while let Some(line) = lines.next() {
+        let (request, args) = match line.strip_prefix('.').or_else(|| line.strip_prefix('\'')) {
+            Some(rest) => {
+                let rest = rest.trim_start();
+                match rest.find(' ') {
+                    Some(i) => (&rest[..i], rest[i..].trim()),
+                    None => (rest, ""),
+                }
+            }
+            None => ("", line),
+        };
+
+        let text = match request {
+            "" => unroff(args),
+            "TP" | "TQ" => {
+                tag = lines.next().map(|line| macro_text(line).unwrap_or_else(|| unroff(line)));
+                continue;
+            }
+            "IP" => {
+                tag = Some(unroff(split_args(args).first().map_or("", |s| s.as_str())));
+                continue;
+            }
+            "It" if args.starts_with("Fl") => {
+                tag = Some(mdoc_flags(args));
+                continue;
+            }
+            _ => match macro_text(line) {
+                Some(text) => text,
+                None => {
+                    tag = None;
+                    continue;
+                }
+            },
+        };
+
+        // The line after the tag is its description.
+        if let Some(tag) = tag.take() {
+            if let Some((flags, _)) = parse_option_line(&tag) {
+                let description = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                for flag in flags {
+                    if options.iter().all(|(f, _)| *f != flag) {
+                        options.push((flag, description.clone()));
+                    }
+                }
+            }
+        }
+    }
+    options
+}
+
+/// The text of a font macro line like `.B \-\-all` or `.BR \-a ", " \-\-all`.
+fn macro_text(line: &str) -> Option<String> {
+    let rest = line.strip_prefix('.')?;
+    let (request, args) = match rest.find(' ') {
+        Some(i) => (&rest[..i], &rest[i + 1..]),
+        None => (rest, ""),
+    };
+    let args = split_args(args);
+    let text = match request {
+        "B" | "I" | "SM" | "SB" => args.join(" "),
+        "BR" | "RB" | "BI" | "IB" | "IR" | "RI" => args.concat(),
+        _ => return None,
+    };
+    Some(unroff(&text))
+}
+
+/// Splits macro arguments at spaces, except inside double quotes.
+fn split_args(args: &str) -> Vec<String> {
+    let mut res = Vec::new();
+    let mut arg = String::new();
+    let mut quoted = false;
+    for c in args.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ' ' if !quoted => {
+                if !arg.is_empty() {
+                    res.push(arg.clone());
+                    arg.clear();
+                }
+            }
+            c => arg.push(c),
+        }
+    }
+    if !arg.is_empty() {
+        res.push(arg);
+    }
+    res
+}
+
+/// The flags of an mdoc item like `.It Fl a , Fl \-all Ar file`.
+fn mdoc_flags(args: &str) -> String {
+    let words: Vec<&str> = args.split_whitespace().collect();
+    let flags: Vec<String> = words
+        .windows(2)
+        .filter(|pair| pair[0] == "Fl")
+        .map(|pair| format!("-{}", unroff(pair[1])))
+        .collect();
+    flags.join(", ")
+}
+
+/// Turns roff escapes into the text they stand for, or nothing for fonts
+/// and the like.
+fn unroff(s: &str) -> String {
+    let mut res = String::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            res.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('f') | Some('*') => {
+                // `\fB`, `\f(CW`, `\f[B]`, `\*(lq`.
+                match chars.next() {
+                    Some('(') => {
+                        chars.next();
+                        chars.next();
+                    }
+                    Some('[') => while chars.next().is_some_and(|c| c != ']') {},
+                    _ => (),
+                }
+            }
+            Some('(') => {
+                let name: String = chars.by_ref().take(2).collect();
+                match name.as_str() {
+                    "em" | "en" | "hy" | "mi" => res.push('-'),
+                    "aq" => res.push('\''),
+                    "dq" => res.push('"'),
+                    _ => (),
+                }
+            }
+            Some('-') => res.push('-'),
+            Some('e') | Some('\\') => res.push('\\'),
+            Some(' ') | Some('~') => res.push(' '),
+            // A comment.
+            Some('"') => break,
+            Some('&') | Some('c') | Some('/') | Some(',') | Some('|') | Some('^') | None => (),
+            Some(c) => res.push(c),
+        }
+    }
+    res
+}
+
+#[test]
+fn test_parse_help() {
+    let help = parse_help(
+        "Usage: ls [OPTION]... [FILE]...\n\
+         \x20 -a, --all                  do not ignore entries starting with .\n\
+         \x20     --color[=WHEN]         color the output WHEN;\n\
+         \x20     --color[=WHEN],\n\
+         \x20     --colour[=WHEN]        the same\n\
+         \x20 -w, --width=COLS           set output width to COLS\n\
+         \x20 -Z, --context\n\
+         \x20       print any security context of each file\n",
+    );
+    assert_eq!(
+        help.options,
+        vec![
+            ("-a".to_string(), "do not ignore entries starting with .".to_string()),
+            ("--all".to_string(), "do not ignore entries starting with .".to_string()),
+            ("--color".to_string(), "color the output WHEN;".to_string()),
+            ("--colour".to_string(), "the same".to_string()),
+            ("-w".to_string(), "set output width to COLS".to_string()),
+            ("--width".to_string(), "set output width to COLS".to_string()),
+            ("-Z".to_string(), "print any security context of each file".to_string()),
+            ("--context".to_string(), "print any security context of each file".to_string()),
+        ]
+    );
+
+    let help = parse_help(
+        "Commands:\n    build, b    Compile the current package\n    new         Create a new cargo package\n\n\
+         Options:\n  -j, --jobs <N>  Number of parallel jobs\n",
+    );
+    assert_eq!(help.subcommands, vec!["build", "new"]);
+    assert_eq!(help.options[1], ("--jobs".to_string(), "Number of parallel jobs".to_string()));
+}
+
+#[test]
+fn test_parse_man() {
+    let roff = ".SH OPTIONS\n\
+                .TP\n\
+                \\fB\\-a\\fR, \\fB\\-\\-all\\fR\n\
+                do not ignore entries starting with .\n\
+                .TP\n\
+                .BR \\-\\-color [=\\fIWHEN\\fR]\n\
+                color the output \\fIWHEN\\fR\n\
+                .IP \"\\fB\\-v\\fR\" 4\n\
+                .B verbose\n\
+                output\n\
+                .It Fl q , Fl \\-quiet\n\
+                Say nothing.\n";
+    assert_eq!(
+        parse_man(roff),
+        vec![
+            ("-a".to_string(), "do not ignore entries starting with .".to_string()),
+            ("--all".to_string(), "do not ignore entries starting with .".to_string()),
+            ("--color".to_string(), "color the output WHEN".to_string()),
+            ("-v".to_string(), "verbose".to_string()),
+            ("-q".to_string(), "Say nothing.".to_string()),
+            ("--quiet".to_string(), "Say nothing.".to_string()),
+        ]
+    );
+    assert_eq!(unroff("\\(lqa\\(em\\e\\&b\\\" comment"), "a-\\b");
+}
+
+#[test]
+fn test_man_page() {
+    use testing::TempDir;
+
+    let base = TempDir::new("man");
+    fs::create_dir_all(base.join("man1")).unwrap();
+    fs::create_dir_all(base.join("other/man1")).unwrap();
+    fs::write(base.join("man1/loop.1"), ".so man1/loop.1\n").unwrap();
+    // Without gzip the first page can't be read, so the next one is.
+    fs::write(base.join("man1/tool.1.gz"), "").unwrap();
+    fs::write(base.join("other/man1/tool.1"), ".TH TOOL 1\n").unwrap();
+    env::set_var("MANPATH", format!("{}:{}", base.display(), base.join("other").display()));
+
+    assert_eq!(man_page("loop", None, 0), None);
+    assert_eq!(man_page("tool", None, 0), Some(".TH TOOL 1\n".into()));
+
+    env::remove_var("MANPATH");
+}
@@ -79,6 +79,45 @@ impl History {
         ret
     }
 
+    /// The oldest entry.
+    pub fn beginning(&mut self) -> Option<&String> {
+        self.prev = HistoryCmd::Prev;
+        self.pos = self.list.len();
+        self.list.back()
+    }
+
+    /// Back to the line that was being edited before moving through the
+    /// history.
+    pub fn end(&mut self) -> Option<&String> {
+        self.prev = HistoryCmd::Prev;
+        self.pos = 0;
+        self.first.as_ref()
+    }
+
+    /// The nearest entry containing `pattern`, older ones first when
+    /// `older`. The position stays where it was when nothing matches.
+    pub fn search(&mut self, pattern: &str, older: bool) -> Option<String> {
+        let (pos, prev) = (self.pos, self.prev.clone());
+        loop {
+            let entry = if older { self.prev() } else { self.next() }.cloned();
+            match entry {
+                Some(entry) => {
+                    if entry.contains(pattern) {
+                        return Some(entry);
+                    }
+                    // `next` ends on the line being edited.
+                    if !older && self.pos == 0 {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+        self.pos = pos;
+        self.prev = prev;
+        None
+    }
+
     pub fn push(&mut self, s: String) {
         self.list.push_front(s);
     }
@@ -114,3 +153,33 @@ fn test_history() {
     assert_eq!(history.next(), Some(&"C".to_string()));
     assert_eq!(history.next(), Some(&"D".to_string()));
 }
+
+#[test]
+fn test_beginning_and_end() {
+    let mut history = History::new();
+    history.push("A".to_string());
+    history.push("B".to_string());
+    history.push("C".to_string());
+    history.set_first("D".to_string());
+
+    assert_eq!(history.beginning(), Some(&"A".to_string()));
+    assert_eq!(history.next(), Some(&"B".to_string()));
+    assert_eq!(history.end(), Some(&"D".to_string()));
+    assert_eq!(history.prev(), Some(&"C".to_string()));
+}
+
+#[test]
+fn test_search() {
+    let mut history = History::new();
+    history.push("ls -l".to_string());
+    history.push("cd src".to_string());
+    history.push("ls -a".to_string());
+    history.set_first("".to_string());
+
+    assert_eq!(history.search("ls", true), Some("ls -a".to_string()));
+    assert_eq!(history.search("ls", true), Some("ls -l".to_string()));
+    assert_eq!(history.search("ls", true), None);
+    assert_eq!(history.search("cd", false), Some("cd src".to_string()));
+    assert_eq!(history.search("nothing", false), None);
+    assert_eq!(history.next(), Some(&"ls -a".to_string()));
+}
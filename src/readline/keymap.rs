@@ -0,0 +1,786 @@
+//! Key bindings, kept in a trie of key sequences, and the inputrc syntax
+//! `bind` and `~/.inputrc` write them in.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use readline::buffer::{UNIX_WORD_DELIMITERS, WORD_DELIMITERS};
+use readline::context::EditingMode;
+use readline::event::Kind;
+
+/// What a key sequence runs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Binding {
+    Function(Kind),
+    /// Keys fed back into the input as if they had been typed.
+    Macro(Vec<u8>),
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Binding::Function(ref kind) => write!(f, "{}", kind.name().unwrap_or("")),
+            Binding::Macro(ref keys) => write!(f, "\"{}\"", format_keyseq(keys)),
+        }
+    }
+}
+
+/// What a lookup found at the start of the input.
+#[derive(Debug, PartialEq)]
+pub enum Match<'a> {
+    /// The first `usize` bytes are a bound key sequence.
+    Bound(usize, &'a Binding),
+    /// No bound sequence starts the input.
+    Unbound,
+    /// The input so far may be the start of a longer sequence.
+    Incomplete,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Node {
+    binding: Option<Binding>,
+    next: BTreeMap<u8, Node>,
+}
+
+/// A trie of key sequences.
+#[derive(Debug, Clone, Default)]
+pub struct Keymap {
+    root: Node,
+}
+
+impl Keymap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn from_table<F>(filter: F) -> Self
+    where
+        F: Fn(&[u8]) -> bool,
+    {
+        let mut keymap = Self::new();
+        for &(seq, ref kind) in EMACS {
+            if filter(seq) {
+                keymap.bind(seq, Binding::Function(kind.clone()));
+            }
+        }
+        keymap
+    }
+
+    pub fn bind(&mut self, seq: &[u8], binding: Binding) {
+        let node = seq
+            .iter()
+            .fold(&mut self.root, |node, b| node.next.entry(*b).or_default());
+        node.binding = Some(binding);
+    }
+
+    /// Returns whether `seq` was bound.
+    pub fn unbind(&mut self, seq: &[u8]) -> bool {
+        let mut node = &mut self.root;
+        for b in seq {
+            node = match node.next.get_mut(b) {
+                Some(next) => next,
+                None => return false,
+            };
+        }
+        node.binding.take().is_some()
+    }
+
+    /// Finds the longest bound sequence at the start of `input`. While the
+    /// input could still grow into a longer sequence the answer is
+    /// `Incomplete`, unless the wait for more has `timed_out`.
+    pub fn lookup(&self, input: &[u8], timed_out: bool) -> Match<'_> {
+        let mut node = &self.root;
+        let mut found = Match::Unbound;
+        for (i, b) in input.iter().enumerate() {
+            node = match node.next.get(b) {
+                Some(next) => next,
+                None => return found,
+            };
+            if let Some(ref binding) = node.binding {
+                found = Match::Bound(i + 1, binding);
+            }
+        }
+        if input.is_empty() || !node.next.is_empty() && !timed_out {
+            return Match::Incomplete;
+        }
+        found
+    }
+
+    /// Every binding, ordered by key sequence.
+    pub fn bindings(&self) -> Vec<(Vec<u8>, &Binding)> {
+        fn walk<'a>(node: &'a Node, seq: &mut Vec<u8>, out: &mut Vec<(Vec<u8>, &'a Binding)>) {
+            if let Some(ref binding) = node.binding {
+                out.push((seq.clone(), binding));
+            }
+            for (b, next) in &node.next {
+                seq.push(*b);
+                walk(next, seq, out);
+                seq.pop();
+            }
+        }
+
+        let mut out = Vec::new();
+        walk(&self.root, &mut Vec::new(), &mut out);
+        out
+    }
+
+    /// The key sequences that run the function `name`.
+    pub fn keys_for(&self, name: &str) -> Vec<Vec<u8>> {
+        let kind = Kind::from_name(name);
+        self.bindings()
+            .into_iter()
+            .filter(|(_, b)| matches!(b, Binding::Function(k) if Some(k) == kind.as_ref()))
+            .map(|(seq, _)| seq)
+            .collect()
+    }
+}
+
+/// The keymaps of the editing modes, and the settings that go with them.
+pub struct Keymaps {
+    pub emacs: Keymap,
+    pub vi_insert: Keymap,
+    pub vi_command: Keymap,
+    /// How long, in milliseconds, to wait for the rest of a key sequence
+    /// like an arrow key's before taking its start on its own.
+    pub keyseq_timeout: u64,
+    /// Whether the terminal marks pasted text, so that it goes in as-is
+    /// instead of running commands at newlines and completing at tabs.
+    pub bracketed_paste: bool,
+    /// Whether pasting more than one line asks first.
+    pub confirm_paste: bool,
+    /// How many completions make it ask before showing them all.
+    pub completion_query_items: usize,
+    /// What separates words for `Alt-D` and the other word commands.
+    pub word_delimiters: String,
+    /// What separates words for `Ctrl-W`.
+    pub unix_word_delimiters: String,
+    /// An `editing-mode` an inputrc file set, for the shell to pick up.
+    pub editing_mode: Option<EditingMode>,
+    /// The files being read, innermost `$include` last.
+    reading: Vec<PathBuf>,
+}
+
+impl Default for Keymaps {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Keymaps {
+    pub fn new() -> Self {
+        // Vi insert mode has the control keys, but Escape goes to command
+        // mode instead of starting Meta keys.
+        let mut vi_insert = Keymap::from_table(|seq| !is_meta(seq));
+        vi_insert.bind(b"\x1b", Binding::Function(Kind::ViEscape));
+
+        Self {
+            emacs: Keymap::from_table(|_| true),
+            vi_insert,
+            // Command mode only needs the special keys, any other key is a
+            // command.
+            vi_command: Keymap::from_table(|seq| seq[0] == b'\x1b' && seq.len() > 1 && !is_meta(seq)),
+            keyseq_timeout: 500,
+            bracketed_paste: true,
+            confirm_paste: false,
+            completion_query_items: 100,
+            word_delimiters: WORD_DELIMITERS.into(),
+            unix_word_delimiters: UNIX_WORD_DELIMITERS.into(),
+            editing_mode: None,
+            reading: Vec::new(),
+        }
+    }
+
+    /// The names `-m` and `set keymap` take.
+    pub fn get(&self, name: &str) -> Option<&Keymap> {
+        match name {
+            "emacs" | "emacs-standard" => Some(&self.emacs),
+            "vi-insert" => Some(&self.vi_insert),
+            "vi" | "vi-command" | "vi-move" => Some(&self.vi_command),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Keymap> {
+        match name {
+            "emacs" | "emacs-standard" => Some(&mut self.emacs),
+            "vi-insert" => Some(&mut self.vi_insert),
+            "vi" | "vi-command" | "vi-move" => Some(&mut self.vi_command),
+            _ => None,
+        }
+    }
+
+    /// The names of the functions keys can be bound to.
+    pub fn function_names() -> Vec<&'static str> {
+        Kind::names()
+    }
+
+    /// Applies a line of an inputrc file: a binding like `"\C-xu": undo`,
+    /// `Meta-b: backward-word` or `"\ew": "macro"`, or a `set` of
+    /// `keymap`, `editing-mode`, `keyseq-timeout`, `enable-bracketed-paste`,
+    /// `confirm-paste`, `completion-query-items`, `word-delimiters` or
+    /// `unix-word-delimiters`. `keymap` is the name of the keymap bindings go
+    /// to.
+    pub fn parse_line(&mut self, line: &str, keymap: &mut String) -> Result<(), String> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(());
+        }
+
+        if let Some(rest) = line.strip_prefix("set") {
+            if rest.starts_with(char::is_whitespace) {
+                return self.set(rest.trim(), keymap);
+            }
+        }
+
+        let (seq, rest) = if line.starts_with('"') {
+            let (quoted, rest) = split_quoted(line)?;
+            let rest = rest.trim_start();
+            match rest.strip_prefix(':') {
+                Some(rest) => (parse_keyseq(quoted)?, rest),
+                None => return Err(format!("{}: missing `:'", line)),
+            }
+        } else {
+            // `:` itself can't be a key name, so the first one ends it.
+            match line.find(':') {
+                Some(i) => (parse_keyname(line[..i].trim())?, &line[i + 1..]),
+                None => return Err(format!("{}: missing `:'", line)),
+            }
+        };
+        if seq.is_empty() {
+            return Err(format!("{}: empty key sequence", line));
+        }
+
+        let rest = rest.trim();
+        let binding = if rest.starts_with('"') || rest.starts_with('\'') {
+            Binding::Macro(parse_keyseq(split_quoted(rest)?.0)?)
+        } else {
+            let name = rest.split_whitespace().next().unwrap_or("");
+            match Kind::from_name(name) {
+                Some(kind) => Binding::Function(kind),
+                None => return Err(format!("{}: unknown function name", name)),
+            }
+        };
+
+        match self.get_mut(keymap) {
+            Some(map) => map.bind(&seq, binding),
+            None => return Err(format!("{}: invalid keymap name", keymap)),
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, setting: &str, keymap: &mut String) -> Result<(), String> {
+        let mut words = setting.split_whitespace();
+        let name = words.next().unwrap_or("");
+        let value = words.next().unwrap_or("");
+        match name {
+            "keymap" => {
+                if self.get(value).is_none() {
+                    return Err(format!("{}: invalid keymap name", value));
+                }
+                *keymap = value.into();
+            }
+            "editing-mode" => {
+                let mode = match value {
+                    "emacs" => EditingMode::Emacs,
+                    "vi" => EditingMode::Vi,
+                    _ => return Err(format!("{}: invalid editing mode", value)),
+                };
+                *keymap = keymap_name(mode).into();
+                self.editing_mode = Some(mode);
+            }
+            "keyseq-timeout" => match value.parse() {
+                Ok(ms) => self.keyseq_timeout = ms,
+                Err(_) => return Err(format!("{}: invalid timeout", value)),
+            },
+            "enable-bracketed-paste" => self.bracketed_paste = is_on(value),
+            "confirm-paste" => self.confirm_paste = is_on(value),
+            // A negative number never asks.
+            "completion-query-items" => match value.parse::<i64>() {
+                Ok(n) if n < 0 => self.completion_query_items = usize::MAX,
+                Ok(n) => self.completion_query_items = n as usize,
+                Err(_) => return Err(format!("{}: invalid number", value)),
+            },
+            "word-delimiters" => self.word_delimiters = parse_delimiters(setting[name.len()..].trim())?,
+            "unix-word-delimiters" => {
+                self.unix_word_delimiters = parse_delimiters(setting[name.len()..].trim())?;
+            }
+            // Readline has many more settings, which don't apply here.
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Applies the lines of an inputrc file. `$if`, `$else`, `$endif` and
+    /// `$include` work as in Readline, where `$if` tests `mode=`, `term=` or
+    /// the application name. Returns an error for every bad line.
+    pub fn read_str(&mut self, text: &str, file: &str, keymap: &mut String) -> Vec<String> {
+        let mut errors = Vec::new();
+        // Whether each enclosing `$if` branch is taken.
+        let mut conditions: Vec<bool> = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let active = conditions.iter().all(|c| *c);
+            if let Some(directive) = line.strip_prefix('$') {
+                let mut words = directive.splitn(2, char::is_whitespace);
+                let arg = words.nth(1).unwrap_or("").trim();
+                match directive.split_whitespace().next().unwrap_or("") {
+                    "if" => conditions.push(self.test(arg)),
+                    "else" => {
+                        if let Some(c) = conditions.last_mut() {
+                            *c = !*c;
+                        }
+                    }
+                    "endif" => {
+                        conditions.pop();
+                    }
+                    "include" if active => {
+                        errors.extend(self.read_file(&expand_home(arg), keymap));
+                    }
+                    "include" => (),
+                    d => errors.push(format!("{}: line {}: ${}: unknown directive", file, i + 1, d)),
+                }
+                continue;
+            }
+            if active {
+                if let Err(e) = self.parse_line(line, keymap) {
+                    errors.push(format!("{}: line {}: {}", file, i + 1, e));
+                }
+            }
+        }
+        errors
+    }
+
+    /// Applies an inputrc file. One that includes itself, directly or not,
+    /// is an error.
+    pub fn read_file(&mut self, path: &Path, keymap: &mut String) -> Vec<String> {
+        let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        if self.reading.contains(&canonical) {
+            return vec![format!("{}: included recursively", path.display())];
+        }
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => return vec![format!("{}: {}", path.display(), e)],
+        };
+        self.reading.push(canonical);
+        let errors = self.read_str(&text, &path.display().to_string(), keymap);
+        self.reading.pop();
+        errors
+    }
+
+    /// Reads `$INPUTRC`, or `~/.inputrc`, if there is one.
+    pub fn read_inputrc(&mut self) -> Vec<String> {
+        let path = match env::var("INPUTRC") {
+            Ok(path) => PathBuf::from(path),
+            Err(_) => expand_home("~/.inputrc"),
+        };
+        if !path.exists() {
+            return Vec::new();
+        }
+        let mut keymap = "emacs".into();
+        self.read_file(&path, &mut keymap)
+    }
+
+    fn test(&self, condition: &str) -> bool {
+        if let Some(mode) = condition.strip_prefix("mode=") {
+            let current = match self.editing_mode {
+                Some(EditingMode::Vi) => "vi",
+                _ => "emacs",
+            };
+            mode == current
+        } else if let Some(term) = condition.strip_prefix("term=") {
+            let current = env::var("TERM").unwrap_or_default();
+            current == term || current.split('-').next() == Some(term)
+        } else {
+            condition.eq_ignore_ascii_case("mican")
+        }
+    }
+}
+
+/// How Readline reads a boolean setting.
+fn is_on(value: &str) -> bool {
+    value.eq_ignore_ascii_case("on") || value == "1"
+}
+
+/// The keymap text is typed in with `mode`.
+pub fn keymap_name(mode: EditingMode) -> &'static str {
+    match mode {
+        EditingMode::Emacs => "emacs",
+        EditingMode::Vi => "vi-insert",
+    }
+}
+
+/// Whether `seq` is Escape followed by a key, rather than the escape
+/// sequence of a special key like `\e[A`.
+fn is_meta(seq: &[u8]) -> bool {
+    seq.len() > 1 && seq[0] == b'\x1b' && seq[1] != b'[' && seq[1] != b'O'
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var("HOME")) {
+        (Some(rest), Ok(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// The characters a delimiters setting gives, quoted like a macro when
+/// they include whitespace.
+fn parse_delimiters(value: &str) -> Result<String, String> {
+    let delimiters = if value.starts_with('"') {
+        String::from_utf8(parse_keyseq(split_quoted(value)?.0)?).map_err(|e| e.to_string())?
+    } else {
+        value.into()
+    };
+    if delimiters.is_empty() {
+        return Err("missing delimiters".into());
+    }
+    Ok(delimiters)
+}
+
+/// Splits off the text between the quote `s` starts with and the matching
+/// closing one.
+fn split_quoted(s: &str) -> Result<(&str, &str), String> {
+    let quote = s.chars().next().unwrap_or('"');
+    let mut escaped = false;
+    for (i, c) in s.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            _ if c == quote => return Ok((&s[1..i], &s[i + 1..])),
+            _ => (),
+        }
+    }
+    Err(format!("{}: no closing `{}'", s, quote))
+}
+
+/// Parses a key sequence or macro in the quoted notation: `\C-x` for
+/// Control, `\M-x` for Meta, `\e` for Escape, the C escapes, and octal
+/// `\nnn` and hex `\xHH` codes.
+pub fn parse_keyseq(s: &str) -> Result<Vec<u8>, String> {
+    let mut chars = s.chars().peekable();
+    let mut keys = Vec::new();
+    while chars.peek().is_some() {
+        keys.extend(parse_key(&mut chars)?);
+    }
+    Ok(keys)
+}
+
+fn parse_key(chars: &mut ::std::iter::Peekable<::std::str::Chars>) -> Result<Vec<u8>, String> {
+    let c = chars.next().unwrap();
+    if c != '\\' {
+        return Ok(c.to_string().into_bytes());
+    }
+
+    let c = match chars.next() {
+        Some(c) => c,
+        None => return Ok(vec![b'\\']),
+    };
+    let byte = match c {
+        'C' | 'M' if chars.peek() == Some(&'-') => {
+            chars.next();
+            if chars.peek().is_none() {
+                return Err(format!("\\{}-: missing key", c));
+            }
+            let key = parse_key(chars)?;
+            return Ok(if c == 'C' {
+                key.iter().map(|b| control(*b)).collect()
+            } else {
+                Some(b'\x1b').into_iter().chain(key).collect()
+            });
+        }
+        'e' => 0x1b,
+        'a' => 0x07,
+        'b' => 0x08,
+        'd' => 0x7f,
+        'f' => 0x0c,
+        'n' => b'\n',
+        'r' => b'\r',
+        't' => b'\t',
+        'v' => 0x0b,
+        '0'..='7' => {
+            let mut n = c.to_digit(8).unwrap();
+            for _ in 0..2 {
+                match chars.peek().and_then(|c| c.to_digit(8)) {
+                    Some(d) => {
+                        n = n * 8 + d;
+                        chars.next();
+                    }
+                    None => break,
+                }
+            }
+            n as u8
+        }
+        'x' => {
+            let mut n = 0;
+            for _ in 0..2 {
+                match chars.peek().and_then(|c| c.to_digit(16)) {
+                    Some(d) => {
+                        n = n * 16 + d;
+                        chars.next();
+                    }
+                    None => break,
+                }
+            }
+            n as u8
+        }
+        c => return Ok(c.to_string().into_bytes()),
+    };
+    Ok(vec![byte])
+}
+
+fn control(b: u8) -> u8 {
+    match b {
+        b'?' => 0x7f,
+        _ => b.to_ascii_uppercase() & 0x1f,
+    }
+}
+
+/// Parses an unquoted key name like `Control-u`, `C-M-b` or `Meta-Rubout`.
+pub fn parse_keyname(s: &str) -> Result<Vec<u8>, String> {
+    let (mut ctrl, mut meta) = (false, false);
+    let mut rest = s;
+    loop {
+        let lower = rest.to_ascii_lowercase();
+        if lower.starts_with("control-") {
+            rest = &rest["control-".len()..];
+            ctrl = true;
+        } else if lower.starts_with("c-") && rest.len() > 2 {
+            rest = &rest[2..];
+            ctrl = true;
+        } else if lower.starts_with("meta-") {
+            rest = &rest["meta-".len()..];
+            meta = true;
+        } else if lower.starts_with("m-") && rest.len() > 2 {
+            rest = &rest[2..];
+            meta = true;
+        } else {
+            break;
+        }
+    }
+
+    let key = match rest.to_ascii_lowercase().as_str() {
+        "del" | "rubout" => 0x7f,
+        "esc" | "escape" => 0x1b,
+        "lfd" | "newline" => b'\n',
+        "ret" | "return" => b'\r',
+        "spc" | "space" => b' ',
+        "tab" => b'\t',
+        _ if rest.len() == 1 => rest.as_bytes()[0],
+        _ => return Err(format!("{}: unknown key name", s)),
+    };
+    let key = if ctrl { control(key) } else { key };
+    Ok(if meta { vec![b'\x1b', key] } else { vec![key] })
+}
+
+/// Writes a key sequence back in the quoted notation.
+pub fn format_keyseq(keys: &[u8]) -> String {
+    let mut s = String::new();
+    for &b in keys {
+        match b {
+            0x1b => s.push_str("\\e"),
+            0x7f => s.push_str("\\C-?"),
+            0x1c => s.push_str("\\C-\\\\"),
+            0x01..=0x1a => {
+                s.push_str("\\C-");
+                s.push((b + 0x60) as char);
+            }
+            0x00..=0x1f => {
+                s.push_str("\\C-");
+                s.push((b + 0x40) as char);
+            }
+            b'"' => s.push_str("\\\""),
+            b'\\' => s.push_str("\\\\"),
+            0x20..=0x7e => s.push(b as char),
+            _ => s.push_str(&format!("\\{:03o}", b)),
+        }
+    }
+    s
+}
+
+/// The default bindings, which are emacs's.
+const EMACS: &[(&[u8], Kind)] = &[
+    (b"\r"      , Kind::Enter),           // Enter
+    (b"\x1b\r"  , Kind::InsertNewline),   // Alt-Enter
+    (b"\x7f"    , Kind::Delete),          // BackSpace
+    (b"\x1b[A"  , Kind::PreviousHistory), // Up
+    (b"\x1b[B"  , Kind::NextHistory),     // Down
+    (b"\x1b[C"  , Kind::ForwardChar),     // Right
+    (b"\x1b[D"  , Kind::BackwardChar),    // Left
+    (b"\x1bOA"  , Kind::PreviousHistory), // Up
+    (b"\x1bOB"  , Kind::NextHistory),     // Down
+    (b"\x1bOC"  , Kind::ForwardChar),     // Right
+    (b"\x1bOD"  , Kind::BackwardChar),    // Left
+    (b"\x1b[3~" , Kind::DeleteChar),      // Delete
+    (b"\x1b[H"  , Kind::BeginningOFLine), // Home
+    (b"\x1bOH"  , Kind::BeginningOFLine), // Home
+    (b"\x1b[1~" , Kind::BeginningOFLine), // Home
+    (b"\x1b[7~" , Kind::BeginningOFLine), // Home
+    (b"\x1b[F"  , Kind::EndOfLine),       // End
+    (b"\x1bOF"  , Kind::EndOfLine),       // End
+    (b"\x1b[4~" , Kind::EndOfLine),       // End
+    (b"\x1b[8~" , Kind::EndOfLine),       // End
+    (b"\x1b[5~" , Kind::MenuPageUp),      // Page Up
+    (b"\x1b[6~" , Kind::MenuPageDown),    // Page Down
+
+    (b"\t"      , Kind::Complete),        // Tab
+    (b"\x1b[200~", Kind::BracketedPaste), // Start of pasted text
+
+    (b"\x01"    , Kind::BeginningOFLine), // Ctrl-A
+    (b"\x02"    , Kind::BackwardChar),    // Ctrl-B
+    (b"\x04"    , Kind::DeleteCharOrEof), // Ctrl-D
+    (b"\x05"    , Kind::EndOfLine),       // Ctrl-E
+    (b"\x06"    , Kind::ForwardChar),     // Ctrl-F
+    (b"\x07"    , Kind::Something),       // Ctrl-G
+    (b"\x08"    , Kind::Delete),          // Ctrl-H
+    (b"\x0a"    , Kind::Enter),           // Ctrl-J
+    (b"\x0b"    , Kind::KillLine),        // Ctrl-K
+    (b"\x0c"    , Kind::CtrlL),           // Ctrl-L
+    (b"\x0e"    , Kind::NextHistory),     // Ctrl-N
+    (b"\x10"    , Kind::PreviousHistory), // Ctrl-P
+    (b"\x12"    , Kind::Something),       // Ctrl-R
+    (b"\x14"    , Kind::TransposeChars),  // Ctrl-T
+    (b"\x15"    , Kind::UnixLineDiscard), // Ctrl-U
+    (b"\x17"    , Kind::UnixWordRubout),  // Ctrl-W
+    (b"\x19"    , Kind::Yank),            // Ctrl-Y
+    (b"\x18\x15", Kind::Undo),            // Ctrl-X, Ctrl-U
+    (b"\x1d"    , Kind::Something),       // Ctrl-]
+    (b"\x1f"    , Kind::Undo),            // Ctrl-_
+    (b"\x1b_"   , Kind::Redo),            // Escape, _
+    (b"\x1b\x08", Kind::BackwardKillWord), // Escape, Ctrl-H
+    (b"\x1b\x1d", Kind::Something),       // Escape, Ctrl-]
+    (b"\x1b\x7f", Kind::BackwardKillWord), // Escape, Rubout
+    (b"\x1bb"   , Kind::BackwardWord),    // Escape, b
+    (b"\x1bc"   , Kind::CapitalizeWord),  // Escape, c
+    (b"\x1bd"   , Kind::KillWord),        // Escape, d
+    (b"\x1bf"   , Kind::ForwardWord),     // Escape, f
+    (b"\x1bl"   , Kind::DowncaseWord),    // Escape, l
+    (b"\x1bt"   , Kind::TransposeWords),  // Escape, t
+    (b"\x1bu"   , Kind::UpcaseWord),      // Escape, u
+    (b"\x1by"   , Kind::YankPop),         // Escape, y
+    (b"\x1b#"   , Kind::Something),       // Escape, #
+    (b"\x1b<"   , Kind::BeginningOfHistory), // Escape, <
+    (b"\x1b>"   , Kind::EndOfHistory),    // Escape, >
+];
+
+#[test]
+fn test_parse_keyseq() {
+    assert_eq!(parse_keyseq("\\C-x\\C-u").unwrap(), b"\x18\x15");
+    assert_eq!(parse_keyseq("\\M-b").unwrap(), b"\x1bb");
+    assert_eq!(parse_keyseq("\\e[A").unwrap(), b"\x1b[A");
+    assert_eq!(parse_keyseq("\\C-?").unwrap(), b"\x7f");
+    assert_eq!(parse_keyseq("\\C-\\M-h").unwrap(), b"\x1b\x08");
+    assert_eq!(parse_keyseq("\\t\\\"\\\\\\033\\x41").unwrap(), b"\t\"\\\x1bA");
+    assert_eq!(parse_keyseq("ls -l\\r").unwrap(), b"ls -l\r");
+
+    assert_eq!(parse_keyname("Control-u").unwrap(), b"\x15");
+    assert_eq!(parse_keyname("C-M-b").unwrap(), b"\x1b\x02");
+    assert_eq!(parse_keyname("Meta-Rubout").unwrap(), b"\x1b\x7f");
+    assert_eq!(parse_keyname("TAB").unwrap(), b"\t");
+    assert!(parse_keyname("Hyper-x").is_err());
+
+    for keys in &[&b"\x18\x15"[..], b"\x1b[3~", b"\x7f\x00\x1c\x1f", b"a\"\\b"] {
+        assert_eq!(parse_keyseq(&format_keyseq(keys)).unwrap(), *keys);
+    }
+    assert_eq!(format_keyseq(b"\x1bb\x01"), "\\eb\\C-a");
+}
+
+#[test]
+fn test_lookup() {
+    let mut keymap = Keymap::new();
+    keymap.bind(b"\x1b", Binding::Function(Kind::ViEscape));
+    keymap.bind(b"\x1b[A", Binding::Function(Kind::PreviousHistory));
+    keymap.bind(b"\x18\x15", Binding::Function(Kind::Undo));
+
+    let up = Binding::Function(Kind::PreviousHistory);
+    let escape = Binding::Function(Kind::ViEscape);
+    assert_eq!(keymap.lookup(b"", false), Match::Incomplete);
+    assert_eq!(keymap.lookup(b"\x1b[Ax", false), Match::Bound(3, &up));
+    assert_eq!(keymap.lookup(b"\x1b[", false), Match::Incomplete);
+    assert_eq!(keymap.lookup(b"\x1b[", true), Match::Bound(1, &escape));
+    assert_eq!(keymap.lookup(b"\x1bx", false), Match::Bound(1, &escape));
+    assert_eq!(keymap.lookup(b"\x18", false), Match::Incomplete);
+    assert_eq!(keymap.lookup(b"\x18", true), Match::Unbound);
+    assert_eq!(keymap.lookup(b"\x18x", false), Match::Unbound);
+    assert_eq!(keymap.lookup(b"abc", false), Match::Unbound);
+
+    assert!(keymap.unbind(b"\x1b"));
+    assert!(!keymap.unbind(b"\x1b["));
+    assert_eq!(keymap.lookup(b"\x1bx", false), Match::Unbound);
+    assert_eq!(keymap.keys_for("previous-history"), vec![b"\x1b[A".to_vec()]);
+}
+
+#[test]
+fn test_parse_line() {
+    let mut keymaps = Keymaps::new();
+    let mut keymap = "emacs".to_string();
+    keymaps.parse_line("\"\\C-xr\": redo", &mut keymap).unwrap();
+    keymaps.parse_line("Meta-g: backward-word  # comment", &mut keymap).unwrap();
+    keymaps.parse_line("\"\\ew\": \"ls -l\\r\"", &mut keymap).unwrap();
+    keymaps.parse_line("set keyseq-timeout 100", &mut keymap).unwrap();
+    keymaps.parse_line("set enable-bracketed-paste Off", &mut keymap).unwrap();
+    keymaps.parse_line("set confirm-paste on", &mut keymap).unwrap();
+    keymaps.parse_line("set completion-query-items 50", &mut keymap).unwrap();
+    assert!(keymaps.parse_line("\"\\C-xq\": no-such-function", &mut keymap).is_err());
+    assert!(keymaps.parse_line("\"\\C-xq\" undo", &mut keymap).is_err());
+
+    let emacs = &keymaps.emacs;
+    assert_eq!(emacs.lookup(b"\x18r", false), Match::Bound(2, &Binding::Function(Kind::Redo)));
+    assert_eq!(emacs.lookup(b"\x1bg", false), Match::Bound(2, &Binding::Function(Kind::BackwardWord)));
+    assert_eq!(emacs.lookup(b"\x1bw", false), Match::Bound(2, &Binding::Macro(b"ls -l\r".to_vec())));
+    assert_eq!(keymaps.keyseq_timeout, 100);
+    assert!(!keymaps.bracketed_paste);
+    assert!(keymaps.confirm_paste);
+    assert_eq!(keymaps.completion_query_items, 50);
+    keymaps.parse_line("set completion-query-items -1", &mut keymap).unwrap();
+    assert_eq!(keymaps.completion_query_items, usize::MAX);
+    keymaps.parse_line("set word-delimiters \" \\t/\"", &mut keymap).unwrap();
+    assert_eq!(keymaps.word_delimiters, " \t/");
+    keymaps.parse_line("set unix-word-delimiters /", &mut keymap).unwrap();
+    assert_eq!(keymaps.unix_word_delimiters, "/");
+    assert!(keymaps.parse_line("set word-delimiters", &mut keymap).is_err());
+
+    keymaps.parse_line("set keymap vi-command", &mut keymap).unwrap();
+    keymaps.parse_line("\"\\C-a\": beginning-of-line", &mut keymap).unwrap();
+    assert_eq!(keymap, "vi-command");
+    assert_eq!(keymaps.vi_command.keys_for("beginning-of-line")[0], b"\x01");
+}
+
+#[test]
+fn test_read_str() {
+    let mut keymaps = Keymaps::new();
+    let mut keymap = "emacs".to_string();
+    let text = "set editing-mode vi\n\
+                $if mode=vi\n\
+                \"\\C-xa\": end-of-line\n\
+                $else\n\
+                \"\\C-xb\": end-of-line\n\
+                $endif\n\
+                $if OtherShell\n\
+                \"\\C-xc\": end-of-line\n\
+                $endif\n\
+                \"\\C-xd\": bogus\n";
+    let errors = keymaps.read_str(text, "inputrc", &mut keymap);
+    assert_eq!(errors, vec!["inputrc: line 10: bogus: unknown function name".to_string()]);
+    assert_eq!(keymaps.editing_mode, Some(EditingMode::Vi));
+    assert_eq!(keymaps.vi_insert.keys_for("end-of-line").iter().filter(|k| k[0] == 0x18).count(), 1);
+    assert!(keymaps.emacs.keys_for("end-of-line").iter().all(|k| k[0] != 0x18));
+}
+
+#[test]
+fn test_read_file() {
+    use testing::TempDir;
+
+    let dir = TempDir::new("inputrc");
+    let (first, second) = (dir.join("first"), dir.join("second"));
+    fs::write(&first, format!("$include {}\nset bell-style none\n", second.display())).unwrap();
+    fs::write(&second, format!("$include {}\n", first.display())).unwrap();
+
+    let mut keymaps = Keymaps::new();
+    let mut keymap = "emacs".to_string();
+    let errors = keymaps.read_file(&first, &mut keymap);
+    assert_eq!(errors, vec![format!("{}: included recursively", first.display())]);
+    // The same file may still be read again afterwards.
+    assert_eq!(keymaps.read_file(&second, &mut keymap).len(), 1);
+}
@@ -0,0 +1,81 @@
+//! Text killed by `Ctrl-K`, `Ctrl-U`, `Ctrl-W` and `Alt-D`, for `Ctrl-Y` and
+//! `Alt-Y` to yank back.
+
+use std::collections::VecDeque;
+
+/// How many kills are remembered.
+const MAX: usize = 32;
+
+#[derive(Debug, Default)]
+pub struct KillRing {
+    /// Newest kill first.
+    ring: VecDeque<String>,
+    /// The entry the last yank inserted, for `yank_pop`.
+    index: usize,
+}
+
+impl KillRing {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Saves killed text. When the previous command was a kill too, the
+    /// text joins the newest entry instead, before it when killing
+    /// backward and after it otherwise.
+    pub fn kill(&mut self, text: &str, backward: bool, append: bool) {
+        if text.is_empty() {
+            return;
+        }
+
+        match self.ring.front_mut() {
+            Some(last) if append => {
+                if backward {
+                    last.insert_str(0, text);
+                } else {
+                    last.push_str(text);
+                }
+            }
+            _ => {
+                self.ring.push_front(text.into());
+                self.ring.truncate(MAX);
+            }
+        }
+        self.index = 0;
+    }
+
+    /// The newest kill.
+    pub fn yank(&mut self) -> Option<&str> {
+        self.index = 0;
+        self.ring.front().map(|s| s.as_str())
+    }
+
+    /// The entry the last yank or yank-pop inserted.
+    pub fn current(&self) -> Option<&str> {
+        self.ring.get(self.index).map(|s| s.as_str())
+    }
+
+    /// The kill before the one yanked last, going round to the newest
+    /// after the oldest.
+    pub fn yank_pop(&mut self) -> Option<&str> {
+        if self.ring.is_empty() {
+            return None;
+        }
+        self.index = (self.index + 1) % self.ring.len();
+        self.ring.get(self.index).map(|s| s.as_str())
+    }
+}
+
+#[test]
+fn test_kill_ring() {
+    let mut ring = KillRing::new();
+    assert_eq!(ring.yank(), None);
+
+    ring.kill("foo", false, false);
+    ring.kill(" bar", false, true);
+    ring.kill("baz ", true, false);
+    ring.kill("qux ", true, true);
+    assert_eq!(ring.yank(), Some("qux baz "));
+    assert_eq!(ring.yank_pop(), Some("foo bar"));
+    assert_eq!(ring.current(), Some("foo bar"));
+    assert_eq!(ring.yank_pop(), Some("qux baz "));
+}
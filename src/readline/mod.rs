@@ -5,6 +5,13 @@ mod terminal;
 mod history;
 mod editor;
 mod event;
+mod help;
 mod color;
 mod signal;
 mod buffer;
+mod kill_ring;
+mod undo;
+mod vi;
+mod width;
+mod tty;
+pub mod keymap;
@@ -1,185 +1,468 @@
-use std::borrow::Cow;
+use std::cell::RefCell;
+use std::env;
 use std::io;
-use std::os::unix::io::AsRawFd;
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::rc::Rc;
 
 use nix;
-use nix::sys::termios::{tcgetattr, tcsetattr, SetArg, LocalFlags, InputFlags,
-                        SpecialCharacterIndices};
-use nix::sys::select::{select, FdSet};
-use nix::unistd::read;
+use nix::errno::Errno;
+use nix::libc::{STDERR_FILENO, STDIN_FILENO, STDOUT_FILENO};
+use nix::poll::{poll, EventFlags, PollFd};
+use nix::unistd::{isatty, read};
 
 use readline::event::Kind as EventKind;
 use readline::event::Event;
-use readline::context::Context;
+use readline::editor::Complete;
+use readline::completer::Matching;
+use readline::context::{Context, EditingMode, Mode};
+use readline::keymap::{Binding, Keymaps, Match};
+use readline::terminal::{self, CursorShape};
 use readline::signal;
+use readline::tty;
+use readline::width::strip_escapes;
+
+/// How many macros can expand in a row without a key being typed.
+const MAX_MACROS: usize = 100;
+
+/// What the terminal sends after pasted text.
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+/// What waiting for input ended with.
+#[derive(Debug, PartialEq)]
+enum Wait {
+    Input,
+    Signal,
+    Timeout,
+}
+
+/// How much of a terminal there is to edit lines on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Term {
+    /// Full line editing.
+    Full,
+    /// `TERM=dumb`, which can't move the cursor: a prompt, and the line
+    /// editing of the terminal itself.
+    Dumb,
+    /// Not a terminal at all: plain lines and no prompt.
+    Plain,
+}
 
 pub struct Reader {
     context: Context,
-    /// Key bindings.
-    bindings: Vec<(Cow<'static, [u8]>, EventKind)>,
+    term: Term,
+    /// Where keys are read from.
+    input_fd: RawFd,
+    /// Where the line is drawn: standard output, or standard error when
+    /// the output is redirected.
+    output_fd: RawFd,
+    keymaps: Rc<RefCell<Keymaps>>,
+    /// Input not handled yet: the rest of a read that held several keys, or
+    /// the start of a key sequence waiting for the rest.
+    input: Vec<u8>,
 }
 
 impl Reader {
-    pub fn new(con: Context) -> Self {
-        settings_term();
+    pub fn new(mut con: Context, keymaps: Rc<RefCell<Keymaps>>) -> Self {
+        let input_fd = STDIN_FILENO;
+        let is_tty = |fd| isatty(fd).unwrap_or(false);
+        let output_fd = if is_tty(STDOUT_FILENO) { STDOUT_FILENO } else { STDERR_FILENO };
+        let term = if !is_tty(input_fd) || !is_tty(output_fd) {
+            Term::Plain
+        } else if env::var("TERM").ok().as_deref() == Some("dumb") {
+            Term::Dumb
+        } else {
+            Term::Full
+        };
+
+        if term == Term::Full {
+            if let Err(e) = tty::save(input_fd) {
+                eprintln!("mican: {}", e);
+            }
+            con.editor.set_output(output_fd);
+            con.editor.export_win_size();
+        }
         Self {
-            bindings: bindings(),
             context: con,
+            term,
+            input_fd,
+            output_fd,
+            keymaps,
+            input: Vec::new(),
         }
     }
 
+    /// Whether lines come from someone at a terminal.
+    pub fn is_interactive(&self) -> bool {
+        self.term != Term::Plain
+    }
+
+    /// Switches between emacs and vi key bindings.
+    pub fn set_editing_mode(&mut self, mode: EditingMode) {
+        self.context.editing_mode = mode;
+    }
+
+    /// Picks candidates for completion this way.
+    pub fn set_matching(&mut self, matching: Matching) {
+        self.context.editor.set_completion_matching(matching);
+    }
+
     /// Interactively reads a line from `stdin`.
-    /// When an interrupt intervened, return None.
-    pub fn read_line(&mut self) -> Option<String> {
+    /// When an interrupt intervened, return None. Ctrl-D on an empty line
+    /// is an `UnexpectedEof` error.
+    pub fn read_line(&mut self) -> io::Result<Option<String>> {
+        if self.term != Term::Full {
+            return self.read_plain_line();
+        }
+
+        tty::raw_mode().map_err(nix_error)?;
+        self.context.mode = self.context.insert_mode();
+        if self.context.editing_mode == EditingMode::Vi {
+            self.context.editor.set_cursor_shape(CursorShape::Bar);
+        }
+        {
+            let keymaps = self.keymaps.borrow();
+            self.context.editor.set_completion_query_items(keymaps.completion_query_items);
+            self.context.word_delimiters = keymaps.word_delimiters.clone();
+            self.context.unix_word_delimiters = keymaps.unix_word_delimiters.clone();
+        }
+        let bracketed_paste = self.keymaps.borrow().bracketed_paste;
+        if bracketed_paste {
+            self.context.editor.set_bracketed_paste(true);
+        }
+
+        let line = self.read_line_in_mode();
+
+        // Commands run with the terminal as it was.
+        if self.context.editing_mode == EditingMode::Vi {
+            self.context.editor.set_cursor_shape(CursorShape::Default);
+        }
+        if bracketed_paste {
+            self.context.editor.set_bracketed_paste(false);
+        }
+        self.context.editor.display()?;
+        tty::restore().map_err(nix_error)?;
+        line
+    }
+
+    fn read_line_in_mode(&mut self) -> io::Result<Option<String>> {
         self.context.editor.write_prompt();
-        self.context.editor.display().unwrap();
+        self.context.editor.display()?;
 
-        signal::prepare().unwrap();
+        signal::prepare()?;
 
+        // Whether the wait for the rest of a key sequence ran out.
+        let mut timed_out = false;
+        // Macros expanded since the last read, to stop one that runs itself.
+        let mut macros = 0;
         loop {
             // Received a something signal.
-            if let Some(_sig) = signal::take() {
-                let e = Event::from_event_kind(&Some(EventKind::Interrupt));
-                if let Ok(Some(line)) = (e.handler)(&mut self.context, Vec::new()) {
-                    self.context.editor.display().unwrap();
-                    return Some(line);
+            if let Some(sig) = signal::take() {
+                let kind = match sig {
+                    signal::Signal::WindowChange => EventKind::Resize,
+                    _ => EventKind::Interrupt,
+                };
+                let e = Event::from_event_kind(&Some(kind.clone()));
+                let line = (e.handler)(&mut self.context, Vec::new())?;
+                self.context.editor.display()?;
+                if line.is_some() {
+                    return Ok(line);
+                }
+                if let EventKind::Interrupt = kind {
+                    self.input.clear();
+                    return Ok(None);
+                }
+                continue;
+            }
+
+            let (len, binding) = match self.next_key(timed_out) {
+                Some(key) => key,
+                None => {
+                    // Only wait a moment for the rest of a key sequence.
+                    let timeout = if self.input.is_empty() {
+                        None
+                    } else {
+                        Some(self.keymaps.borrow().keyseq_timeout)
+                    };
+                    match self.wait_input(timeout)? {
+                        Wait::Input => {
+                            self.read_char()?;
+                            timed_out = false;
+                            macros = 0;
+                        }
+                        Wait::Timeout => timed_out = true,
+                        Wait::Signal => (),
+                    }
+                    continue;
+                }
+            };
+            timed_out = false;
+            let mut keys: Vec<u8> = self.input.drain(..len).collect();
+            if self.context.mode == Mode::CompletionQuery {
+                self.answer_completion_query(&keys);
+                self.context.editor.display()?;
+                continue;
+            }
+            if let Some(Binding::Function(EventKind::BracketedPaste)) = binding {
+                keys = self.read_paste()?;
+                if !self.confirm_paste(&keys)? {
+                    self.context.editor.display()?;
+                    continue;
+                }
+            }
+
+            let res = match binding {
+                Some(Binding::Macro(text)) => {
+                    macros += 1;
+                    if macros < MAX_MACROS {
+                        self.input.splice(0..0, text);
+                    }
+                    continue;
                 }
-                self.context.editor.display().unwrap();
-                return None;
+                Some(Binding::Function(EventKind::SelfInsert)) | None => {
+                    // Keys without bindings are commands in vi command mode.
+                    if self.context.mode == Mode::ViCommand {
+                        Some(EventKind::ViCommand)
+                    } else {
+                        None
+                    }
+                }
+                Some(Binding::Function(kind)) => Some(kind),
+            };
+
+            // Characters typed in a row are undone together, and so
+            // is everything typed in vi insert mode.
+            if self.context.mode != Mode::ViInsert &&
+                (res.is_some() || self.context.last_kind.is_some())
+            {
+                self.context.editor.close_undo_unit();
             }
+            let e = Event::from_event_kind(&res);
+            let line = (e.handler)(&mut self.context, keys);
+            self.context.last_kind = res;
+            self.context.editor.display()?;
+            if let Some(line) = line? {
+                return Ok(Some(line));
+            }
+        }
+    }
+
+    /// Reads a line as it comes, without escape sequences. Reads byte by
+    /// byte so that commands get the rest of a pipe.
+    fn read_plain_line(&mut self) -> io::Result<Option<String>> {
+        if self.term == Term::Dumb {
+            let prompt = strip_escapes(&self.context.editor.prompt);
+            terminal::write_all(self.output_fd, prompt.as_bytes())?;
+            signal::prepare()?;
+        }
 
-            if wait_input() {
-                let mut ch: Vec<u8> = Vec::new();
-                if self.read_char(&mut ch).is_ok() {
-                    let res = self.find_bind(&ch);
-                    let e = Event::from_event_kind(&res);
-                    if let Ok(Some(line)) = (e.handler)(&mut self.context, ch) {
-                        self.context.editor.display().unwrap();
-                        return Some(line);
+        let mut line = Vec::new();
+        let mut byte = [0];
+        loop {
+            match read(self.input_fd, &mut byte) {
+                Ok(0) if line.is_empty() => {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "end of input"));
+                }
+                Ok(0) => break,
+                Ok(_) if byte[0] == b'\n' => break,
+                Ok(_) => line.push(byte[0]),
+                Err(nix::Error::Sys(Errno::EINTR)) => {
+                    if let Some(signal::Signal::Interrupt) = signal::take() {
+                        terminal::write_all(self.output_fd, b"\n")?;
+                        return Ok(None);
                     }
-                    self.context.editor.display().unwrap();
                 }
+                Err(e) => return Err(nix_error(e)),
+            }
+        }
+        Ok(Some(String::from_utf8_lossy(&line).into_owned()))
+    }
+
+    /// Reads up to the end of a bracketed paste and returns the text pasted.
+    fn read_paste(&mut self) -> io::Result<Vec<u8>> {
+        loop {
+            let end = self
+                .input
+                .windows(PASTE_END.len())
+                .position(|w| w == PASTE_END);
+            if let Some(i) = end {
+                let text = self.input[..i].to_vec();
+                self.input.drain(..i + PASTE_END.len());
+                return Ok(text);
+            }
+            // Rather than wait for an end that may never come, a signal
+            // ends the paste.
+            match self.wait_input(None)? {
+                Wait::Input => {
+                    self.read_char()?;
+                }
+                _ => return Ok(mem::take(&mut self.input)),
             }
         }
     }
 
-    fn read_char(&self, buf: &mut Vec<u8>) -> nix::Result<usize> {
-        buf.reserve(32);
+    /// Asks before a paste of several lines goes in, with `confirm-paste`.
+    fn confirm_paste(&mut self, text: &[u8]) -> io::Result<bool> {
+        let lines = text
+            .split(|b| *b == b'\n' || *b == b'\r')
+            .filter(|line| !line.is_empty())
+            .count();
+        if lines < 2 || !self.keymaps.borrow().confirm_paste {
+            return Ok(true);
+        }
 
-        let len = buf.len();
-        let cap = buf.capacity();
-        let n;
+        self.context.editor.show_status(&format!("Paste {} lines? (y/n) ", lines));
+        self.context.editor.display()?;
+        let answer = loop {
+            if !self.input.is_empty() {
+                break self.input.remove(0);
+            }
+            match self.wait_input(None)? {
+                Wait::Input => {
+                    self.read_char()?;
+                }
+                _ => break b'n',
+            }
+        };
+        self.context.editor.redraw();
+        Ok(answer == b'y' || answer == b'Y')
+    }
 
-        unsafe {
-            buf.set_len(cap);
-            let result = retry(|| read(io::stdout().as_raw_fd(), &mut buf[len..]));
+    /// Shows the completion menu if `keys` say yes to `Display all N
+    /// possibilities?`, and goes back to the line if not.
+    fn answer_completion_query(&mut self, keys: &[u8]) {
+        self.context.editor.clear_below();
+        self.context.last_kind = None;
+        if keys == b"y" || keys == b"Y" || keys == b" " {
+            self.context.editor.completion_menu();
+            self.context.mode = Mode::Completion;
+        } else {
+            self.context.mode = self.context.insert_mode();
+        }
+    }
 
-            buf.set_len(len);
+    /// The length of the key at the start of the input and what it is bound
+    /// to, or None to read more first.
+    fn next_key(&self, timed_out: bool) -> Option<(usize, Option<Binding>)> {
+        let keymaps = self.keymaps.borrow();
+        let keymap = match self.context.mode {
+            Mode::ViCommand => &keymaps.vi_command,
+            Mode::ViInsert => &keymaps.vi_insert,
+            _ if self.context.editing_mode == EditingMode::Vi => &keymaps.vi_insert,
+            _ => &keymaps.emacs,
+        };
+        match keymap.lookup(&self.input, timed_out) {
+            Match::Bound(len, binding) => Some((len, Some(binding.clone()))),
+            Match::Incomplete => None,
+            // A key nothing is bound to, like F5, is dropped whole rather
+            // than typed in after its Escape.
+            Match::Unbound if self.input[0] == 0x1b && self.input.len() > 1 => {
+                let nothing = Some(Binding::Function(EventKind::Something));
+                match escape_len(&self.input) {
+                    Some(len) => Some((len, nothing)),
+                    None if timed_out => Some((self.input.len(), nothing)),
+                    None => None,
+                }
+            }
+            Match::Unbound => {
+                // One character, however many bytes it takes.
+                let len = char_len(self.input[0]);
+                if len <= self.input.len() {
+                    Some((len, None))
+                } else if timed_out {
+                    Some((self.input.len(), None))
+                } else {
+                    None
+                }
+            }
+        }
+    }
 
-            n = result?;
-            buf.set_len(len + n);
+    /// Waits until there is input or a signal, for `timeout` milliseconds
+    /// if given.
+    fn wait_input(&self, timeout: Option<u64>) -> io::Result<Wait> {
+        let mut fds = vec![PollFd::new(self.input_fd, EventFlags::POLLIN)];
+        if let Some(fd) = signal::fd() {
+            fds.push(PollFd::new(fd, EventFlags::POLLIN));
         }
+        let timeout = timeout.map_or(-1, |ms| ms as i32);
 
-        Ok(n)
+        let n = retry(|| poll(&mut fds, timeout)).map_err(nix_error)?;
+        let ready = |fd: &PollFd| fd.revents().is_some_and(|e| !e.is_empty());
+        Ok(if n == 0 {
+            Wait::Timeout
+        } else if fds.len() > 1 && ready(&fds[1]) {
+            Wait::Signal
+        } else {
+            // Hang-ups and errors are for `read` to report.
+            Wait::Input
+        })
     }
 
-    fn find_bind(&self, ch: &[u8]) -> Option<EventKind> {
-        for (ref bind, ref cmd) in &self.bindings {
-            if &Cow::Borrowed(ch) == bind {
-                return Some(cmd.clone());
+    /// Reads what is there to the end of `input`.
+    fn read_char(&mut self) -> io::Result<usize> {
+        let mut buf = [0; 256];
+        let input_fd = self.input_fd;
+        match retry(|| read(input_fd, &mut buf)).map_err(nix_error)? {
+            0 => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "end of input")),
+            n => {
+                self.input.extend_from_slice(&buf[..n]);
+                Ok(n)
             }
         }
-        None
     }
 }
 
-fn settings_term() {
-    let stdin_fileno = io::stdout().as_raw_fd();
-    let mut tio = tcgetattr(stdin_fileno).unwrap();
+/// How many bytes the UTF-8 character that starts with `byte` takes.
+fn char_len(byte: u8) -> usize {
+    match byte {
+        0xf0..=0xff => 4,
+        0xe0..=0xef => 3,
+        0xc0..=0xdf => 2,
+        _ => 1,
+    }
+}
 
-    tio.input_flags.remove(
-        InputFlags::INLCR | InputFlags::ICRNL,
-    );
-    tio.local_flags.remove(
-        LocalFlags::ICANON | LocalFlags::ECHO,
-    );
-    tio.control_chars[SpecialCharacterIndices::VMIN as usize] = 0;
-    tio.control_chars[SpecialCharacterIndices::VTIME as usize] = 0;
+/// The length of the escape sequence at the start of `input`: a CSI one up
+/// to its final byte, an SS3 key, or Escape and a character for an Alt-key.
+/// None if not all of it has come yet.
+fn escape_len(input: &[u8]) -> Option<usize> {
+    let len = match *input.get(1)? {
+        b'[' => 3 + input.get(2..)?.iter().position(|b| (0x40..=0x7e).contains(b))?,
+        b'O' => 3,
+        byte => 1 + char_len(byte),
+    };
+    if len <= input.len() {
+        Some(len)
+    } else {
+        None
+    }
+}
 
-    tcsetattr(stdin_fileno, SetArg::TCSANOW, &tio).unwrap();
+fn nix_error(e: nix::Error) -> io::Error {
+    io::Error::other(e)
 }
 
+/// Runs `f` again for as long as a signal interrupts it.
 fn retry<F, R>(mut f: F) -> nix::Result<R>
 where
     F: FnMut() -> nix::Result<R>,
 {
     loop {
-        match f() {                                                                                                                                                                                             
-            Err(_e) => (),                                                                                                                                         
-            res => return res,                                                                                                                                                                                   
-        }
-    }
-}
-
-#[cfg_attr(feature = "cargo-clippy", allow(never_loop))]
-fn wait_input() -> bool {
-    let stdin_fileno = io::stdout().as_raw_fd();
-    let mut r_fds = FdSet::new();
-    r_fds.insert(stdin_fileno);
-
-    let mut e_fds = FdSet::new();
-    r_fds.insert(stdin_fileno);
-
-    loop {
-        match select(
-            stdin_fileno + 1,
-            Some(&mut r_fds),
-            None,
-            Some(&mut e_fds),
-            None.as_mut(),
-        ) {
-            Ok(n) => return n == 1,
-            Err(_e) => return false,
+        match f() {
+            Err(nix::Error::Sys(Errno::EINTR)) => (),
+            res => return res,
         }
     }
 }
 
-fn bindings() -> Vec<(Cow<'static, [u8]>, EventKind)> {
-    vec![
-        (Cow::Borrowed(b"\r"      ), EventKind::Enter),           // Enter
-        (Cow::Borrowed(b"\x7f"    ), EventKind::Delete),          // BackSpace
-        (Cow::Borrowed(b"\x1b[A"  ), EventKind::PreviousHistory), // Up
-        (Cow::Borrowed(b"\x1b[B"  ), EventKind::NextHistory),     // Down
-        (Cow::Borrowed(b"\x1b[C"  ), EventKind::ForwardChar),     // Left
-        (Cow::Borrowed(b"\x1b[D"  ), EventKind::BackwardChar),    // Right
-
-        (Cow::Borrowed(b"\t"      ), EventKind::Complete),        // Tab
-
-        (Cow::Borrowed(b"\x01"    ), EventKind::BeginningOFLine), // Ctrl-A
-        (Cow::Borrowed(b"\x02"    ), EventKind::BackwardChar),    // Ctrl-B
-        (Cow::Borrowed(b"\x05"    ), EventKind::EndOfLine),       // Ctrl-E
-        (Cow::Borrowed(b"\x06"    ), EventKind::ForwardChar),     // Ctrl-F
-        (Cow::Borrowed(b"\x07"    ), EventKind::Something),       // Ctrl-G
-        (Cow::Borrowed(b"\x0a"    ), EventKind::Enter),           // Ctrl-J
-        (Cow::Borrowed(b"\x0b"    ), EventKind::Something),       // Ctrl-K
-        (Cow::Borrowed(b"\x0c"    ), EventKind::CtrlL),           // Ctrl-L
-        (Cow::Borrowed(b"\x0d"    ), EventKind::Enter),           // Ctrl-N
-        (Cow::Borrowed(b"\x0e"    ), EventKind::NextHistory),     // Ctrl-N
-        (Cow::Borrowed(b"\x10"    ), EventKind::PreviousHistory), // Ctrl-P
-        (Cow::Borrowed(b"\x12"    ), EventKind::Something),       // Ctrl-R
-        (Cow::Borrowed(b"\x14"    ), EventKind::Something),       // Ctrl-T
-        (Cow::Borrowed(b"\x19"    ), EventKind::Something),       // Ctrl-Y
-        (Cow::Borrowed(b"\x1d"    ), EventKind::Something),       // Ctrl-]
-        (Cow::Borrowed(b"\x1b\x08"), EventKind::Something),       // Escape, Ctrl-H
-        (Cow::Borrowed(b"\x1b\x1d"), EventKind::Something),       // Escape, Ctrl-]
-        (Cow::Borrowed(b"\x1b\x7f"), EventKind::Something),       // Escape, Rubout
-        (Cow::Borrowed(b"\x1bb"   ), EventKind::Something),       // Escape, b
-        (Cow::Borrowed(b"\x1bd"   ), EventKind::Something),       // Escape, d
-        (Cow::Borrowed(b"\x1bf"   ), EventKind::Something),       // Escape, f
-        (Cow::Borrowed(b"\x1bt"   ), EventKind::Something),       // Escape, t
-        (Cow::Borrowed(b"\x1by"   ), EventKind::Something),       // Escape, y
-        (Cow::Borrowed(b"\x1b#"   ), EventKind::Something),       // Escape, #
-        (Cow::Borrowed(b"\x1b<"   ), EventKind::Something),       // Escape, <
-        (Cow::Borrowed(b"\x1b>"   ), EventKind::Something),       // Escape, >
-    ]
+#[test]
+fn test_escape_len() {
+    assert_eq!(escape_len(b"\x1b[15~x"), Some(5));
+    assert_eq!(escape_len(b"\x1b[1;5P"), Some(6));
+    assert_eq!(escape_len(b"\x1b[15"), None);
+    assert_eq!(escape_len(b"\x1bOPx"), Some(3));
+    assert_eq!(escape_len(b"\x1bO"), None);
+    assert_eq!(escape_len("\x1b\u{e9}x".as_bytes()), Some(3));
+    assert_eq!(escape_len(b"\x1b"), None);
 }
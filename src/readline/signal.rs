@@ -1,9 +1,13 @@
 use std::io;
+use std::os::unix::io::RawFd;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
 
+use nix::fcntl::OFlag;
+use nix::libc::{self, c_int};
 use nix::sys::signal;
-use nix::libc::c_int;
 use nix::sys::signal::Signal as NixSignal;
+use nix::unistd::{pipe2, read};
 
 #[derive(Debug)]
 pub enum Signal {
@@ -11,20 +15,41 @@ pub enum Signal {
     Suspend,
     Continue,
     Quit,
+    WindowChange,
 }
 
 static LAST_SIGNAL: AtomicUsize = AtomicUsize::new(0);
 
-#[cfg_attr(feature = "cargo-clippy", allow(cast_sign_loss))]
+/// A pipe the handler writes a byte to, so that a signal wakes up a `poll`
+/// on its read end.
+static PIPE: OnceLock<(RawFd, RawFd)> = OnceLock::new();
+
+#[allow(clippy::cast_sign_loss)]
 extern "C" fn handle_sigint(sig: i32) {
     set_raw_signal(sig as usize);
+    if let Some(&(_, w)) = PIPE.get() {
+        unsafe { libc::write(w, b"!".as_ptr() as *const libc::c_void, 1) };
+    }
 }
 
 fn set_raw_signal(sig: usize) {
     LAST_SIGNAL.store(sig, Ordering::Relaxed);
 }
 
+/// The file descriptor that is readable once a signal came.
+pub fn fd() -> Option<RawFd> {
+    PIPE.get().map(|&(r, _)| r)
+}
+
 pub fn take() -> Option<Signal> {
+    if let Some(&(r, _)) = PIPE.get() {
+        let mut buf = [0; 64];
+        while let Ok(n) = read(r, &mut buf) {
+            if n < buf.len() {
+                break;
+            }
+        }
+    }
     take_last_signal()
 }
 
@@ -32,7 +57,7 @@ fn take_last_signal() -> Option<Signal> {
     conv_signal(LAST_SIGNAL.swap(!0, Ordering::Relaxed))
 }
 
-#[cfg_attr(feature = "cargo-clippy", allow(cast_possible_wrap, cast_possible_truncation))]
+#[allow(clippy::cast_possible_wrap, clippy::cast_possible_truncation)]
 fn conv_signal(n: usize) -> Option<Signal> {
     if n == !0 {
         None
@@ -42,12 +67,18 @@ fn conv_signal(n: usize) -> Option<Signal> {
             Some(NixSignal::SIGTSTP) => Some(Signal::Suspend),
             Some(NixSignal::SIGCONT) => Some(Signal::Continue),
             Some(NixSignal::SIGQUIT) => Some(Signal::Quit),
+            Some(NixSignal::SIGWINCH) => Some(Signal::WindowChange),
             _ => None,
         }
     }
 }
 
 pub fn prepare() -> io::Result<()> {
+    if PIPE.get().is_none() {
+        let fds = pipe2(OFlag::O_CLOEXEC | OFlag::O_NONBLOCK).map_err(io::Error::other)?;
+        let _ = PIPE.set(fds);
+    }
+
     let sig_action = signal::SigAction::new(
         signal::SigHandler::Handler(handle_sigint),
         signal::SaFlags::empty(),
@@ -58,6 +89,7 @@ pub fn prepare() -> io::Result<()> {
     let _ = unsafe { signal::sigaction(NixSignal::SIGTSTP, &sig_action).unwrap() };
     let _ = unsafe { signal::sigaction(NixSignal::SIGCONT, &sig_action).unwrap() };
     let _ = unsafe { signal::sigaction(NixSignal::SIGQUIT, &sig_action).unwrap() };
+    let _ = unsafe { signal::sigaction(NixSignal::SIGWINCH, &sig_action).unwrap() };
 
     Ok(())
 }
@@ -1,16 +1,9 @@
 use std::io;
 use std::mem::zeroed;
+use nix;
+use nix::errno::Errno;
 use nix::libc::{c_int, c_ushort, ioctl, TIOCGWINSZ};
-
-trait Cursor {
-    fn move_up(dst: impl io::Write, n: usize) -> io::Result<()>;
-
-    fn move_down(dst: impl io::Write, n: usize) -> io::Result<()>;
-
-    fn move_left(dst: impl io::Write, n: usize) -> io::Result<()>;
-
-    fn move_right(dst: impl io::Write, n: usize) -> io::Result<()>;
-}
+use nix::unistd::write;
 
 pub fn move_to(n: usize) -> String {
     format!("\x1b[{}G", n)
@@ -20,22 +13,48 @@ pub fn move_up(n: usize) -> String {
     format!("\x1b[{}A", n)
 }
 
-pub fn move_right(n: usize) -> String {
-    format!("\x1b[{}C", n)
-}
-
-pub fn move_left(n: usize) -> String {
-    format!("\x1b[{}D", n)
+pub fn move_down(n: usize) -> String {
+    format!("\x1b[{}B", n)
 }
 
 pub fn move_under_line_first(n: usize) -> String {
     format!("\x1b[{}E", n)
 }
 
+/// The cursor shapes of DECSCUSR, `\x1b[N q`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CursorShape {
+    /// Whatever the user configured the terminal with.
+    Default = 0,
+    Block = 2,
+    Bar = 6,
+}
+
+pub fn cursor_shape(shape: CursorShape) -> String {
+    format!("\x1b[{} q", shape as u8)
+}
+
+/// Has the terminal mark pasted text with `\x1b[200~` and `\x1b[201~`.
+pub fn bracketed_paste(on: bool) -> String {
+    format!("\x1b[?2004{}", if on { 'h' } else { 'l' })
+}
+
 pub fn clear_to_screen_end() -> String {
     "\x1b[0J".into()
 }
 
+/// Writes all of `buf` to `fd`, however many writes that takes.
+pub fn write_all(fd: c_int, mut buf: &[u8]) -> io::Result<()> {
+    while !buf.is_empty() {
+        match write(fd, buf) {
+            Ok(n) => buf = &buf[n..],
+            Err(nix::Error::Sys(Errno::EINTR)) => (),
+            Err(e) => return Err(io::Error::other(e)),
+        }
+    }
+    Ok(())
+}
+
 pub fn get_winsize(fd: c_int) -> io::Result<Winsize> {
     let mut winsz: Winsize = unsafe { zeroed() };
 
@@ -56,3 +75,15 @@ pub struct Winsize {
     pub ws_xpixel: c_ushort,
     pub ws_ypixel: c_ushort,
 }
+
+/// Falls back to the classic 80x24 when the size can't be queried.
+impl Default for Winsize {
+    fn default() -> Self {
+        Self {
+            ws_row: 24,
+            ws_col: 80,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! The terminal's own settings, and the raw mode the editor reads keys in.
+//! Commands run with the settings the terminal had when the shell started,
+//! and the shell puts them back however it ends.
+
+use std::mem;
+use std::panic;
+use std::ptr;
+use std::sync::OnceLock;
+
+use nix;
+use nix::libc;
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::sys::termios::{tcgetattr, tcsetattr, InputFlags, LocalFlags, SetArg,
+                        SpecialCharacterIndices, Termios};
+
+/// The terminal and its settings from before the shell changed them.
+static ORIGINAL: OnceLock<(libc::c_int, libc::termios)> = OnceLock::new();
+
+/// Signals that end the shell while it may have the terminal in raw mode.
+const FATAL: &[Signal] = &[Signal::SIGHUP, Signal::SIGTERM];
+
+/// Saves the settings of the terminal on `fd` to go back to, and makes sure
+/// they are put back should the shell panic.
+pub fn save(fd: libc::c_int) -> nix::Result<()> {
+    let termios = tcgetattr(fd)?;
+    if ORIGINAL.set((fd, termios.into())).is_ok() {
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let _ = restore();
+            hook(info);
+        }));
+    }
+    Ok(())
+}
+
+/// Switches to reading key by key without echo, for the editor.
+pub fn raw_mode() -> nix::Result<()> {
+    let (fd, original) = match ORIGINAL.get() {
+        Some(saved) => *saved,
+        None => return Ok(()),
+    };
+    let mut tio = Termios::from(original);
+
+    tio.input_flags.remove(
+        InputFlags::INLCR | InputFlags::ICRNL,
+    );
+    tio.local_flags.remove(
+        LocalFlags::ICANON | LocalFlags::ECHO,
+    );
+    // A read waits for at least one byte.
+    tio.control_chars[SpecialCharacterIndices::VMIN as usize] = 1;
+    tio.control_chars[SpecialCharacterIndices::VTIME as usize] = 0;
+
+    tcsetattr(fd, SetArg::TCSANOW, &tio)?;
+    catch_fatal_signals()
+}
+
+/// Puts back the settings the terminal had.
+pub fn restore() -> nix::Result<()> {
+    match ORIGINAL.get() {
+        Some(&(fd, original)) => tcsetattr(fd, SetArg::TCSADRAIN, &Termios::from(original)),
+        None => Ok(()),
+    }
+}
+
+/// Has the signals in `FATAL` restore the terminal before they kill the
+/// shell, unless a trap already handles them.
+fn catch_fatal_signals() -> nix::Result<()> {
+    let action = SigAction::new(
+        SigHandler::Handler(handle_fatal),
+        SaFlags::empty(),
+        SigSet::empty(),
+    );
+    for &sig in FATAL {
+        // nix can't read back the flags the kernel adds, so ask libc.
+        let mut old: libc::sigaction = unsafe { mem::zeroed() };
+        unsafe { libc::sigaction(sig as libc::c_int, ptr::null(), &mut old) };
+        if old.sa_sigaction == libc::SIG_DFL {
+            unsafe { signal::sigaction(sig, &action)? };
+        }
+    }
+    Ok(())
+}
+
+extern "C" fn handle_fatal(sig: libc::c_int) {
+    // Only async-signal-safe calls here.
+    unsafe {
+        if let Some(&(fd, ref original)) = ORIGINAL.get() {
+            libc::tcsetattr(fd, libc::TCSANOW, original);
+        }
+        libc::signal(sig, libc::SIG_DFL);
+        libc::raise(sig);
+    }
+}
@@ -0,0 +1,92 @@
+//! Undo and redo for the line editor.
+//!
+//! Every change to the buffer is recorded as an `Edit`. Edits made by one
+//! command form a unit that is undone at once; a run of typed characters
+//! stays one unit until some other command closes it.
+
+/// The text between `start` and `start + removed.len()` was replaced with
+/// `inserted`. Offsets count chars.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edit {
+    pub start: usize,
+    pub removed: String,
+    pub inserted: String,
+}
+
+#[derive(Debug, Default)]
+pub struct UndoStack {
+    undo: Vec<Vec<Edit>>,
+    redo: Vec<Vec<Edit>>,
+    /// Whether the next edit joins the newest unit.
+    open: bool,
+}
+
+impl UndoStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, edit: Edit) {
+        self.redo.clear();
+        if self.open {
+            if let Some(unit) = self.undo.last_mut() {
+                unit.push(edit);
+                return;
+            }
+        }
+        self.undo.push(vec![edit]);
+        self.open = true;
+    }
+
+    /// Makes the next edit start a unit of its own.
+    pub fn close(&mut self) {
+        self.open = false;
+    }
+
+    /// Takes the newest unit to undo. Its edits have to be reverted in
+    /// reverse order.
+    pub fn undo(&mut self) -> Option<Vec<Edit>> {
+        self.open = false;
+        let unit = self.undo.pop()?;
+        self.redo.push(unit.clone());
+        Some(unit)
+    }
+
+    /// Takes the unit undone last, to be applied again in order.
+    pub fn redo(&mut self) -> Option<Vec<Edit>> {
+        self.open = false;
+        let unit = self.redo.pop()?;
+        self.undo.push(unit.clone());
+        Some(unit)
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.open = false;
+    }
+}
+
+#[test]
+fn test_undo_stack() {
+    let edit = |start, removed: &str, inserted: &str| Edit {
+        start,
+        removed: removed.into(),
+        inserted: inserted.into(),
+    };
+
+    let mut stack = UndoStack::new();
+    stack.record(edit(0, "", "a"));
+    stack.record(edit(1, "", "b"));
+    stack.close();
+    stack.record(edit(0, "ab", ""));
+
+    assert_eq!(stack.undo(), Some(vec![edit(0, "ab", "")]));
+    assert_eq!(stack.undo(), Some(vec![edit(0, "", "a"), edit(1, "", "b")]));
+    assert_eq!(stack.undo(), None);
+    assert_eq!(stack.redo(), Some(vec![edit(0, "", "a"), edit(1, "", "b")]));
+
+    // A new edit forgets what could be redone.
+    stack.record(edit(2, "", "c"));
+    assert_eq!(stack.redo(), None);
+}
@@ -0,0 +1,841 @@
+//! Vi editing mode, switched on with `set -o vi`.
+//!
+//! Insert mode uses the usual bindings. In command mode every key goes
+//! through here: keys pile up until they make a whole command like `d2w`,
+//! which then runs.
+
+use std::io;
+
+use readline::context::{Context, Mode};
+use readline::editor::Complete;
+use readline::event::{Event, Kind};
+use readline::terminal::CursorShape;
+
+#[derive(Debug, Default)]
+pub struct Vi {
+    /// Keys of the command being typed.
+    keys: String,
+    /// Keys of the last command that changed the line, for `.`.
+    last_change: Option<String>,
+    /// What was typed in insert mode after `last_change`, for `.`.
+    inserted: String,
+    /// Whether what is typed in insert mode goes to `inserted`.
+    recording: bool,
+    /// The last `f`, `t`, `F` or `T` and its character, for `;` and `,`.
+    last_find: Option<(char, char)>,
+    /// Text saved by `y`, `d` and `c`, for `p` and `P`.
+    register: String,
+    /// The search being typed after `/` or `?`.
+    search: Option<(char, String)>,
+    /// The last search, for `n` and `N`.
+    last_search: Option<(char, String)>,
+}
+
+impl Vi {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Saves text typed in insert mode so that `.` can type it again.
+    pub fn record_insert(&mut self, s: &str) {
+        if self.recording {
+            self.inserted.push_str(s);
+        }
+    }
+
+    /// Forgets the last character typed in insert mode, after a backspace.
+    pub fn record_delete(&mut self) {
+        if self.recording {
+            self.inserted.pop();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Motion {
+    Left,
+    Right,
+    /// `w`, or `W` when big.
+    WordForward(bool),
+    /// `b` or `B`.
+    WordBackward(bool),
+    /// `e` or `E`.
+    WordEnd(bool),
+    /// `0`.
+    Start,
+    /// `^`.
+    FirstNonBlank,
+    /// `$`.
+    End,
+    /// `f`, `t`, `F` or `T` and the character to look for.
+    Find(char, char),
+    /// `;`, or `,` when reversed.
+    RepeatFind(bool),
+}
+
+impl Motion {
+    /// Whether an operator takes in the character the motion lands on.
+    fn is_inclusive(&self) -> bool {
+        match *self {
+            Motion::WordEnd(_) | Motion::End => true,
+            Motion::Find(kind, _) => kind == 'f' || kind == 't',
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Target {
+    Motion(Motion),
+    /// `iw` and `aw`, or `iW` and `aW` when big.
+    Object { inner: bool, big: bool },
+    /// `dd`, `cc` and `yy`.
+    Line,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Action {
+    Move(Motion),
+    Operate(Operator, Target),
+    /// `i`, `a`, `I` or `A`.
+    Insert(char),
+    /// `r` and the replacement.
+    Replace(char),
+    /// A command of a single key with nothing to parse after it.
+    Key(char),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Command {
+    /// `None` when no count was typed.
+    count: Option<usize>,
+    action: Action,
+}
+
+#[derive(Debug, PartialEq)]
+enum Parse<T> {
+    Done(T),
+    /// More keys are needed.
+    Incomplete,
+    Invalid,
+}
+
+/// The largest count a command takes, more than any line needs.
+const MAX_COUNT: usize = 99_999;
+
+fn take_count<I: Iterator<Item = char>>(chars: &mut ::std::iter::Peekable<I>) -> Option<usize> {
+    let mut count = None;
+    while let Some(&c) = chars.peek() {
+        match c.to_digit(10) {
+            // A leading `0` is the motion, not a count.
+            Some(d) if d > 0 || count.is_some() => {
+                count = Some((count.unwrap_or(0) * 10 + d as usize).min(MAX_COUNT));
+                chars.next();
+            }
+            _ => break,
+        }
+    }
+    count
+}
+
+fn parse_motion<I: Iterator<Item = char>>(c: char, chars: &mut I) -> Parse<Motion> {
+    let motion = match c {
+        'h' | '\x08' | '\x7f' => Motion::Left,
+        'l' | ' ' => Motion::Right,
+        'w' | 'W' => Motion::WordForward(c == 'W'),
+        'b' | 'B' => Motion::WordBackward(c == 'B'),
+        'e' | 'E' => Motion::WordEnd(c == 'E'),
+        '0' => Motion::Start,
+        '^' => Motion::FirstNonBlank,
+        '$' => Motion::End,
+        'f' | 't' | 'F' | 'T' => {
+            match chars.next() {
+                Some(target) => Motion::Find(c, target),
+                None => return Parse::Incomplete,
+            }
+        }
+        ';' | ',' => Motion::RepeatFind(c == ','),
+        _ => return Parse::Invalid,
+    };
+    Parse::Done(motion)
+}
+
+fn parse(keys: &str) -> Parse<Command> {
+    let mut chars = keys.chars().peekable();
+    let count = take_count(&mut chars);
+    let c = match chars.next() {
+        Some(c) => c,
+        None => return Parse::Incomplete,
+    };
+    let done = |action| Parse::Done(Command { count, action });
+
+    let operator = match c {
+        'd' => Operator::Delete,
+        'c' => Operator::Change,
+        'y' => Operator::Yank,
+        // Short forms of operator commands.
+        'x' => return done(Action::Operate(Operator::Delete, Target::Motion(Motion::Right))),
+        'X' => return done(Action::Operate(Operator::Delete, Target::Motion(Motion::Left))),
+        'D' => return done(Action::Operate(Operator::Delete, Target::Motion(Motion::End))),
+        'C' => return done(Action::Operate(Operator::Change, Target::Motion(Motion::End))),
+        's' => return done(Action::Operate(Operator::Change, Target::Motion(Motion::Right))),
+        'S' => return done(Action::Operate(Operator::Change, Target::Line)),
+        'Y' => return done(Action::Operate(Operator::Yank, Target::Line)),
+        'i' | 'a' | 'I' | 'A' => return done(Action::Insert(c)),
+        'r' => {
+            return match chars.next() {
+                Some(r) => done(Action::Replace(r)),
+                None => Parse::Incomplete,
+            }
+        }
+        'p' | 'P' | 'u' | '\x12' | '~' | '.' | 'j' | 'k' | '+' | '-' | '/' | '?' | 'n' |
+        'N' | '\r' | '\n' => return done(Action::Key(c)),
+        _ => {
+            return match parse_motion(c, &mut chars) {
+                Parse::Done(m) => done(Action::Move(m)),
+                Parse::Incomplete => Parse::Incomplete,
+                Parse::Invalid => Parse::Invalid,
+            }
+        }
+    };
+
+    let count2 = take_count(&mut chars);
+    let count = match (count, count2) {
+        (Some(a), Some(b)) => Some(a.saturating_mul(b).min(MAX_COUNT)),
+        (a, b) => a.or(b),
+    };
+    let done = |target| Parse::Done(Command { count, action: Action::Operate(operator, target) });
+
+    let c2 = match chars.next() {
+        Some(c2) => c2,
+        None => return Parse::Incomplete,
+    };
+    if c2 == c {
+        return done(Target::Line);
+    }
+    if c2 == 'i' || c2 == 'a' {
+        return match chars.next() {
+            Some('w') => done(Target::Object { inner: c2 == 'i', big: false }),
+            Some('W') => done(Target::Object { inner: c2 == 'i', big: true }),
+            Some(_) => Parse::Invalid,
+            None => Parse::Incomplete,
+        };
+    }
+    match parse_motion(c2, &mut chars) {
+        Parse::Done(m) => done(Target::Motion(m)),
+        Parse::Incomplete => Parse::Incomplete,
+        Parse::Invalid => Parse::Invalid,
+    }
+}
+
+/// 0 for blanks, 1 for word characters, 2 for punctuation. Big words are
+/// anything between blanks.
+fn class(c: char, big: bool) -> u8 {
+    if c.is_whitespace() {
+        0
+    } else if big || c.is_alphanumeric() || c == '_' {
+        1
+    } else {
+        2
+    }
+}
+
+fn next_word_start(chars: &[char], pos: usize, big: bool) -> usize {
+    let len = chars.len();
+    let mut i = pos;
+    if i >= len {
+        return len;
+    }
+    let k = class(chars[i], big);
+    if k != 0 {
+        while i < len && class(chars[i], big) == k {
+            i += 1;
+        }
+    }
+    while i < len && class(chars[i], big) == 0 {
+        i += 1;
+    }
+    i
+}
+
+fn prev_word_start(chars: &[char], pos: usize, big: bool) -> usize {
+    if pos == 0 {
+        return 0;
+    }
+    let mut i = pos - 1;
+    while i > 0 && class(chars[i], big) == 0 {
+        i -= 1;
+    }
+    let k = class(chars[i], big);
+    while i > 0 && class(chars[i - 1], big) == k {
+        i -= 1;
+    }
+    i
+}
+
+/// The last character of the word the cursor is in or the next one.
+fn word_end(chars: &[char], pos: usize, big: bool) -> usize {
+    let len = chars.len();
+    let mut i = pos + 1;
+    while i < len && class(chars[i], big) == 0 {
+        i += 1;
+    }
+    if i >= len {
+        return len.saturating_sub(1);
+    }
+    let k = class(chars[i], big);
+    while i + 1 < len && class(chars[i + 1], big) == k {
+        i += 1;
+    }
+    i
+}
+
+/// Where `f`, `t`, `F` or `T` lands, looking for the `count`th `target`.
+/// A repeated `t` or `T` skips the target it stopped next to.
+fn find(chars: &[char], pos: usize, kind: char, target: char, count: usize, repeat: bool) -> Option<usize> {
+    let skip = if repeat { 1 } else { 0 };
+    let mut i = pos;
+    for _ in 0..count {
+        i = if kind == 'f' || kind == 't' {
+            let from = i + 1 + if kind == 't' { skip } else { 0 };
+            from + chars.get(from..)?.iter().position(|&c| c == target)?
+        } else {
+            let to = if kind == 'T' { i.checked_sub(skip)? } else { i };
+            chars[..to].iter().rposition(|&c| c == target)?
+        };
+        if kind == 't' {
+            i -= 1;
+        } else if kind == 'T' {
+            i += 1;
+        }
+    }
+    Some(i)
+}
+
+/// `iw` or `aw`: the run of word characters, punctuation or blanks the
+/// cursor is on, and with `aw` the blanks after it too.
+fn text_object(chars: &[char], pos: usize, inner: bool, big: bool, count: usize) -> Option<(usize, usize)> {
+    let len = chars.len();
+    if len == 0 {
+        return None;
+    }
+    let pos = pos.min(len - 1);
+    let k = class(chars[pos], big);
+
+    let mut start = pos;
+    while start > 0 && class(chars[start - 1], big) == k {
+        start -= 1;
+    }
+    let mut end = pos + 1;
+    while end < len && class(chars[end], big) == k {
+        end += 1;
+    }
+    for _ in 1..count {
+        if end >= len {
+            break;
+        }
+        let k = class(chars[end], big);
+        while end < len && class(chars[end], big) == k {
+            end += 1;
+        }
+    }
+
+    if !inner {
+        let mut after = end;
+        while after < len && class(chars[after], big) == 0 {
+            after += 1;
+        }
+        if k == 0 {
+            // On blanks, `aw` takes the word after them.
+            if after < len {
+                let k = class(chars[after], big);
+                while after < len && class(chars[after], big) == k {
+                    after += 1;
+                }
+            }
+            end = after;
+        } else if after > end {
+            end = after;
+        } else {
+            while start > 0 && class(chars[start - 1], big) == 0 {
+                start -= 1;
+            }
+        }
+    }
+    Some((start, end))
+}
+
+/// Where a motion moves the cursor, or `None` when it can't.
+fn motion_target(con: &mut Context, motion: Motion, count: usize) -> Option<usize> {
+    let pos = con.editor.pos();
+    let target = {
+        let chars = con.editor.chars();
+        match motion {
+            Motion::Left => {
+                let mut p = pos;
+                for _ in 0..count {
+                    p = con.editor.prev_boundary(p);
+                }
+                p
+            }
+            Motion::Right => {
+                let mut p = pos;
+                for _ in 0..count {
+                    p = con.editor.next_boundary(p);
+                }
+                p
+            }
+            Motion::WordForward(big) => (0..count).fold(pos, |p, _| next_word_start(chars, p, big)),
+            Motion::WordBackward(big) => (0..count).fold(pos, |p, _| prev_word_start(chars, p, big)),
+            Motion::WordEnd(big) => (0..count).fold(pos, |p, _| word_end(chars, p, big)),
+            Motion::Start => 0,
+            Motion::FirstNonBlank => chars.iter().position(|c| !c.is_whitespace()).unwrap_or(0),
+            Motion::End => chars.len().saturating_sub(1),
+            Motion::Find(kind, target) => {
+                con.vi.last_find = Some((kind, target));
+                find(chars, pos, kind, target, count, false)?
+            }
+            Motion::RepeatFind(reverse) => {
+                let (kind, target) = con.vi.last_find?;
+                let kind = if reverse {
+                    match kind {
+                        'f' => 'F',
+                        'F' => 'f',
+                        't' => 'T',
+                        _ => 't',
+                    }
+                } else {
+                    kind
+                };
+                find(chars, pos, kind, target, count, true)?
+            }
+        }
+    };
+    Some(target)
+}
+
+/// The effective motion of `RepeatFind`, to know whether it is inclusive.
+fn resolve(con: &Context, motion: Motion) -> Motion {
+    match (motion, con.vi.last_find) {
+        (Motion::RepeatFind(reverse), Some((kind, target))) => {
+            let forward = (kind == 'f' || kind == 't') != reverse;
+            Motion::Find(if forward { 'f' } else { 'F' }, target)
+        }
+        _ => motion,
+    }
+}
+
+/// Keeps the cursor on a character, as command mode does.
+fn clamp(con: &mut Context) {
+    let pos = con.editor.pos();
+    let len = con.editor.chars().len();
+    if len > 0 && pos >= len {
+        let last = con.editor.prev_boundary(len);
+        con.editor.move_to(last);
+    }
+}
+
+fn enter_insert(con: &mut Context) {
+    con.mode = Mode::ViInsert;
+    con.editor.set_cursor_shape(CursorShape::Bar);
+}
+
+/// Leaves insert mode, after Escape. The cursor steps back onto the last
+/// character typed.
+pub fn escape(con: &mut Context) {
+    con.editor.completion_clear();
+    con.mode = Mode::ViCommand;
+    con.vi.recording = false;
+    con.editor.set_cursor_shape(CursorShape::Block);
+    con.editor.close_undo_unit();
+
+    let pos = con.editor.pos();
+    if pos > 0 && pos > con.editor.line_start() {
+        con.editor.move_to(con.editor.prev_boundary(pos));
+    }
+}
+
+/// Handles keys typed in command mode.
+pub fn command(con: &mut Context, keys: &[u8]) -> io::Result<Option<String>> {
+    let keys = String::from_utf8_lossy(keys).into_owned();
+    let mut chars = keys.chars();
+    while let Some(c) = chars.next() {
+        if con.mode == Mode::ViInsert {
+            // The rest of a pasted chunk after `i` or `c` is text.
+            let rest: String = Some(c).into_iter().chain(chars).collect();
+            con.vi.record_insert(&rest);
+            con.editor.put(&rest);
+            break;
+        }
+        if let Some(line) = key(con, c)? {
+            return Ok(Some(line));
+        }
+    }
+    Ok(None)
+}
+
+fn key(con: &mut Context, c: char) -> io::Result<Option<String>> {
+    if con.vi.search.is_some() {
+        search_key(con, c);
+        return Ok(None);
+    }
+    if c == '\x1b' {
+        con.vi.keys.clear();
+        return Ok(None);
+    }
+
+    con.vi.keys.push(c);
+    match parse(&con.vi.keys) {
+        Parse::Incomplete => Ok(None),
+        Parse::Invalid => {
+            con.vi.keys.clear();
+            Ok(None)
+        }
+        Parse::Done(cmd) => {
+            let keys = ::std::mem::take(&mut con.vi.keys);
+            run(con, cmd, &keys, false)
+        }
+    }
+}
+
+/// Runs a command. `keys` are what was typed for it, kept for `.`.
+fn run(con: &mut Context, cmd: Command, keys: &str, replaying: bool) -> io::Result<Option<String>> {
+    let count = cmd.count.unwrap_or(1);
+    let changes = match cmd.action {
+        Action::Move(m) => {
+            if let Some(pos) = motion_target(con, m, count) {
+                con.editor.move_to(pos);
+            }
+            false
+        }
+        Action::Operate(op, target) => {
+            operate(con, op, target, count);
+            op != Operator::Yank
+        }
+        Action::Insert(c) => {
+            match c {
+                'a' => {
+                    let pos = con.editor.next_boundary(con.editor.pos());
+                    con.editor.move_to(pos);
+                }
+                'I' => {
+                    let pos = motion_target(con, Motion::FirstNonBlank, 1).unwrap_or(0);
+                    con.editor.move_to(pos);
+                }
+                'A' => con.editor.move_to_end(),
+                _ => (),
+            }
+            enter_insert(con);
+            true
+        }
+        Action::Replace(r) => {
+            let start = con.editor.pos();
+            let end = motion_target(con, Motion::Right, count).unwrap_or(start);
+            if con.editor.chars()[start..end].iter().filter(|&&c| c != '\n').count() < count {
+                return Ok(None);
+            }
+            let with: String = (0..count).map(|_| r).collect();
+            con.editor.kill(start, end);
+            con.editor.put(&with);
+            let pos = con.editor.prev_boundary(con.editor.pos());
+            con.editor.move_to(pos);
+            true
+        }
+        Action::Key(c) => return run_key(con, c, cmd.count, keys, replaying),
+    };
+
+    if changes && !replaying {
+        con.vi.last_change = Some(keys.into());
+        if con.mode == Mode::ViInsert {
+            con.vi.inserted.clear();
+            con.vi.recording = true;
+        }
+    }
+    if con.mode == Mode::ViCommand {
+        clamp(con);
+    }
+    Ok(None)
+}
+
+fn operate(con: &mut Context, op: Operator, target: Target, count: usize) {
+    let pos = con.editor.pos();
+    let len = con.editor.chars().len();
+    let (start, end) = match target {
+        Target::Line => (0, len),
+        Target::Object { inner, big } => {
+            match text_object(con.editor.chars(), pos, inner, big, count) {
+                Some(range) => range,
+                None => return,
+            }
+        }
+        Target::Motion(m) => {
+            // `cw` changes up to the end of the word, like `ce`.
+            let m = match m {
+                Motion::WordForward(big) if op == Operator::Change &&
+                    con.editor.chars().get(pos).is_some_and(|c| !c.is_whitespace()) => {
+                    let end = end_of_current_word(con, pos, big, count);
+                    let end = con.editor.next_boundary(end);
+                    return finish(con, op, pos, end);
+                }
+                m => m,
+            };
+            let target = match motion_target(con, m, count) {
+                Some(target) => target,
+                None => return,
+            };
+            let inclusive = resolve(con, m).is_inclusive();
+            if target < pos {
+                (target, pos)
+            } else if inclusive && target < len {
+                (pos, con.editor.next_boundary(target))
+            } else {
+                (pos, target)
+            }
+        }
+    };
+    finish(con, op, start, end);
+}
+
+/// The last character of the `count`th word from `pos`, counting the one
+/// the cursor is on.
+fn end_of_current_word(con: &Context, pos: usize, big: bool, count: usize) -> usize {
+    let chars = con.editor.chars();
+    let k = class(chars[pos], big);
+    let mut end = pos;
+    while end + 1 < chars.len() && class(chars[end + 1], big) == k {
+        end += 1;
+    }
+    (1..count).fold(end, |p, _| word_end(chars, p, big))
+}
+
+fn finish(con: &mut Context, op: Operator, start: usize, end: usize) {
+    match op {
+        Operator::Yank => {
+            con.vi.register = con.editor.text(start, end);
+            con.editor.move_to(start);
+        }
+        Operator::Delete => {
+            if start < end {
+                con.vi.register = con.editor.kill(start, end);
+            }
+        }
+        Operator::Change => {
+            if start < end {
+                con.vi.register = con.editor.kill(start, end);
+            }
+            enter_insert(con);
+        }
+    }
+}
+
+fn run_key(
+    con: &mut Context,
+    c: char,
+    count: Option<usize>,
+    keys: &str,
+    replaying: bool,
+) -> io::Result<Option<String>> {
+    let n = count.unwrap_or(1);
+    let mut changes = false;
+    match c {
+        '\r' | '\n' => {
+            con.vi.keys.clear();
+            con.vi.search = None;
+            let e = Event::from_event_kind(&Some(Kind::Enter));
+            return (e.handler)(con, Vec::new());
+        }
+        'p' | 'P' => {
+            if con.vi.register.is_empty() {
+                return Ok(None);
+            }
+            if c == 'p' {
+                let pos = con.editor.next_boundary(con.editor.pos());
+                con.editor.move_to(pos);
+            }
+            let text: String = (0..n).map(|_| con.vi.register.as_str()).collect();
+            con.editor.put(&text);
+            let pos = con.editor.prev_boundary(con.editor.pos());
+            con.editor.move_to(pos);
+            changes = true;
+        }
+        'u' => {
+            for _ in 0..n {
+                con.editor.undo();
+            }
+        }
+        '\x12' => {
+            for _ in 0..n {
+                con.editor.redo();
+            }
+        }
+        '~' => {
+            let start = con.editor.pos();
+            let end = motion_target(con, Motion::Right, n).unwrap_or(start);
+            let toggled: String = con.editor
+                .text(start, end)
+                .chars()
+                .flat_map(|c| if c.is_uppercase() {
+                    c.to_lowercase().collect::<Vec<_>>()
+                } else {
+                    c.to_uppercase().collect()
+                })
+                .collect();
+            con.editor.kill(start, end);
+            con.editor.put(&toggled);
+            changes = true;
+        }
+        '.' => {
+            let last = match con.vi.last_change.clone() {
+                Some(last) => last,
+                None => return Ok(None),
+            };
+            let mut cmd = match parse(&last) {
+                Parse::Done(cmd) => cmd,
+                _ => return Ok(None),
+            };
+            if count.is_some() {
+                cmd.count = count;
+            }
+            run(con, cmd, &last, true)?;
+            if con.mode == Mode::ViInsert {
+                let inserted = con.vi.inserted.clone();
+                con.editor.put(&inserted);
+                escape(con);
+            }
+        }
+        'k' | '-' | 'j' | '+' => {
+            let kind = if c == 'k' || c == '-' {
+                Kind::PreviousHistory
+            } else {
+                Kind::NextHistory
+            };
+            for _ in 0..n {
+                let e = Event::from_event_kind(&Some(kind.clone()));
+                (e.handler)(con, Vec::new())?;
+            }
+            con.editor.move_to_first();
+        }
+        '/' | '?' => {
+            con.vi.search = Some((c, String::new()));
+            con.editor.show_status(&c.to_string());
+        }
+        'n' | 'N' => {
+            if let Some((dir, pattern)) = con.vi.last_search.clone() {
+                let older = (dir == '/') == (c == 'n');
+                search(con, &pattern, older);
+            }
+        }
+        _ => (),
+    }
+
+    if changes && !replaying {
+        con.vi.last_change = Some(keys.into());
+    }
+    clamp(con);
+    Ok(None)
+}
+
+/// Handles a key typed after `/` or `?`.
+fn search_key(con: &mut Context, c: char) {
+    let (dir, mut pattern) = con.vi.search.take().unwrap();
+    match c {
+        '\r' | '\n' => {
+            con.editor.redraw();
+            // An empty pattern searches for the last one again.
+            let pattern = match (pattern.is_empty(), con.vi.last_search.clone()) {
+                (true, Some((_, last))) => last,
+                _ => pattern,
+            };
+            con.vi.last_search = Some((dir, pattern.clone()));
+            search(con, &pattern, dir == '/');
+            return;
+        }
+        '\x1b' | '\x03' | '\x07' => {
+            con.editor.redraw();
+            return;
+        }
+        // Backspace on an empty pattern gives up the search.
+        '\x7f' | '\x08' if pattern.is_empty() => {
+            con.editor.redraw();
+            return;
+        }
+        '\x7f' | '\x08' => {
+            pattern.pop();
+        }
+        c if !c.is_control() => pattern.push(c),
+        _ => (),
+    }
+    con.editor.show_status(&format!("{}{}", dir, pattern));
+    con.vi.search = Some((dir, pattern));
+}
+
+/// Replaces the line with the nearest history entry containing `pattern`.
+fn search(con: &mut Context, pattern: &str, older: bool) {
+    if con.history.is_started() {
+        con.history.set_first(con.editor.line());
+    }
+    if let Some(entry) = con.history.search(pattern, older) {
+        con.editor.replace(&entry);
+        con.editor.move_to_first();
+    }
+}
+
+#[test]
+fn test_parse() {
+    let cmd = |count, action| Parse::Done(Command { count, action });
+
+    assert_eq!(parse("w"), cmd(None, Action::Move(Motion::WordForward(false))));
+    assert_eq!(parse("0"), cmd(None, Action::Move(Motion::Start)));
+    assert_eq!(parse("10l"), cmd(Some(10), Action::Move(Motion::Right)));
+    assert_eq!(parse("f"), Parse::Incomplete);
+    assert_eq!(parse("fx"), cmd(None, Action::Move(Motion::Find('f', 'x'))));
+    assert_eq!(
+        parse("2d3w"),
+        cmd(Some(6), Action::Operate(Operator::Delete, Target::Motion(Motion::WordForward(false))))
+    );
+    assert_eq!(parse("dd"), cmd(None, Action::Operate(Operator::Delete, Target::Line)));
+    assert_eq!(parse("ci"), Parse::Incomplete);
+    assert_eq!(
+        parse("caW"),
+        cmd(None, Action::Operate(Operator::Change, Target::Object { inner: false, big: true }))
+    );
+    assert_eq!(parse("dq"), Parse::Invalid);
+    assert_eq!(parse("r"), Parse::Incomplete);
+    assert_eq!(parse("3."), cmd(Some(3), Action::Key('.')));
+    let digits = "9".repeat(40);
+    assert_eq!(parse(&format!("{}l", digits)), cmd(Some(MAX_COUNT), Action::Move(Motion::Right)));
+    assert_eq!(
+        parse(&format!("{}d{}w", digits, digits)),
+        cmd(Some(MAX_COUNT), Action::Operate(Operator::Delete, Target::Motion(Motion::WordForward(false))))
+    );
+}
+
+#[test]
+fn test_motions() {
+    let chars: Vec<char> = "ls -la ~/src".chars().collect();
+    assert_eq!(next_word_start(&chars, 0, false), 3);
+    assert_eq!(next_word_start(&chars, 3, false), 4);
+    assert_eq!(next_word_start(&chars, 3, true), 7);
+    assert_eq!(prev_word_start(&chars, 7, false), 4);
+    assert_eq!(prev_word_start(&chars, 7, true), 3);
+    assert_eq!(word_end(&chars, 0, false), 1);
+    assert_eq!(word_end(&chars, 1, true), 5);
+    assert_eq!(find(&chars, 0, 'f', 'l', 1, false), Some(4));
+    assert_eq!(find(&chars, 0, 't', 'l', 1, false), Some(3));
+    assert_eq!(find(&chars, 3, 't', 'l', 1, false), Some(3));
+    assert_eq!(find(&chars, 10, 'F', 's', 1, false), Some(9));
+    assert_eq!(find(&chars, 10, 'T', 's', 1, false), Some(10));
+    assert_eq!(find(&chars, 10, 'T', 's', 1, true), Some(2));
+    assert_eq!(find(&chars, 0, 'f', 'z', 1, false), None);
+    assert_eq!(text_object(&chars, 4, true, false, 1), Some((4, 6)));
+    assert_eq!(text_object(&chars, 4, false, false, 1), Some((4, 7)));
+    assert_eq!(text_object(&chars, 10, false, false, 1), Some((9, 12)));
+    assert_eq!(text_object(&chars, 6, false, false, 1), Some((6, 9)));
+}
@@ -0,0 +1,113 @@
+//! How many terminal columns text takes up.
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
+
+/// Columns taken by one grapheme cluster. Wide (East Asian) characters and
+/// emoji take two, combining marks and joiners none of their own.
+pub fn cluster_width(g: &str) -> usize {
+    let is_regional = |c: char| ('\u{1f1e6}'..='\u{1f1ff}').contains(&c);
+    // An emoji presentation selector or a pair of regional indicators (a
+    // flag) is drawn as a single wide glyph.
+    if g.contains('\u{fe0f}') || g.chars().filter(|&c| is_regional(c)).count() == 2 {
+        return 2;
+    }
+    g.chars().map(|c| c.width().unwrap_or(0)).max().unwrap_or(0)
+}
+
+/// Columns taken by `s` on a single row, escape sequences not counted.
+pub fn str_width(s: &str) -> usize {
+    strip_escapes(s).graphemes(true).map(cluster_width).sum()
+}
+
+/// Removes what the terminal doesn't draw: CSI (`\x1b[...m`) and OSC
+/// (`\x1b]...\x07`) sequences, and anything between the `\x01` and `\x02`
+/// markers readline uses for invisible parts of a prompt.
+pub fn strip_escapes(s: &str) -> String {
+    let mut res = String::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x01' => {
+                for c in chars.by_ref() {
+                    if c == '\x02' {
+                        break;
+                    }
+                }
+            }
+            '\x02' => (),
+            '\x1b' => {
+                match chars.next() {
+                    Some('[') => {
+                        for c in chars.by_ref() {
+                            if ('@'..='~').contains(&c) {
+                                break;
+                            }
+                        }
+                    }
+                    Some(']') => {
+                        while let Some(c) = chars.next() {
+                            if c == '\x07' {
+                                break;
+                            }
+                            if c == '\x1b' && chars.peek() == Some(&'\\') {
+                                chars.next();
+                                break;
+                            }
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            _ => res.push(c),
+        }
+    }
+    res
+}
+
+/// Pads `s` with spaces to `width` columns.
+pub fn pad(s: &str, width: usize) -> String {
+    let mut res = s.to_string();
+    for _ in str_width(s)..width {
+        res.push(' ');
+    }
+    res
+}
+
+/// Cuts `s` down to `width` columns, ending it with `…` if anything is cut.
+pub fn truncate(s: &str, width: usize) -> String {
+    if str_width(s) <= width {
+        return s.to_string();
+    }
+    let mut res = String::new();
+    let mut used = 0;
+    for g in s.graphemes(true) {
+        let w = cluster_width(g);
+        if used + w + 1 > width {
+            break;
+        }
+        res.push_str(g);
+        used += w;
+    }
+    if width > 0 {
+        res.push('…');
+    }
+    res
+}
+
+#[test]
+fn test_width() {
+    assert_eq!(str_width("mican"), 5);
+    assert_eq!(str_width("日本語"), 6);
+    assert_eq!(str_width("🍊"), 2);
+    assert_eq!(str_width("e\u{301}"), 1);
+    // A family emoji: three people joined with ZWJ.
+    assert_eq!(str_width("\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}"), 2);
+    assert_eq!(str_width("\u{1f1ef}\u{1f1f5}"), 2);
+    assert_eq!(str_width("\x1b[1;32mmican\x1b[m> "), 7);
+    assert_eq!(str_width("\x01\x1b]0;title\x07\x02> "), 2);
+    assert_eq!(pad("日本", 6), "日本  ");
+    assert_eq!(truncate("mican", 5), "mican");
+    assert_eq!(truncate("mican", 4), "mic…");
+    assert_eq!(truncate("日本語", 4), "日…");
+}
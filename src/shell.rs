@@ -0,0 +1,479 @@
+//! The state of a running shell and the executor for parsed command lines.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::process;
+use std::rc::Rc;
+
+use nix::sys::signal::Signal;
+use nix::unistd::getpid;
+
+use commands::{self, Builtin};
+use error::ParseError;
+use expand::{self, is_name};
+use hash::CommandTable;
+use options::{Options, Shopts};
+use parser::{Ast, Parser};
+use process::{exit_status, Process};
+use readline::completer::Specs;
+use readline::keymap::Keymaps;
+use token::{CommandData, RedirectKind};
+use trap::{self, Condition, Traps};
+
+enum Job {
+    Done(i32),
+    Running(Process),
+}
+
+pub struct Shell {
+    pub options: Options,
+    pub shopts: Shopts,
+    /// `$0`.
+    pub name: String,
+    /// `$1`, `$2`, ...
+    pub positional: Vec<String>,
+    /// `$?`.
+    pub last_status: i32,
+    pub traps: Traps,
+    /// Reading commands from a terminal rather than a script.
+    pub interactive: bool,
+    /// The line editor's key bindings, which `bind` changes.
+    pub keymaps: Rc<RefCell<Keymaps>>,
+    /// How to complete the arguments of commands, which `complete` changes.
+    pub completions: Rc<RefCell<Specs>>,
+    /// Where the commands in `$PATH` are, for running and completing them.
+    pub command_table: Rc<RefCell<CommandTable>>,
+    /// Variables that are not exported to the environment, shared with
+    /// completion.
+    pub vars: Rc<RefCell<HashMap<String, String>>>,
+    /// Set while a trap or a completion function runs so ERR and DEBUG
+    /// don't trigger themselves.
+    in_trap: bool,
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shell {
+    pub fn new() -> Self {
+        Self {
+            options: Options::new(),
+            shopts: Shopts::new(),
+            name: "mican".into(),
+            positional: Vec::new(),
+            last_status: 0,
+            traps: Traps::new(),
+            interactive: false,
+            keymaps: Rc::new(RefCell::new(Keymaps::new())),
+            completions: Rc::new(RefCell::new(Specs::new())),
+            command_table: Rc::new(RefCell::new(CommandTable::new())),
+            vars: Rc::new(RefCell::new(HashMap::new())),
+            in_trap: false,
+        }
+    }
+
+    /// Parses and executes one line of input.
+    pub fn run(&mut self, input: &str) -> Result<i32, ParseError> {
+        if self.options.verbose {
+            eprintln!("{}", input);
+        }
+
+        match Parser::new(input.to_string()).parse_list() {
+            Ok(list) => {
+                for ast in list {
+                    self.execute(ast);
+                }
+                Ok(self.last_status)
+            }
+            Err(e) => {
+                self.last_status = 2;
+                Err(e)
+            }
+        }
+    }
+
+    /// Runs a pipeline and returns its exit status.
+    pub fn execute(&mut self, ast: Ast) -> i32 {
+        if ast.commands.is_empty() {
+            return self.last_status;
+        }
+
+        self.run_trap(Condition::Debug);
+
+        let mut jobs = Vec::new();
+        let in_pipeline = ast.commands.len() > 1;
+        for c in ast.commands {
+            let job = match self.prepare(c) {
+                Ok((c, assigns)) => self.spawn(c, assigns, in_pipeline),
+                Err(e) => {
+                    eprintln!("mican: {}", e);
+                    Job::Done(1)
+                }
+            };
+            jobs.push(job);
+        }
+
+        let statuses: Vec<i32> = jobs.into_iter()
+            .map(|job| match job {
+                Job::Done(status) => status,
+                Job::Running(p) => {
+                    match p.wait() {
+                        Ok(status) => exit_status(&status),
+                        Err(e) => {
+                            eprintln!("mican: {}", e);
+                            1
+                        }
+                    }
+                }
+            })
+            .collect();
+
+        let status = if self.options.pipefail {
+            statuses.iter().rev().find(|&&s| s != 0).cloned().unwrap_or(0)
+        } else {
+            *statuses.last().unwrap()
+        };
+        self.last_status = status;
+
+        if status != 0 {
+            self.run_trap(Condition::Err);
+        }
+        self.run_pending_traps();
+
+        if self.options.errexit && status != 0 && !self.in_trap {
+            self.exit(status);
+        }
+        status
+    }
+
+    /// Runs the EXIT trap, if any, and exits.
+    pub fn exit(&mut self, status: i32) -> ! {
+        self.last_status = status;
+        if let Some(cmd) = self.traps.remove(Condition::Exit) {
+            self.run_trap_command(&cmd);
+        }
+        process::exit(status)
+    }
+
+    /// Installs the signal dispositions the trap table asks for. An
+    /// interactive shell keeps catching SIGINT and SIGQUIT so that Ctrl-C
+    /// only reaches the foreground job.
+    pub fn install_signals(&self) -> Result<(), String> {
+        for &sig in &[Signal::SIGINT, Signal::SIGQUIT] {
+            if self.traps.get(Condition::Signal(sig)).is_none() {
+                self.reset_signal(sig)?;
+            }
+        }
+        for (cond, cmd) in self.traps.iter() {
+            if let Condition::Signal(sig) = *cond {
+                if cmd.is_empty() {
+                    trap::ignore(sig)?;
+                } else {
+                    trap::catch(sig)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Puts back what a signal does when it isn't trapped.
+    pub fn reset_signal(&self, sig: Signal) -> Result<(), String> {
+        if self.interactive && (sig == Signal::SIGINT || sig == Signal::SIGQUIT) {
+            trap::catch(sig)
+        } else {
+            trap::default(sig)
+        }
+    }
+
+    /// Runs the traps of the signals caught since the last safe point.
+    pub fn run_pending_traps(&mut self) {
+        for sig in trap::take_pending() {
+            self.run_trap(Condition::Signal(sig));
+        }
+    }
+
+    fn run_trap(&mut self, cond: Condition) {
+        if self.in_trap {
+            return;
+        }
+        if let Some(cmd) = self.traps.get(cond).cloned() {
+            self.run_trap_command(&cmd);
+        }
+    }
+
+    /// Runs a trap's command. `$?` is left as it was before the trap.
+    fn run_trap_command(&mut self, cmd: &str) {
+        if cmd.is_empty() {
+            return;
+        }
+        self.run_internal(cmd);
+    }
+
+    /// Runs a command line the shell runs on its own, like a trap's or a
+    /// completion function: ERR and DEBUG don't fire, `set -e` doesn't exit,
+    /// and `$?` is left as it was.
+    pub fn run_internal(&mut self, cmd: &str) {
+        let (status, in_trap) = (self.last_status, self.in_trap);
+        self.in_trap = true;
+        if let Err(e) = self.run(cmd) {
+            eprint!("{}", e.render(cmd, None));
+        }
+        self.in_trap = in_trap;
+        self.last_status = status;
+    }
+
+    /// Expands the words and opens the redirections of a command. Leading
+    /// `NAME=value` words are returned separately.
+    fn prepare(&mut self, mut c: CommandData) -> Result<(CommandData, Vec<(String, String)>), String> {
+        let mut words = vec![c.program.clone()];
+        words.append(&mut c.options);
+
+        let mut assigns = Vec::new();
+        while let Some(word) = words.first().cloned() {
+            match word.find('=') {
+                Some(i) if is_name(&word[..i]) => {
+                    assigns.push((word[..i].to_string(), expand::expand_str(self, &word[i + 1..])?));
+                    words.remove(0);
+                }
+                _ => break,
+            }
+        }
+
+        let mut fields = Vec::new();
+        for word in words.iter().filter(|w| !w.is_empty()) {
+            fields.append(&mut expand::expand(self, word)?);
+        }
+
+        if self.options.xtrace {
+            self.trace(&assigns, &fields);
+        }
+
+        for r in c.redirects.clone() {
+            let mut targets = expand::expand(self, &r.target)?;
+            if targets.len() != 1 {
+                return Err(format!("{}: ambiguous redirect", r.target));
+            }
+            let target = targets.remove(0);
+
+            if r.kind == RedirectKind::Input {
+                let f = fs::File::open(&target).map_err(|e| format!("{}: {}", target, e))?;
+                c.set_input(f);
+                continue;
+            }
+
+            let mut options = fs::OpenOptions::new();
+            options.write(true);
+            if r.kind == RedirectKind::Append {
+                options.create(true).append(true);
+            } else if self.options.noclobber {
+                options.create_new(true);
+            } else {
+                options.create(true).truncate(true);
+            }
+            let f = match options.open(&target) {
+                // `set -C` still lets through what isn't a regular file, like
+                // `/dev/null`.
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    if fs::metadata(&target).map_or(true, |m| m.is_file()) {
+                        return Err(format!("{}: cannot overwrite existing file", target));
+                    }
+                    fs::OpenOptions::new().write(true).open(&target)
+                }
+                result => result,
+            };
+            c.set_out(f.map_err(|e| format!("{}: {}", target, e))?);
+        }
+
+        c.program = if fields.is_empty() {
+            String::new()
+        } else {
+            fields.remove(0)
+        };
+        c.options = fields;
+        Ok((c, assigns))
+    }
+
+    fn spawn(&mut self, c: CommandData, assigns: Vec<(String, String)>, in_pipeline: bool) -> Job {
+        if c.program.is_empty() {
+            for (name, value) in assigns {
+                self.set_var(&name, &value);
+            }
+            return Job::Done(0);
+        }
+
+        let builtin = commands::builtin(&c.program);
+        if let Some(Builtin::Shell(f)) = builtin {
+            // In a pipeline it is a subshell, so that `exit | cat` doesn't
+            // exit this shell nor `set` change it.
+            if in_pipeline {
+                let p = Process::new(commands::other::run);
+                if p.in_child() {
+                    self.traps = Traps::new();
+                    Process::exit_with(f(self, c));
+                }
+                return Job::Running(p);
+            }
+            return match f(self, c) {
+                Ok(()) => Job::Done(0),
+                Err(e) => {
+                    if !e.is_empty() {
+                        eprintln!("mican: {}", e);
+                    }
+                    Job::Done(1)
+                }
+            };
+        }
+
+        let mut path = None;
+        let p = match builtin {
+            Some(Builtin::Forked(f)) => Process::new(f),
+            _ => {
+                // Looked up before forking so that the table is kept. A
+                // `PATH=...` before the command searches that instead.
+                if assigns.iter().all(|(name, _)| name != "PATH") {
+                    path = self.command_table.borrow_mut().find(&c.program);
+                }
+                Process::new(commands::other::run)
+            }
+        };
+        if p.in_child() {
+            for (name, value) in assigns {
+                env::set_var(name, value);
+            }
+            match path {
+                Some(path) => Process::exit_with(commands::other::exec(c, &path)),
+                None => p.run(c),
+            }
+        }
+        Job::Running(p)
+    }
+
+    /// Prints a command as `set -x` does, prefixed with the expanded `PS4`.
+    fn trace(&self, assigns: &[(String, String)], fields: &[String]) {
+        let ps4 = match self.var("PS4") {
+            Some(ps4) => expand::expand_str(self, &ps4).unwrap_or(ps4),
+            None => "+ ".into(),
+        };
+        let words: Vec<String> = assigns
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, quote(value)))
+            .chain(fields.iter().map(|f| quote(f)))
+            .collect();
+        eprintln!("{}{}", ps4, words.join(" "));
+    }
+
+    /// Looks up a parameter, special ones like `$?` and `$1` included.
+    pub fn var(&self, name: &str) -> Option<String> {
+        match name {
+            "?" => Some(self.last_status.to_string()),
+            "#" => Some(self.positional.len().to_string()),
+            "$" => Some(getpid().to_string()),
+            "-" => Some(self.options.flags()),
+            "0" => Some(self.name.clone()),
+            "@" | "*" => Some(self.positional.join(" ")),
+            _ if name.chars().all(|c| c.is_ascii_digit()) => {
+                match name.parse::<usize>().ok()? {
+                    // `${00}` is `$0`.
+                    0 => Some(self.name.clone()),
+                    n => self.positional.get(n - 1).cloned(),
+                }
+            }
+            _ => self.vars.borrow().get(name).cloned().or_else(|| env::var(name).ok()),
+        }
+    }
+
+    /// Sets a variable, updating the environment if it is already exported.
+    pub fn set_var(&mut self, name: &str, value: &str) {
+        if env::var_os(name).is_some() {
+            env::set_var(name, value);
+        } else {
+            self.vars.borrow_mut().insert(name.into(), value.into());
+        }
+    }
+
+    /// Removes a variable, from the environment too.
+    pub fn unset_var(&mut self, name: &str) {
+        self.vars.borrow_mut().remove(name);
+        env::remove_var(name);
+    }
+
+    /// All shell and environment variables, sorted by name.
+    pub fn vars(&self) -> Vec<(String, String)> {
+        let mut vars: Vec<(String, String)> = env::vars().collect();
+        vars.extend(self.vars.borrow().iter().map(|(k, v)| (k.clone(), v.clone())));
+        vars.sort();
+        vars
+    }
+}
+
+/// Quotes a word so it reads back as the same word.
+pub fn quote(s: &str) -> String {
+    let safe = |c: char| c.is_alphanumeric() || "-_./=:,+@%".contains(c);
+    if !s.is_empty() && s.chars().all(safe) {
+        s.into()
+    } else {
+        format!("'{}'", s.replace('\'', "'\\''"))
+    }
+}
+
+#[test]
+fn test_pipefail() {
+    let mut shell = Shell::new();
+    assert_eq!(shell.run("false | true"), Ok(0));
+    shell.options.pipefail = true;
+    assert_eq!(shell.run("false | true"), Ok(1));
+    assert_eq!(shell.run("true | false | true"), Ok(1));
+    assert_eq!(shell.var("?"), Some("1".to_string()));
+}
+
+#[test]
+fn test_pipeline_builtins() {
+    let mut shell = Shell::new();
+    // Each runs in a subshell, which it exits or changes instead.
+    assert_eq!(shell.run("exit 3 | true"), Ok(0));
+    assert_eq!(shell.run("true | exit 3"), Ok(3));
+    assert_eq!(shell.run("set -o pipefail | true"), Ok(0));
+    assert!(!shell.options.pipefail);
+}
+
+#[test]
+fn test_noclobber() {
+    use testing::TempDir;
+
+    let dir = TempDir::new("noclobber");
+    let file = dir.join("file");
+    let mut shell = Shell::new();
+    shell.options.noclobber = true;
+    assert_eq!(shell.run(&format!("echo a > {}", file.display())), Ok(0));
+    assert_eq!(shell.run(&format!("echo b > {}", file.display())), Ok(1));
+    assert_eq!(shell.run(&format!("echo b >> {}", file.display())), Ok(0));
+    assert_eq!(fs::read_to_string(&file).unwrap(), "a\nb\n");
+    assert_eq!(shell.run("echo c > /dev/null"), Ok(0));
+}
+
+#[test]
+fn test_traps() {
+    let mut shell = Shell::new();
+    shell.traps.set(Condition::Err, "ERR_RAN=yes");
+    shell.traps.set(Condition::Debug, "DEBUG_RAN=yes");
+    assert_eq!(shell.run("false"), Ok(1));
+    assert_eq!(shell.var("ERR_RAN"), Some("yes".to_string()));
+    assert_eq!(shell.var("DEBUG_RAN"), Some("yes".to_string()));
+    // $? is the failing command's, not the trap's.
+    assert_eq!(shell.last_status, 1);
+}
+
+#[test]
+fn test_positional() {
+    let mut shell = Shell::new();
+    shell.positional = vec!["a".into(), "b".into()];
+    assert_eq!(shell.var("2"), Some("b".to_string()));
+    assert_eq!(shell.var("3"), None);
+    assert_eq!(shell.var("00"), Some("mican".to_string()));
+}
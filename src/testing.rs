@@ -0,0 +1,38 @@
+//! What the tests share.
+
+use std::env;
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// A directory for the files a test needs, removed with them when dropped,
+/// so that a failing test doesn't leave it behind.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// An empty directory whose name has `name` in it.
+    pub fn new(name: &str) -> Self {
+        let path = env::temp_dir().join(format!("mican-test-{}-{}", name, process::id()));
+        // Left over from a run that was killed.
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
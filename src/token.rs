@@ -1,3 +1,4 @@
+use std::fmt;
 use std::fs;
 use std::io::{self, Read, Write};
 
@@ -8,6 +9,51 @@ pub enum Token {
     // RedirectTo,
 }
 
+/// What the parser was looking at, used to describe parse errors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind {
+    Command,
+    Pipe,
+    Semicolon,
+    Redirect,
+    FileName,
+    ClosingQuote(char),
+    EndOfLine,
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TokenKind::Command => write!(f, "a command"),
+            TokenKind::Pipe => write!(f, "`|`"),
+            TokenKind::Semicolon => write!(f, "`;`"),
+            TokenKind::Redirect => write!(f, "a redirection"),
+            TokenKind::FileName => write!(f, "a file name"),
+            TokenKind::ClosingQuote(q) => write!(f, "a closing `{}`", q),
+            TokenKind::EndOfLine => write!(f, "end of line"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirectKind {
+    /// `< file`
+    Input,
+    /// `> file`, refused by `set -C` when the file exists.
+    Output,
+    /// `>> file`
+    Append,
+    /// `>| file`, overwrites even with `set -C`.
+    Clobber,
+}
+
+/// A redirection as written; the target is expanded when the command runs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirect {
+    pub kind: RedirectKind,
+    pub target: String,
+}
+
 #[derive(Debug)]
 pub enum Input {
     File(fs::File),
@@ -94,17 +140,21 @@ impl Clone for Output {
     }
 }
 
+/// A simple command. `program` and `options` hold the words as written
+/// until `expand` turns them into the final arguments.
 #[derive(Debug)]
 pub struct CommandData {
     pub program: String,
     pub options: Vec<String>,
+    pub redirects: Vec<Redirect>,
     pub input: Option<Input>,
     pub out: Option<Output>,
 }
 
 impl PartialEq for CommandData {
     fn eq(&self, other: &Self) -> bool {
-        self.program == other.program && self.options == other.options
+        self.program == other.program && self.options == other.options &&
+            self.redirects == other.redirects
     }
 }
 
@@ -0,0 +1,157 @@
+//! Traps set by the `trap` builtin and the signal handling behind them.
+//!
+//! Signal handlers only record which signals arrived. The shell runs the
+//! trap commands later, between commands, where it is safe to do so.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use nix::libc::c_int;
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+
+/// When a trap runs: on a signal or on one of the pseudo-signals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Condition {
+    /// When the shell exits.
+    Exit,
+    Signal(Signal),
+    /// After a pipeline fails.
+    Err,
+    /// Before each pipeline runs.
+    Debug,
+}
+
+impl Condition {
+    /// Parses `EXIT`, `0`, `ERR`, `DEBUG`, `INT`, `SIGINT` or `2`, in any case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let upper = name.to_uppercase();
+        match upper.as_str() {
+            "EXIT" | "0" => return Some(Condition::Exit),
+            "ERR" => return Some(Condition::Err),
+            "DEBUG" => return Some(Condition::Debug),
+            _ => (),
+        }
+
+        if let Ok(n) = upper.parse::<c_int>() {
+            return Signal::from_c_int(n).ok().map(Condition::Signal);
+        }
+        let full = if upper.starts_with("SIG") {
+            upper
+        } else {
+            format!("SIG{}", upper)
+        };
+        Signal::iterator()
+            .find(|sig| format!("{:?}", sig) == full)
+            .map(Condition::Signal)
+    }
+
+    pub fn name(&self) -> String {
+        match *self {
+            Condition::Exit => "EXIT".into(),
+            Condition::Err => "ERR".into(),
+            Condition::Debug => "DEBUG".into(),
+            Condition::Signal(sig) => format!("{:?}", sig),
+        }
+    }
+}
+
+/// The trap table. An empty command means the signal is ignored.
+#[derive(Debug, Default)]
+pub struct Traps {
+    traps: BTreeMap<Condition, String>,
+}
+
+impl Traps {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, cond: Condition) -> Option<&String> {
+        self.traps.get(&cond)
+    }
+
+    pub fn set(&mut self, cond: Condition, cmd: &str) {
+        self.traps.insert(cond, cmd.into());
+    }
+
+    pub fn remove(&mut self, cond: Condition) -> Option<String> {
+        self.traps.remove(&cond)
+    }
+
+    pub fn iter(&self) -> ::std::collections::btree_map::Iter<'_, Condition, String> {
+        self.traps.iter()
+    }
+}
+
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+static IGNORED: AtomicUsize = AtomicUsize::new(0);
+
+fn bit(sig: Signal) -> usize {
+    1 << (sig as usize)
+}
+
+extern "C" fn record(sig: c_int) {
+    if let Ok(sig) = Signal::from_c_int(sig) {
+        PENDING.fetch_or(bit(sig), Ordering::SeqCst);
+    }
+}
+
+fn set_handler(sig: Signal, handler: SigHandler) -> Result<(), String> {
+    // SA_RESTART keeps `waitpid` going while a trapped signal arrives.
+    let action = SigAction::new(handler, SaFlags::SA_RESTART, SigSet::empty());
+    unsafe { sigaction(sig, &action) }
+        .map(|_| ())
+        .map_err(|e| format!("{:?}: {}", sig, e))?;
+
+    if handler == SigHandler::SigIgn {
+        IGNORED.fetch_or(bit(sig), Ordering::SeqCst);
+    } else {
+        IGNORED.fetch_and(!bit(sig), Ordering::SeqCst);
+    }
+    Ok(())
+}
+
+/// Records the signal for `take_pending` instead of acting on it.
+pub fn catch(sig: Signal) -> Result<(), String> {
+    set_handler(sig, SigHandler::Handler(record))
+}
+
+pub fn ignore(sig: Signal) -> Result<(), String> {
+    set_handler(sig, SigHandler::SigIgn)
+}
+
+pub fn default(sig: Signal) -> Result<(), String> {
+    set_handler(sig, SigHandler::SigDfl)
+}
+
+/// Returns the signals caught since the last call.
+pub fn take_pending() -> Vec<Signal> {
+    let pending = PENDING.swap(0, Ordering::SeqCst);
+    Signal::iterator().filter(|&sig| pending & bit(sig) != 0).collect()
+}
+
+/// Called in a forked child: everything the shell catches goes back to the
+/// default action so Ctrl-C reaches the job. Signals ignored with `trap ''`
+/// stay ignored, as POSIX requires.
+pub fn reset_in_child() {
+    let ignored = IGNORED.load(Ordering::SeqCst);
+    for sig in Signal::iterator() {
+        if sig == Signal::SIGKILL || sig == Signal::SIGSTOP || ignored & bit(sig) != 0 {
+            continue;
+        }
+        let action = SigAction::new(SigHandler::SigDfl, SaFlags::empty(), SigSet::empty());
+        let _ = unsafe { sigaction(sig, &action) };
+    }
+    let _ = SigSet::empty().thread_set_mask();
+}
+
+#[test]
+fn test_condition_from_name() {
+    assert_eq!(Condition::from_name("EXIT"), Some(Condition::Exit));
+    assert_eq!(Condition::from_name("0"), Some(Condition::Exit));
+    assert_eq!(Condition::from_name("int"), Some(Condition::Signal(Signal::SIGINT)));
+    assert_eq!(Condition::from_name("SIGTERM"), Some(Condition::Signal(Signal::SIGTERM)));
+    assert_eq!(Condition::from_name("15"), Some(Condition::Signal(Signal::SIGTERM)));
+    assert_eq!(Condition::from_name("ERR"), Some(Condition::Err));
+    assert_eq!(Condition::from_name("NOPE"), None);
+}